@@ -0,0 +1,146 @@
+// Times editing a 10,000-line script through `incremental::Document`
+// against scanning and parsing all of it again. Run with `cargo bench`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use luxor::incremental::{Document, Edit};
+use luxor::parser::Parser;
+use luxor::scanner::Scanner;
+
+const LINES: usize = 10_000;
+
+// Functions and classes in turn, each six lines long
+fn script() -> String {
+    let mut src = String::new();
+    let mut i = 0;
+    while src.lines().count() < LINES {
+        if i % 2 == 0 {
+            src.push_str(&format!(
+                "fun step{i}(a, b) {{\n  // Adds up\n  var total = a + b * {i};\n  if (total > 10) return total - 1;\n  return \"step {i}\";\n}}\n",
+                i = i
+            ));
+        } else {
+            src.push_str(&format!(
+                "class Shape{i} {{\n  init(w) {{ this.w = w; }}\n  area() {{\n    return this.w * this.w + {i};\n  }}\n}}\n",
+                i = i
+            ));
+        }
+        i += 1;
+    }
+    src
+}
+
+// Mean, 99th percentile and slowest of running `f` `runs` times. The
+// slowest is often a one-off, like the first run or the machine being busy.
+fn time(runs: usize, mut f: impl FnMut(usize)) -> (Duration, Duration, Duration) {
+    let mut times: Vec<Duration> = (0..runs)
+        .map(|run| {
+            let start = Instant::now();
+            f(run);
+            start.elapsed()
+        })
+        .collect();
+    times.sort();
+    let mean = times.iter().sum::<Duration>() / runs as u32;
+    (mean, times[runs * 99 / 100], times[runs - 1])
+}
+
+fn report(name: &str, (mean, p99, slowest): (Duration, Duration, Duration)) {
+    println!(
+        "{:<32} mean {:>10.3?}   p99 {:>10.3?}   max {:>10.3?}",
+        name, mean, p99, slowest
+    );
+}
+
+fn main() {
+    let src = script();
+    println!("{} lines, {} bytes\n", src.lines().count(), src.len());
+
+    report(
+        "scan and parse from scratch",
+        time(20, |_| {
+            let tokens = Scanner::new(&src).scan_tokens().clone();
+            black_box(Parser::new(tokens).parse().unwrap());
+        }),
+    );
+    report(
+        "Document::new",
+        time(20, |_| {
+            black_box(Document::new(&src));
+        }),
+    );
+
+    // Places spread through the script, each just inside a `total`
+    let places: Vec<usize> = src
+        .match_indices("total =")
+        .map(|(i, _)| i + 2)
+        .step_by(97)
+        .collect();
+    let mut document = Document::new(&src);
+
+    // Typing a character into a name and taking it out again
+    report(
+        "type a character",
+        time(1000, |run| {
+            let at = places[run / 2 % places.len()];
+            let edit = if run % 2 == 0 {
+                Edit {
+                    range: at..at,
+                    text: "x".to_string(),
+                }
+            } else {
+                Edit {
+                    range: at..at + 1,
+                    text: String::new(),
+                }
+            };
+            document.edit(black_box(&edit));
+        }),
+    );
+
+    // Breaking a line and joining it again, which moves every line after
+    report(
+        "insert and remove a newline",
+        time(1000, |run| {
+            let at = places[run / 2 % places.len()] + 5;
+            let edit = if run % 2 == 0 {
+                Edit {
+                    range: at..at,
+                    text: "\n".to_string(),
+                }
+            } else {
+                Edit {
+                    range: at..at + 1,
+                    text: String::new(),
+                }
+            };
+            document.edit(black_box(&edit));
+        }),
+    );
+
+    // Opening a string that runs to the end of the script, and closing it.
+    // Everything after the edit scans differently, so this rescans and
+    // reparses to the end, or starts over when that's much of the script.
+    report(
+        "open and close a string",
+        time(100, |run| {
+            let at = places[run / 2 % places.len()];
+            let edit = if run % 2 == 0 {
+                Edit {
+                    range: at..at,
+                    text: "\"".to_string(),
+                }
+            } else {
+                Edit {
+                    range: at..at + 1,
+                    text: String::new(),
+                }
+            };
+            document.edit(black_box(&edit));
+        }),
+    );
+
+    assert_eq!(document.source(), src);
+    assert!(document.errors().is_empty());
+}
@@ -1,35 +1,321 @@
+use crate::resolver::Locals;
 use crate::token::*;
+use crate::visit::{Printer, Visitor};
 use std::fmt;
+use std::ops::{Index, IndexMut};
+use std::rc::Rc;
 
-#[derive(Debug)]
+// Expressions live in an `ExprArena` and refer to their operands by id. Ids
+// count up from 0 as nodes are added and never change, so side tables can
+// be a `Vec` indexed by `ExprId::index` or a map keyed by the id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ExprId(u32);
+
+impl ExprId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for ExprId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Expr {
     Binary {
-        left: Box<Expr>,
+        left: ExprId,
         operator: Token,
-        right: Box<Expr>,
+        right: ExprId,
+    },
+    Assign {
+        name: Token,
+        value: ExprId,
+    },
+    Call {
+        callee: ExprId,
+        paren: Token,
+        arguments: Vec<ExprId>,
+    },
+    Get {
+        object: ExprId,
+        name: Token,
     },
     Grouping {
-        expression: Box<Expr>,
+        expression: ExprId,
+    },
+    // `object[index]`; `bracket` is the closing one
+    Index {
+        object: ExprId,
+        bracket: Token,
+        index: ExprId,
     },
     Lit(Token),
+    // `bracket` is the opening one
+    List {
+        bracket: Token,
+        elements: Vec<ExprId>,
+    },
+    Logical {
+        left: ExprId,
+        operator: Token,
+        right: ExprId,
+    },
+    // Key and value of each entry, in source order; `brace` is the opening
+    // one
+    Map {
+        brace: Token,
+        entries: Vec<(ExprId, ExprId)>,
+    },
+    Set {
+        object: ExprId,
+        name: Token,
+        value: ExprId,
+    },
+    SetIndex {
+        object: ExprId,
+        bracket: Token,
+        index: ExprId,
+        value: ExprId,
+    },
+    // `object[start:end]`, either bound may be left out
+    Slice {
+        object: ExprId,
+        bracket: Token,
+        start: Option<ExprId>,
+        end: Option<ExprId>,
+    },
+    Super {
+        keyword: Token,
+        method: Token,
+    },
+    This {
+        keyword: Token,
+    },
     Unary {
         operator: Token,
-        right: Box<Expr>,
+        right: ExprId,
+    },
+    Variable {
+        name: Token,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum Stmt {
+    // `brace` is the closing one
+    Block {
+        statements: Vec<Stmt>,
+        brace: Token,
+    },
+    Break {
+        keyword: Token,
+    },
+    // `superclass` is an `Expr::Variable`
+    Class {
+        name: Token,
+        superclass: Option<ExprId>,
+        methods: Vec<Rc<Function>>,
+    },
+    Continue {
+        keyword: Token,
+    },
+    Expression {
+        expression: ExprId,
+    },
+    For {
+        keyword: Token,
+        initializer: Option<Box<Stmt>>,
+        condition: Option<ExprId>,
+        increment: Option<ExprId>,
+        body: Box<Stmt>,
+    },
+    // `for (var name in iterable) body`
+    ForIn {
+        keyword: Token,
+        name: Token,
+        iterable: ExprId,
+        body: Box<Stmt>,
+    },
+    Function(Rc<Function>),
+    If {
+        condition: ExprId,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+    },
+    Print {
+        expression: ExprId,
+    },
+    Return {
+        keyword: Token,
+        value: Option<ExprId>,
+    },
+    Var {
+        name: Token,
+        initializer: Option<ExprId>,
+    },
+    While {
+        condition: ExprId,
+        body: Box<Stmt>,
     },
 }
 
-impl fmt::Display for Expr {
+impl Stmt {
+    // Line of the token that best identifies the statement. Blocks keep
+    // only their closing brace, so that's theirs.
+    pub fn line(&self, exprs: &ExprArena) -> u32 {
+        match self {
+            Stmt::Block { brace, .. } => brace.line,
+            Stmt::Class { name, .. } | Stmt::Var { name, .. } => name.line,
+            Stmt::Expression { expression } | Stmt::Print { expression } => exprs.line(*expression),
+            Stmt::Function(function) => function.name.line,
+            Stmt::If { condition, .. } | Stmt::While { condition, .. } => exprs.line(*condition),
+            Stmt::Break { keyword }
+            | Stmt::Continue { keyword }
+            | Stmt::For { keyword, .. }
+            | Stmt::ForIn { keyword, .. }
+            | Stmt::Return { keyword, .. } => keyword.line,
+        }
+    }
+}
+
+// Shared between the declaring statement and every closure created from it.
+// `brace` closes the body.
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<Stmt>,
+    pub brace: Token,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExprArena {
+    exprs: Vec<Expr>,
+}
+
+impl ExprArena {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn push(&mut self, expr: Expr) -> ExprId {
+        let id = ExprId(self.exprs.len() as u32);
+        self.exprs.push(expr);
+        id
+    }
+
+    pub fn len(&self) -> usize {
+        self.exprs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exprs.is_empty()
+    }
+
+    // Every node with its id, in id order
+    pub fn iter(&self) -> impl Iterator<Item = (ExprId, &Expr)> {
+        self.exprs
+            .iter()
+            .enumerate()
+            .map(|(i, expr)| (ExprId(i as u32), expr))
+    }
+
+    // The name an `Expr::Variable` refers to
+    pub fn variable_name(&self, id: ExprId) -> Option<&Token> {
+        match &self[id] {
+            Expr::Variable { name } => Some(name),
+            _ => None,
+        }
+    }
+
+    // Line of the token that best identifies the expression
+    pub fn line(&self, id: ExprId) -> u32 {
+        match &self[id] {
+            Expr::Assign { name, .. }
+            | Expr::Get { name, .. }
+            | Expr::Set { name, .. }
+            | Expr::Variable { name } => name.line,
+            Expr::Binary { operator, .. }
+            | Expr::Logical { operator, .. }
+            | Expr::Unary { operator, .. } => operator.line,
+            Expr::Call { paren, .. } => paren.line,
+            Expr::Index { bracket, .. }
+            | Expr::List { bracket, .. }
+            | Expr::SetIndex { bracket, .. }
+            | Expr::Slice { bracket, .. } => bracket.line,
+            Expr::Map { brace, .. } => brace.line,
+            Expr::Grouping { expression } => self.line(*expression),
+            Expr::Lit(t) => t.line,
+            Expr::Super { keyword, .. } | Expr::This { keyword } => keyword.line,
+        }
+    }
+
+    // S-expression form of a node: an `ExprId`, `&Stmt` or `&Function`
+    pub fn sexp<N>(&self, node: N) -> Sexp<'_, N> {
+        Sexp { exprs: self, node }
+    }
+}
+
+impl Index<ExprId> for ExprArena {
+    type Output = Expr;
+
+    fn index(&self, id: ExprId) -> &Expr {
+        &self.exprs[id.index()]
+    }
+}
+
+impl IndexMut<ExprId> for ExprArena {
+    fn index_mut(&mut self, id: ExprId) -> &mut Expr {
+        &mut self.exprs[id.index()]
+    }
+}
+
+// A parsed script: its statements, the arena holding their expressions and
+// where the variables they use are declared. Functions declared in it keep
+// the arena and locals alive after the program is gone.
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    pub exprs: Rc<ExprArena>,
+    pub statements: Vec<Stmt>,
+    pub locals: Rc<Locals>,
+}
+
+// One statement per line
+impl fmt::Display for Program {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match &self {
-            Expr::Binary {
-                left,
-                operator,
-                right,
-            } => write!(f, "({} {} {})", operator, left, right),
-            Expr::Grouping { expression } => write!(f, "(group {})", expression),
-            Expr::Lit(t) => write!(f, "{}", t),
-            Expr::Unary { operator, right } => write!(f, "({} {})", operator, right),
+        for (i, stmt) in self.statements.iter().enumerate() {
+            if i > 0 {
+                f.write_str("\n")?;
+            }
+            write!(f, "{}", self.exprs.sexp(stmt))?;
         }
+        Ok(())
+    }
+}
+
+pub struct Sexp<'a, N> {
+    exprs: &'a ExprArena,
+    node: N,
+}
+
+impl fmt::Display for Sexp<'_, ExprId> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Printer { f }.visit_expr(self.exprs, self.node)
+    }
+}
+
+impl fmt::Display for Sexp<'_, &Stmt> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Printer { f }.visit_stmt(self.exprs, self.node)
+    }
+}
+
+impl fmt::Display for Sexp<'_, &Function> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Printer { f }.visit_function(self.exprs, self.node)
     }
 }
 
@@ -39,23 +325,61 @@ mod tests {
 
     #[test]
     fn test_pretty_print() {
-        let x = Box::new(Expr::Binary {
-            left: Box::new(Expr::Unary {
-                operator: Token::new(TokenType::Minus, 1),
-                right: Box::new(Expr::Lit(Token::new(
-                    TokenType::Literal(LiteralKind::Number(123.0)),
-                    1,
-                ))),
-            }),
+        let mut exprs = ExprArena::new();
+        let number = |n| Expr::Lit(Token::new(TokenType::Literal(LiteralKind::Number(n)), 1));
+
+        let n123 = exprs.push(number(123.0));
+        let negated = exprs.push(Expr::Unary {
+            operator: Token::new(TokenType::Minus, 1),
+            right: n123,
+        });
+        let n45 = exprs.push(number(45.67));
+        let group = exprs.push(Expr::Grouping { expression: n45 });
+        let x = exprs.push(Expr::Binary {
+            left: negated,
             operator: Token::new(TokenType::Star, 1),
-            right: Box::new(Expr::Grouping {
-                expression: Box::new(Expr::Lit(Token::new(
-                    TokenType::Literal(LiteralKind::Number(45.67)),
-                    1,
-                ))),
-            }),
+            right: group,
         });
 
-        assert_eq!(x.to_string(), "(* (- 123) (group 45.67))");
+        assert_eq!(exprs.sexp(x).to_string(), "(* (- 123) (group 45.67))");
+        assert_eq!(exprs.len(), 5);
+        assert_eq!(x.index(), 4);
+    }
+
+    #[test]
+    fn test_pretty_print_stmt() {
+        let ident = |s: &str| {
+            Token::new(
+                TokenType::Literal(LiteralKind::Identifier(s.to_string())),
+                1,
+            )
+        };
+        let mut exprs = ExprArena::new();
+        let a = exprs.push(Expr::Variable { name: ident("a") });
+        let stmt = Stmt::Function(Rc::new(Function {
+            name: ident("f"),
+            params: vec![ident("a"), ident("b")],
+            body: vec![Stmt::Return {
+                keyword: Token::new(TokenType::Return, 1),
+                value: Some(a),
+            }],
+            brace: Token::new(TokenType::RightBrace, 1),
+        }));
+
+        assert_eq!(exprs.sexp(&stmt).to_string(), "(fun f (a b) (return a))");
+    }
+
+    #[test]
+    fn test_block_line() {
+        let exprs = ExprArena::new();
+        let block = |statements| Stmt::Block {
+            statements,
+            brace: Token::new(TokenType::RightBrace, 3),
+        };
+        let inner = Stmt::Break {
+            keyword: Token::new(TokenType::Break, 2),
+        };
+        assert_eq!(block(vec![]).line(&exprs), 3);
+        assert_eq!(block(vec![inner]).line(&exprs), 3);
     }
 }
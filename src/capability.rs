@@ -0,0 +1,81 @@
+use std::fmt;
+
+// Something outside the interpreter a native can touch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Clock,
+    Env,
+    FileRead,
+    FileWrite,
+    Stdin,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 5] = [
+        Capability::Clock,
+        Capability::Env,
+        Capability::FileRead,
+        Capability::FileWrite,
+        Capability::Stdin,
+    ];
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Capability::Clock => f.write_str("clock"),
+            Capability::Env => f.write_str("env"),
+            Capability::FileRead => f.write_str("file-read"),
+            Capability::FileWrite => f.write_str("file-write"),
+            Capability::Stdin => f.write_str("stdin"),
+        }
+    }
+}
+
+// The set of capabilities granted to scripts. Empty by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities(u8);
+
+impl Capabilities {
+    pub fn none() -> Self {
+        Capabilities(0)
+    }
+
+    pub fn all() -> Self {
+        Capability::ALL
+            .iter()
+            .fold(Capabilities::none(), |caps, &cap| caps.grant(cap))
+    }
+
+    pub fn grant(self, cap: Capability) -> Self {
+        Capabilities(self.0 | cap.bit())
+    }
+
+    pub fn revoke(self, cap: Capability) -> Self {
+        Capabilities(self.0 & !cap.bit())
+    }
+
+    pub fn contains(self, cap: Capability) -> bool {
+        self.0 & cap.bit() != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grant_and_revoke() {
+        let caps = Capabilities::none().grant(Capability::Clock);
+        assert!(caps.contains(Capability::Clock));
+        assert!(!caps.contains(Capability::Stdin));
+
+        let caps = Capabilities::all().revoke(Capability::FileWrite);
+        assert!(caps.contains(Capability::FileRead));
+        assert!(!caps.contains(Capability::FileWrite));
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::error::RuntimeError;
+use crate::function::{Arity, LoxFunction};
+use crate::value::Value;
+
+// A Rust type exposed to scripts as a class. Each script-side instance owns
+// one value of the implementing type, created by `construct` when the class
+// is called.
+//
+// Lookups on an instance try fields first, then `get`, then methods from the
+// script side, and finally `method_arity`/`call` here. Script classes may
+// inherit from a native class; the native value is then constructed with the
+// arguments passed to the subclass.
+pub trait NativeClass: 'static {
+    fn construct(args: &[Value]) -> Result<Self, RuntimeError>
+    where
+        Self: Sized;
+
+    // Read a native property, `None` if there's no such property
+    fn get(&self, _name: &str) -> Option<Value> {
+        None
+    }
+
+    // Write a native property. Returning false stores `value` as a plain
+    // field on the instance instead.
+    fn set(&mut self, _name: &str, _value: &Value) -> Result<bool, RuntimeError> {
+        Ok(false)
+    }
+
+    // Arity of the native method `name`, `None` if there's no such method
+    fn method_arity(&self, _name: &str) -> Option<Arity> {
+        None
+    }
+
+    // Run the native method `name`, only called after `method_arity` has
+    // accepted the arguments
+    fn call(&mut self, name: &str, _args: &[Value]) -> Result<Value, RuntimeError> {
+        Err(format!("Undefined property '{}'.", name).into())
+    }
+}
+
+pub(crate) type Constructor = dyn Fn(&[Value]) -> Result<Box<dyn NativeClass>, RuntimeError>;
+
+pub(crate) struct NativeConstructor {
+    pub(crate) arity: Arity,
+    pub(crate) construct: Box<Constructor>,
+}
+
+pub struct LoxClass {
+    pub name: String,
+    pub(crate) superclass: Option<Rc<LoxClass>>,
+    pub(crate) methods: HashMap<String, Rc<LoxFunction>>,
+    // Only set on classes registered from Rust
+    pub(crate) native: Option<NativeConstructor>,
+}
+
+impl LoxClass {
+    pub(crate) fn find_method(&self, name: &str) -> Option<Rc<LoxFunction>> {
+        match self.methods.get(name) {
+            Some(method) => Some(Rc::clone(method)),
+            None => self.superclass.as_ref()?.find_method(name),
+        }
+    }
+
+    // The native class at the root of the inheritance chain, if any
+    pub(crate) fn native(&self) -> Option<&NativeConstructor> {
+        match &self.native {
+            Some(native) => Some(native),
+            None => self.superclass.as_ref()?.native(),
+        }
+    }
+
+    pub fn arity(&self) -> Arity {
+        if let Some(init) = self.find_method("init") {
+            return init.arity();
+        }
+
+        match self.native() {
+            Some(native) => native.arity,
+            None => Arity::Exact(0),
+        }
+    }
+}
+
+impl fmt::Debug for LoxClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+pub struct LoxInstance {
+    pub(crate) class: Rc<LoxClass>,
+    pub(crate) fields: HashMap<String, Value>,
+    pub(crate) native: Option<Box<dyn NativeClass>>,
+}
+
+impl fmt::Debug for LoxInstance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
@@ -0,0 +1,92 @@
+// `luxor compile`: save a script scanned, parsed and checked, for `luxor`
+// to run without doing that again
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use luxor::loxc;
+use luxor::parser::Parser;
+use luxor::scanner::Scanner;
+
+const USAGE: &str = "\
+Usage: luxor compile [-o <file>] [file | -]
+
+Compiles a script to a .loxc file, which 'luxor <file>.loxc' runs. With no
+file, or '-', reads the script from stdin. The compiled script is written
+next to the source with a .loxc extension unless -o says where, which it
+must for stdin. It only runs on a luxor that reads its format version.
+
+Options:
+  -o <file>     Write the compiled script to <file>
+  -h, --help    Show this message";
+
+pub fn run(args: &[String]) -> i32 {
+    let mut output = None;
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let source = match arg.as_str() {
+            "-o" => match args.next() {
+                Some(file) => {
+                    output = Some(PathBuf::from(file));
+                    continue;
+                }
+                None => {
+                    eprintln!("-o needs an argument\n\n{}", USAGE);
+                    return 64;
+                }
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return 0;
+            }
+            "-" => arg.as_str(),
+            flag if flag.starts_with('-') => {
+                eprintln!("Unknown option '{}'\n\n{}", flag, USAGE);
+                return 64;
+            }
+            file => file,
+        };
+        if path.is_some() {
+            eprintln!("Only one script can be compiled at a time\n\n{}", USAGE);
+            return 64;
+        }
+        path = Some(source);
+    }
+    let path = path.unwrap_or("-");
+
+    let output = match output {
+        Some(output) => output,
+        None if path == "-" => {
+            eprintln!("-o is needed to compile from stdin\n\n{}", USAGE);
+            return 64;
+        }
+        None => Path::new(path).with_extension("loxc"),
+    };
+
+    let name = if path == "-" { "<stdin>" } else { path };
+    let src = match super::read(path) {
+        Ok(src) => src,
+        Err(e) => {
+            eprintln!("{}: {}", name, e);
+            return 66;
+        }
+    };
+    let tokens = Scanner::new(&src).scan_tokens().to_vec();
+    let program = match Parser::new(tokens).parse() {
+        Ok(program) => program,
+        Err(errors) => {
+            eprintln!("{}:", name);
+            for error in errors {
+                eprintln!("{}", error);
+            }
+            return 65;
+        }
+    };
+
+    if let Err(e) = fs::write(&output, loxc::compile(&program)) {
+        eprintln!("{}: {}", output.display(), e);
+        return 73;
+    }
+    0
+}
@@ -0,0 +1,220 @@
+// `luxor fmt`: rewrite scripts in the standard layout, or with `--check`
+// report the ones that aren't, as a diff
+
+use std::fs;
+
+use luxor::format::{format, FormatError};
+
+const USAGE: &str = "\
+Usage: luxor fmt [--check] [file... | -]
+
+Formats each file in place. With no files, or '-', reads a script from
+stdin and writes the formatted script to stdout.
+
+Options:
+  --check       Change nothing; print a diff of what would change and exit
+                with status 1 if anything would
+  -h, --help    Show this message";
+
+// Number of unchanged lines shown around each change
+const CONTEXT: usize = 3;
+
+// Returns the exit status
+pub fn run(args: &[String]) -> i32 {
+    let mut check = false;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return 0;
+            }
+            "-" => paths.push(arg.as_str()),
+            flag if flag.starts_with('-') => {
+                eprintln!("Unknown option '{}'\n\n{}", flag, USAGE);
+                return 64;
+            }
+            path => paths.push(path),
+        }
+    }
+    if paths.is_empty() {
+        paths.push("-");
+    }
+
+    let mut status = 0;
+    for path in paths {
+        let name = if path == "-" { "<stdin>" } else { path };
+        let src = match super::read(path) {
+            Ok(src) => src,
+            Err(e) => {
+                eprintln!("{}: {}", name, e);
+                status = status.max(66);
+                continue;
+            }
+        };
+        let formatted = match format(&src) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("{}:\n{}", name, e);
+                status = status.max(match e {
+                    FormatError::Syntax(_) => 65,
+                    FormatError::ChangedMeaning => 70,
+                });
+                continue;
+            }
+        };
+
+        if check {
+            if formatted != src {
+                print!("{}", diff(name, &src, &formatted));
+                status = status.max(1);
+            }
+        } else if path == "-" {
+            print!("{}", formatted);
+        } else if formatted != src {
+            if let Err(e) = fs::write(path, formatted) {
+                eprintln!("{}: {}", name, e);
+                status = status.max(73);
+            }
+        }
+    }
+    status
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit {
+    Same,
+    Removed,
+    Added,
+}
+
+// A unified diff of two texts, one hunk per group of nearby changes
+fn diff(name: &str, old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let edits = edit_script(&old, &new);
+
+    // Line numbers in each text before every edit
+    let mut at = vec![(0, 0)];
+    for edit in &edits {
+        let (o, n) = at[at.len() - 1];
+        at.push(match edit {
+            Edit::Same => (o + 1, n + 1),
+            Edit::Removed => (o + 1, n),
+            Edit::Added => (o, n + 1),
+        });
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", name, name);
+    let mut i = 0;
+    while let Some(first) = (i..edits.len()).find(|&k| edits[k] != Edit::Same) {
+        // Extend the hunk while the next change is close enough that the
+        // context would overlap
+        let mut last = first;
+        while let Some(next) = (last + 1..edits.len()).find(|&k| edits[k] != Edit::Same) {
+            if next - last > 2 * CONTEXT {
+                break;
+            }
+            last = next;
+        }
+
+        let start = first.saturating_sub(CONTEXT);
+        let end = (last + 1 + CONTEXT).min(edits.len());
+        let (old_start, new_start) = at[start];
+        let (old_end, new_end) = at[end];
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            old_start + 1,
+            old_end - old_start,
+            new_start + 1,
+            new_end - new_start
+        ));
+        for k in start..end {
+            let (o, n) = at[k];
+            match edits[k] {
+                Edit::Same => out.push_str(&format!(" {}\n", old[o])),
+                Edit::Removed => out.push_str(&format!("-{}\n", old[o])),
+                Edit::Added => out.push_str(&format!("+{}\n", new[n])),
+            }
+        }
+        i = end;
+    }
+    out
+}
+
+// Shortest edits turning `old` into `new`, by longest common subsequence.
+// Unchanged lines at either end are skipped, which leaves little to compare
+// for a formatter's output.
+fn edit_script(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    // lcs[i][j] is the length of the LCS of a[i..] and b[j..]
+    let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut edits = vec![Edit::Same; prefix];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            edits.push(Edit::Same);
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            edits.push(Edit::Removed);
+            i += 1;
+        } else {
+            edits.push(Edit::Added);
+            j += 1;
+        }
+    }
+    edits.extend(vec![Edit::Same; suffix]);
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\n";
+        let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\n";
+        assert_eq!(
+            diff("x.lox", old, new),
+            concat!(
+                "--- x.lox\n",
+                "+++ x.lox\n",
+                "@@ -1,5 +1,5 @@\n",
+                " a\n",
+                "-b\n",
+                "+B\n",
+                " c\n",
+                " d\n",
+                " e\n",
+                "@@ -9,3 +9,4 @@\n",
+                " i\n",
+                " j\n",
+                " k\n",
+                "+l\n",
+            )
+        );
+        assert_eq!(diff("x.lox", old, old), "--- x.lox\n+++ x.lox\n");
+    }
+}
@@ -0,0 +1,55 @@
+// `luxor highlight`: print a script with syntax highlighting
+
+use luxor::highlight::{ansi, html};
+
+const USAGE: &str = "\
+Usage: luxor highlight [--html] [file | -]
+
+Prints the script with its keywords, literals, comments and names colored
+by what they are, for a terminal. With no file, or '-', reads the script
+from stdin.
+
+Options:
+  --html        Print a standalone HTML page instead, with a CSS class on
+                each highlighted span
+  -h, --help    Show this message";
+
+pub fn run(args: &[String]) -> i32 {
+    let mut as_html = false;
+    let mut path = None;
+    for arg in args {
+        match arg.as_str() {
+            "--html" => as_html = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return 0;
+            }
+            flag if flag.starts_with('-') && flag != "-" => {
+                eprintln!("Unknown option '{}'\n\n{}", flag, USAGE);
+                return 64;
+            }
+            _ if path.is_some() => {
+                eprintln!("Expected one script\n\n{}", USAGE);
+                return 64;
+            }
+            file => path = Some(file),
+        }
+    }
+
+    let path = path.unwrap_or("-");
+    let name = if path == "-" { "<stdin>" } else { path };
+    let src = match super::read(path) {
+        Ok(src) => src,
+        Err(e) => {
+            eprintln!("{}: {}", name, e);
+            return 66;
+        }
+    };
+
+    if as_html {
+        print!("{}", html(&src, name));
+    } else {
+        print!("{}", ansi(&src));
+    }
+    0
+}
@@ -0,0 +1,116 @@
+// `luxor lint`: report likely mistakes in scripts
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use luxor::lint::{find_config, lint, Config, Rule};
+
+const USAGE: &str = "\
+Usage: luxor lint [--config <file>] [file... | -]
+
+Reports likely mistakes in each script, or in a script read from stdin
+when there are no files or '-'. Exits with status 1 if anything was found.
+
+Rules are set in the [lint] section of luxor.toml, which is looked for in
+the current directory and then its parents:
+
+  [lint]
+  shadowed-variable = false
+
+A '// lint:allow(rule, ...)' comment silences rules for the line it ends,
+or for the next line when it's on a line of its own.
+
+Options:
+  --config <file>   Read settings from <file> instead of luxor.toml
+  -h, --help        Show this message
+
+Rules:";
+
+pub fn run(args: &[String]) -> i32 {
+    let mut config_path = None;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => match args.next() {
+                Some(path) => config_path = Some(PathBuf::from(path)),
+                None => {
+                    eprintln!("--config needs an argument\n\n{}", usage());
+                    return 64;
+                }
+            },
+            "-h" | "--help" => {
+                println!("{}", usage());
+                return 0;
+            }
+            "-" => paths.push(arg.as_str()),
+            flag if flag.starts_with('-') => {
+                eprintln!("Unknown option '{}'\n\n{}", flag, usage());
+                return 64;
+            }
+            path => paths.push(path),
+        }
+    }
+    if paths.is_empty() {
+        paths.push("-");
+    }
+
+    let config_path = config_path.or_else(|| find_config(&env::current_dir().ok()?));
+    let config = match &config_path {
+        Some(path) => match fs::read_to_string(path) {
+            Ok(src) => match Config::parse(&src) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("{}:\n{}", path.display(), e);
+                    return 78;
+                }
+            },
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                return 66;
+            }
+        },
+        None => Config::default(),
+    };
+
+    let mut status = 0;
+    for path in paths {
+        let name = if path == "-" { "<stdin>" } else { path };
+        let src = match super::read(path) {
+            Ok(src) => src,
+            Err(e) => {
+                eprintln!("{}: {}", name, e);
+                status = status.max(66);
+                continue;
+            }
+        };
+
+        match lint(&src, &config) {
+            Ok(lints) if lints.is_empty() => {}
+            Ok(lints) => {
+                println!("{}:", name);
+                for lint in lints {
+                    println!("{}", lint);
+                }
+                status = status.max(1);
+            }
+            Err(errors) => {
+                eprintln!("{}:", name);
+                for error in errors {
+                    eprintln!("{}", error);
+                }
+                status = status.max(65);
+            }
+        }
+    }
+    status
+}
+
+fn usage() -> String {
+    let mut usage = USAGE.to_string();
+    for rule in &Rule::ALL {
+        usage.push_str(&format!("\n  {}", rule));
+    }
+    usage
+}
@@ -0,0 +1,38 @@
+// `luxor lsp`: a language server for editors, speaking JSON-RPC on stdio
+
+use std::io;
+
+const USAGE: &str = "\
+Usage: luxor lsp
+
+Runs a Language Server Protocol server on stdin and stdout, for editors.
+It reports syntax errors and lint warnings as you type, and answers hover,
+go-to-definition, references, document symbols and completion. Lint rules
+come from the nearest luxor.toml to each file, as for 'luxor lint'.
+
+Options:
+  -h, --help    Show this message";
+
+pub fn run(args: &[String]) -> i32 {
+    match args.first().map(String::as_str) {
+        None => {}
+        Some("-h") | Some("--help") => {
+            println!("{}", USAGE);
+            return 0;
+        }
+        Some(arg) => {
+            eprintln!("Unexpected argument '{}'\n\n{}", arg, USAGE);
+            return 64;
+        }
+    }
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    match luxor::lsp::serve(stdin.lock(), stdout.lock()) {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("luxor lsp: {}", e);
+            74
+        }
+    }
+}
@@ -0,0 +1,22 @@
+// Subcommands of the `luxor` binary, each with its own arguments. `run`
+// takes the arguments after the command name and returns the exit status.
+
+pub mod compile;
+pub mod fmt;
+pub mod highlight;
+pub mod lint;
+pub mod lsp;
+
+use std::fs;
+use std::io::{self, Read};
+
+// A script from a file, or from stdin for "-"
+fn read(path: &str) -> io::Result<String> {
+    if path == "-" {
+        let mut src = String::new();
+        io::stdin().read_to_string(&mut src)?;
+        Ok(src)
+    } else {
+        fs::read_to_string(path)
+    }
+}
@@ -0,0 +1,86 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::value::Value;
+
+#[derive(Debug, Default)]
+pub struct Environment {
+    values: HashMap<String, Value>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+    // Registered with the garbage collector
+    pub(crate) tracked: Cell<bool>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
+        Environment {
+            values: HashMap::new(),
+            enclosing: Some(enclosing),
+            tracked: Cell::new(false),
+        }
+    }
+
+    // Redefining an existing name is allowed and simply overwrites it
+    pub fn define(&mut self, name: &str, value: Value) {
+        self.values.insert(name.to_string(), value);
+    }
+
+    // Variables defined directly in this scope, in no particular order
+    pub fn values(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.values.iter()
+    }
+
+    pub(crate) fn enclosing(&self) -> Option<&Rc<RefCell<Environment>>> {
+        self.enclosing.as_ref()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        match self.values.get(name) {
+            Some(v) => Some(v.clone()),
+            None => self.enclosing.as_ref()?.borrow().get(name),
+        }
+    }
+
+    // The variable in the scope `depth` out from this one, as worked out by
+    // the resolver
+    pub fn get_at(&self, depth: usize, name: &str) -> Option<Value> {
+        if depth == 0 {
+            return self.values.get(name).cloned();
+        }
+        self.enclosing.as_ref()?.borrow().get_at(depth - 1, name)
+    }
+
+    pub fn assign_at(&mut self, depth: usize, name: &str, value: Value) -> bool {
+        if depth == 0 {
+            return match self.values.get_mut(name) {
+                Some(v) => {
+                    *v = value;
+                    true
+                }
+                None => false,
+            };
+        }
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign_at(depth - 1, name, value),
+            None => false,
+        }
+    }
+
+    // Returns false if the variable isn't defined in any enclosing scope
+    pub fn assign(&mut self, name: &str, value: Value) -> bool {
+        if let Some(v) = self.values.get_mut(name) {
+            *v = value;
+            return true;
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::{error, fmt, io};
+
+use crate::loxc::LoadError;
+
+// A problem found while scanning or parsing, reported before anything runs
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub line: u32,
+    pub location: String,
+    pub message: String,
+}
+
+impl SyntaxError {
+    pub fn new(line: u32, location: &str, message: &str) -> Self {
+        SyntaxError {
+            line,
+            location: location.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_diagnostic(f, "Error", self.line, &self.location, &self.message)
+    }
+}
+
+// The one layout for everything reported against a line of source, so
+// errors and lint warnings read the same. `location` is empty or starts
+// with a space, like " at 'x'".
+pub(crate) fn write_diagnostic(
+    f: &mut fmt::Formatter<'_>,
+    severity: &str,
+    line: u32,
+    location: &str,
+    message: &str,
+) -> fmt::Result {
+    write!(f, "[line {}] {}{}: {}", line, severity, location, message)
+}
+
+impl error::Error for SyntaxError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    // Raised by the script itself or by a native it called
+    Error { line: u32, message: String },
+    // The run was cut short by one of the interpreter's `Limits`
+    LimitExceeded { line: u32, limit: Limit },
+}
+
+impl RuntimeError {
+    pub fn new(line: u32, message: &str) -> Self {
+        RuntimeError::Error {
+            line,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn line(&self) -> u32 {
+        match self {
+            RuntimeError::Error { line, .. } | RuntimeError::LimitExceeded { line, .. } => *line,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            RuntimeError::Error { message, .. } => message.clone(),
+            RuntimeError::LimitExceeded { limit, .. } => limit.to_string(),
+        }
+    }
+
+    // Errors raised from Rust code don't know where they happened
+    pub(crate) fn at_line(mut self, at: u32) -> Self {
+        match &mut self {
+            RuntimeError::Error { line, .. } | RuntimeError::LimitExceeded { line, .. } => {
+                if *line == 0 {
+                    *line = at;
+                }
+            }
+        }
+        self
+    }
+}
+
+// For natives: the interpreter fills in the line of the call site
+impl From<&str> for RuntimeError {
+    fn from(message: &str) -> Self {
+        RuntimeError::new(0, message)
+    }
+}
+
+impl From<String> for RuntimeError {
+    fn from(message: String) -> Self {
+        RuntimeError::Error { line: 0, message }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\n[line {}]", self.message(), self.line())
+    }
+}
+
+impl error::Error for RuntimeError {}
+
+// Which of the interpreter's `Limits` was hit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Steps,
+    Heap,
+    // Either `max_call_depth` or `max_stack_bytes`
+    CallDepth,
+    Timeout,
+    Interrupted,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Steps => f.write_str("Step limit exceeded."),
+            Limit::Heap => f.write_str("Heap limit exceeded."),
+            Limit::CallDepth => f.write_str("Stack overflow."),
+            Limit::Timeout => f.write_str("Timed out."),
+            Limit::Interrupted => f.write_str("Interrupted."),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Syntax(Vec<SyntaxError>),
+    Runtime(RuntimeError),
+    // A compiled script that can't be run
+    Load(LoadError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Syntax(errors) => {
+                let lines: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                f.write_str(&lines.join("\n"))
+            }
+            Error::Runtime(e) => write!(f, "{}", e),
+            Error::Load(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Syntax(errors) => errors.first().map(|e| e as &(dyn error::Error + 'static)),
+            Error::Runtime(e) => Some(e),
+            Error::Load(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<Vec<SyntaxError>> for Error {
+    fn from(errors: Vec<SyntaxError>) -> Self {
+        Error::Syntax(errors)
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(e: RuntimeError) -> Self {
+        Error::Runtime(e)
+    }
+}
+
+impl From<LoadError> for Error {
+    fn from(e: LoadError) -> Self {
+        Error::Load(e)
+    }
+}
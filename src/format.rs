@@ -0,0 +1,1152 @@
+// Source formatter behind `luxor fmt`.
+//
+// The program is parsed as usual, then walked statement by statement while
+// a cursor steps through the scanned tokens in the same order, so every
+// token is printed from its source text and the comments the scanner set
+// aside can be put back next to the token they followed. The walk builds a
+// `Doc`, a tree of text and possible line breaks in the style of Wadler's
+// "prettier printer", which is laid out to fit the line width.
+//
+// The output is parsed again and must give the same syntax tree and the
+// same comments, otherwise formatting fails rather than change the program.
+
+use std::fmt;
+
+use crate::ast::{Expr, ExprArena, ExprId, Function, Program, Stmt};
+use crate::error::SyntaxError;
+use crate::parser::Parser;
+use crate::scanner::{Comment, Scanner};
+use crate::token::{LiteralKind, Token, TokenType};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Style {
+    // Spaces per level of nesting
+    pub indent: usize,
+    // Lines are broken to stay within this many columns where possible
+    pub width: usize,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            indent: 2,
+            width: 80,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatError {
+    // The source doesn't parse, so there's nothing to format
+    Syntax(Vec<SyntaxError>),
+    // The formatted source parsed differently from the original, which is
+    // a bug in the formatter
+    ChangedMeaning,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Syntax(errors) => {
+                let lines: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                f.write_str(&lines.join("\n"))
+            }
+            FormatError::ChangedMeaning => {
+                f.write_str("Formatting would change the meaning of the program.")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+pub fn format(src: &str) -> Result<String, FormatError> {
+    format_with(src, &Style::default())
+}
+
+pub fn format_with(src: &str, style: &Style) -> Result<String, FormatError> {
+    let (program, tokens, comments) = parse(src)?;
+
+    let mut builder = Builder {
+        exprs: &program.exprs,
+        tokens: &tokens,
+        comments: &comments,
+        cursor: 0,
+        next_comment: 0,
+        last_line: 0,
+    };
+    let doc = builder.program(&program.statements);
+    let out = render(&doc, style);
+
+    let (reparsed, _, recomments) = parse(&out).map_err(|_| FormatError::ChangedMeaning)?;
+    let same = Compare {
+        left: &program.exprs,
+        right: &reparsed.exprs,
+    };
+    let texts =
+        |comments: &[Comment]| -> Vec<String> { comments.iter().map(|c| c.text.clone()).collect() };
+    if !same.stmts(&program.statements, &reparsed.statements)
+        || texts(&comments) != texts(&recomments)
+    {
+        return Err(FormatError::ChangedMeaning);
+    }
+
+    Ok(out)
+}
+
+fn parse(src: &str) -> Result<(Program, Vec<Token>, Vec<Comment>), FormatError> {
+    let mut scanner = Scanner::new(src);
+    let tokens = scanner.scan_tokens().to_vec();
+    let comments = scanner.comments().to_vec();
+    let program = Parser::new(tokens.clone())
+        .parse()
+        .map_err(FormatError::Syntax)?;
+    Ok((program, tokens, comments))
+}
+
+enum Doc {
+    Text(String),
+    // A space, or a line break when the enclosing group doesn't fit
+    Line,
+    // Nothing, or a line break when the enclosing group doesn't fit
+    SoftLine,
+    // Always a line break; consecutive breaks make a single one
+    HardLine,
+    // A line break with an empty line after it
+    BlankLine,
+    // Contents are indented one level more after line breaks
+    Nest(Box<Doc>),
+    // Printed on one line if it fits, otherwise its lines all break. The
+    // flag is set when it holds a hard line and so can never fit.
+    Group(Box<Doc>, bool),
+    Concat(Vec<Doc>),
+}
+
+impl Doc {
+    fn text(s: &str) -> Doc {
+        Doc::Text(s.to_string())
+    }
+
+    fn nest(docs: Vec<Doc>) -> Doc {
+        Doc::Nest(Box::new(Doc::Concat(docs)))
+    }
+
+    fn group(docs: Vec<Doc>) -> Doc {
+        let doc = Doc::Concat(docs);
+        let broken = doc.has_hard_line();
+        Doc::Group(Box::new(doc), broken)
+    }
+
+    fn has_hard_line(&self) -> bool {
+        match self {
+            Doc::HardLine | Doc::BlankLine => true,
+            Doc::Text(_) | Doc::Line | Doc::SoftLine => false,
+            Doc::Nest(doc) => doc.has_hard_line(),
+            Doc::Group(_, broken) => *broken,
+            Doc::Concat(docs) => docs.iter().any(Doc::has_hard_line),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+fn render(doc: &Doc, style: &Style) -> String {
+    let mut out = Writer {
+        out: String::new(),
+        indent: style.indent,
+        column: 0,
+        at_line_start: true,
+    };
+
+    let mut stack = vec![(0, Mode::Break, doc)];
+    while let Some((indent, mode, doc)) = stack.pop() {
+        match doc {
+            Doc::Text(s) => out.text(indent, s),
+            Doc::Line if mode == Mode::Flat => out.text(indent, " "),
+            Doc::SoftLine if mode == Mode::Flat => {}
+            Doc::Line | Doc::SoftLine | Doc::HardLine => out.newline(),
+            Doc::BlankLine => out.blank_line(),
+            Doc::Nest(doc) => stack.push((indent + 1, mode, doc)),
+            Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| (indent, mode, doc))),
+            Doc::Group(doc, broken) => {
+                let mode = if mode == Mode::Flat {
+                    Mode::Flat
+                } else if *broken {
+                    Mode::Break
+                } else {
+                    let room = style.width as isize - out.column(indent) as isize;
+                    if fits(room, (indent, Mode::Flat, doc), &stack) {
+                        Mode::Flat
+                    } else {
+                        Mode::Break
+                    }
+                };
+                stack.push((indent, mode, doc));
+            }
+        }
+    }
+
+    out.newline();
+    out.out
+}
+
+// Whether `next` and whatever follows it up to the next line break fit in
+// `room` columns
+fn fits(mut room: isize, next: (usize, Mode, &Doc), rest: &[(usize, Mode, &Doc)]) -> bool {
+    let mut stack = vec![next];
+    let mut rest = rest.iter().rev();
+    while room >= 0 {
+        let (indent, mode, doc) = match stack.pop().or_else(|| rest.next().copied()) {
+            Some(item) => item,
+            None => return true,
+        };
+        match doc {
+            Doc::Text(s) => {
+                let first_line = s.split('\n').next().unwrap_or("");
+                room -= first_line.chars().count() as isize;
+                if s.contains('\n') {
+                    return room >= 0;
+                }
+            }
+            Doc::Line if mode == Mode::Flat => room -= 1,
+            Doc::SoftLine if mode == Mode::Flat => {}
+            Doc::Line | Doc::SoftLine | Doc::HardLine | Doc::BlankLine => return true,
+            Doc::Nest(doc) => stack.push((indent + 1, mode, doc)),
+            Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| (indent, mode, doc))),
+            Doc::Group(doc, broken) => {
+                let mode = if *broken { Mode::Break } else { mode };
+                stack.push((indent, mode, doc));
+            }
+        }
+    }
+    false
+}
+
+// Indents lazily, so lines never end in whitespace and breaks collapse
+struct Writer {
+    out: String,
+    indent: usize,
+    column: usize,
+    at_line_start: bool,
+}
+
+impl Writer {
+    fn column(&self, indent: usize) -> usize {
+        if self.at_line_start {
+            indent * self.indent
+        } else {
+            self.column
+        }
+    }
+
+    fn text(&mut self, indent: usize, s: &str) {
+        if self.at_line_start {
+            if s.trim().is_empty() {
+                return;
+            }
+            let spaces = indent * self.indent;
+            self.out.extend(std::iter::repeat_n(' ', spaces));
+            self.column = spaces;
+            self.at_line_start = false;
+        }
+        self.out.push_str(s);
+        match s.rfind('\n') {
+            Some(at) => self.column = s[at + 1..].chars().count(),
+            None => self.column += s.chars().count(),
+        }
+    }
+
+    fn newline(&mut self) {
+        if !self.at_line_start {
+            let len = self.out.trim_end_matches(' ').len();
+            self.out.truncate(len);
+            self.out.push('\n');
+            self.at_line_start = true;
+        }
+    }
+
+    fn blank_line(&mut self) {
+        self.newline();
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+}
+
+// Builds the `Doc` for a program, taking tokens and comments in source order
+struct Builder<'a> {
+    exprs: &'a ExprArena,
+    tokens: &'a [Token],
+    comments: &'a [Comment],
+    // The next token to print
+    cursor: usize,
+    next_comment: usize,
+    // Where the last token or comment printed ended
+    last_line: u32,
+}
+
+impl Builder<'_> {
+    fn program(&mut self, statements: &[Stmt]) -> Doc {
+        let mut docs = Vec::new();
+        for (i, stmt) in statements.iter().enumerate() {
+            docs.push(self.new_line(i > 0));
+            docs.push(self.stmt(stmt));
+        }
+        self.own_line_comments(&mut docs, !statements.is_empty());
+        Doc::Concat(docs)
+    }
+
+    // The next token, with the comments around it
+    fn token(&mut self) -> Doc {
+        let mut docs = Vec::new();
+        if self.own_line_comments(&mut docs, false) {
+            docs.push(Doc::HardLine);
+        }
+
+        let token = &self.tokens[self.cursor];
+        docs.push(Doc::Text(source_text(token)));
+        self.cursor += 1;
+        self.last_line = token.line;
+
+        // Comments that follow on the same line stay there
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.token_index != self.cursor || comment.line != token.line {
+                break;
+            }
+            docs.push(Doc::Text(format!(" {}", comment.text)));
+            if comment.text.starts_with("//") {
+                docs.push(Doc::HardLine);
+            }
+            self.last_line = comment.end_line;
+            self.next_comment += 1;
+        }
+
+        Doc::Concat(docs)
+    }
+
+    // Comments on lines of their own before the next token, each starting
+    // a new line. Blank lines between them are kept, and before the first
+    // one if `blank_lines` is set. Returns whether there were any.
+    fn own_line_comments(&mut self, docs: &mut Vec<Doc>, mut blank_lines: bool) -> bool {
+        let start = self.next_comment;
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.token_index != self.cursor {
+                break;
+            }
+            docs.push(self.line_break(comment.line, blank_lines));
+            blank_lines = true;
+            docs.push(Doc::text(&comment.text));
+            self.last_line = comment.end_line;
+            self.next_comment += 1;
+        }
+        self.next_comment > start
+    }
+
+    // Starts the line for the next statement, after any comments leading up
+    // to it. A blank line in the source is kept unless `blank_lines` is
+    // false and it's the first thing in the file or block.
+    fn new_line(&mut self, blank_lines: bool) -> Doc {
+        let mut docs = Vec::new();
+        let commented = self.own_line_comments(&mut docs, blank_lines);
+        let line = self.tokens[self.cursor].line;
+        docs.push(self.line_break(line, blank_lines || commented));
+        Doc::Concat(docs)
+    }
+
+    fn line_break(&self, line: u32, blank_lines: bool) -> Doc {
+        if blank_lines && self.last_line > 0 && line > self.last_line + 1 {
+            Doc::BlankLine
+        } else {
+            Doc::HardLine
+        }
+    }
+
+    // `{` items `}`, one item per line
+    fn braced<T>(&mut self, items: &[T], mut item: impl FnMut(&mut Self, &T) -> Doc) -> Doc {
+        let open = self.token();
+        let mut inner = Vec::new();
+        for (i, it) in items.iter().enumerate() {
+            inner.push(self.new_line(i > 0));
+            inner.push(item(self, it));
+        }
+        self.own_line_comments(&mut inner, !items.is_empty());
+
+        if inner.is_empty() {
+            return Doc::Concat(vec![open, self.token()]);
+        }
+        Doc::Concat(vec![open, Doc::nest(inner), Doc::HardLine, self.token()])
+    }
+
+    fn block(&mut self, statements: &[Stmt]) -> Doc {
+        self.braced(statements, Self::stmt)
+    }
+
+    // The body of an `if`, `else`, `while` or `for`: blocks open on the same
+    // line, anything else follows on the same line when it fits
+    fn body(&mut self, stmt: &Stmt) -> Doc {
+        match stmt {
+            Stmt::Block { statements, .. } => {
+                Doc::Concat(vec![Doc::text(" "), self.block(statements)])
+            }
+            _ => Doc::group(vec![Doc::nest(vec![Doc::Line, self.stmt(stmt)])]),
+        }
+    }
+
+    // "(" expression ")" after a keyword
+    fn condition(&mut self, condition: ExprId) -> Doc {
+        Doc::Concat(vec![
+            Doc::text(" "),
+            self.token(),
+            self.expr(condition),
+            self.token(),
+        ])
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Doc {
+        match stmt {
+            Stmt::Block { statements, .. } => self.block(statements),
+            Stmt::Break { .. } | Stmt::Continue { .. } => {
+                Doc::Concat(vec![self.token(), self.token()])
+            }
+            Stmt::Class {
+                superclass,
+                methods,
+                ..
+            } => {
+                let mut docs = vec![self.token(), Doc::text(" "), self.token()];
+                if superclass.is_some() {
+                    docs.extend(vec![
+                        Doc::text(" "),
+                        self.token(),
+                        Doc::text(" "),
+                        self.token(),
+                    ]);
+                }
+                docs.push(Doc::text(" "));
+                docs.push(self.braced(methods, |b, method| b.function(method)));
+                Doc::Concat(docs)
+            }
+            Stmt::Expression { expression } => {
+                Doc::Concat(vec![self.expr(*expression), self.token()])
+            }
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                let mut docs = vec![self.token(), Doc::text(" "), self.token()];
+                match initializer {
+                    Some(initializer) => docs.push(self.stmt(initializer)),
+                    None => docs.push(self.token()),
+                }
+                if let Some(condition) = condition {
+                    docs.push(Doc::text(" "));
+                    docs.push(self.expr(*condition));
+                }
+                docs.push(self.token());
+                if let Some(increment) = increment {
+                    docs.push(Doc::text(" "));
+                    docs.push(self.expr(*increment));
+                }
+                docs.push(self.token());
+                docs.push(self.body(body));
+                Doc::Concat(docs)
+            }
+            Stmt::ForIn { iterable, body, .. } => {
+                // `for (var name in`
+                let mut docs = vec![self.token(), Doc::text(" "), self.token(), self.token()];
+                for _ in 0..2 {
+                    docs.push(Doc::text(" "));
+                    docs.push(self.token());
+                }
+                docs.push(Doc::text(" "));
+                docs.push(self.expr(*iterable));
+                docs.push(self.token());
+                docs.push(self.body(body));
+                Doc::Concat(docs)
+            }
+            Stmt::Function(function) => {
+                let keyword = self.token();
+                Doc::Concat(vec![keyword, Doc::text(" "), self.function(function)])
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let mut docs = vec![self.token(), self.condition(*condition)];
+                docs.push(self.body(then_branch));
+                if let Some(else_branch) = else_branch {
+                    match **then_branch {
+                        Stmt::Block { .. } => docs.push(Doc::text(" ")),
+                        _ => docs.push(Doc::HardLine),
+                    }
+                    docs.push(self.token());
+                    match **else_branch {
+                        // `else if` chains stay flat
+                        Stmt::If { .. } => {
+                            docs.push(Doc::text(" "));
+                            docs.push(self.stmt(else_branch));
+                        }
+                        _ => docs.push(self.body(else_branch)),
+                    }
+                }
+                Doc::Concat(docs)
+            }
+            Stmt::Print { expression } => Doc::Concat(vec![
+                self.token(),
+                Doc::text(" "),
+                self.expr(*expression),
+                self.token(),
+            ]),
+            Stmt::Return { value, .. } => {
+                let mut docs = vec![self.token()];
+                if let Some(value) = value {
+                    docs.push(Doc::text(" "));
+                    docs.push(self.expr(*value));
+                }
+                docs.push(self.token());
+                Doc::Concat(docs)
+            }
+            Stmt::Var { initializer, .. } => {
+                let mut docs = vec![self.token(), Doc::text(" "), self.token()];
+                if let Some(initializer) = initializer {
+                    docs.extend(vec![
+                        Doc::text(" "),
+                        self.token(),
+                        Doc::text(" "),
+                        self.expr(*initializer),
+                    ]);
+                }
+                docs.push(self.token());
+                Doc::Concat(docs)
+            }
+            Stmt::While { condition, body } => {
+                let mut docs = vec![self.token(), self.condition(*condition)];
+                docs.push(self.body(body));
+                Doc::Concat(docs)
+            }
+        }
+    }
+
+    // Name, parameters and body; the caller prints any `fun`
+    fn function(&mut self, function: &Function) -> Doc {
+        let name = self.token();
+        let params = self.list(function.params.len(), |b, _| b.token());
+        Doc::Concat(vec![
+            name,
+            params,
+            Doc::text(" "),
+            self.block(&function.body),
+        ])
+    }
+
+    // "(", "[" or "{" with items separated by ",", broken one per line if
+    // too long, and the closing token
+    fn list(&mut self, len: usize, mut item: impl FnMut(&mut Self, usize) -> Doc) -> Doc {
+        let open = self.token();
+        if len == 0 {
+            return Doc::Concat(vec![open, self.token()]);
+        }
+
+        let mut inner = vec![Doc::SoftLine];
+        for i in 0..len {
+            if i > 0 {
+                inner.push(self.token());
+                inner.push(Doc::Line);
+            }
+            inner.push(item(self, i));
+        }
+        Doc::group(vec![open, Doc::nest(inner), Doc::SoftLine, self.token()])
+    }
+
+    fn expr(&mut self, id: ExprId) -> Doc {
+        let exprs = self.exprs;
+        match &exprs[id] {
+            Expr::Binary { .. } | Expr::Logical { .. } => self.chain(id),
+            Expr::Assign { value, .. } => Doc::Concat(vec![
+                self.token(),
+                Doc::text(" "),
+                self.token(),
+                Doc::text(" "),
+                self.expr(*value),
+            ]),
+            Expr::Call {
+                callee, arguments, ..
+            } => Doc::Concat(vec![
+                self.expr(*callee),
+                self.list(arguments.len(), |b, i| b.expr(arguments[i])),
+            ]),
+            Expr::Get { object, .. } => {
+                Doc::Concat(vec![self.expr(*object), self.token(), self.token()])
+            }
+            Expr::Grouping { expression } => {
+                Doc::Concat(vec![self.token(), self.expr(*expression), self.token()])
+            }
+            Expr::Index { object, index, .. } => Doc::Concat(vec![
+                self.expr(*object),
+                self.token(),
+                self.expr(*index),
+                self.token(),
+            ]),
+            Expr::List { elements, .. } => self.list(elements.len(), |b, i| b.expr(elements[i])),
+            Expr::Map { entries, .. } => self.list(entries.len(), |b, i| {
+                let (key, value) = entries[i];
+                Doc::Concat(vec![b.expr(key), b.token(), Doc::text(" "), b.expr(value)])
+            }),
+            Expr::Set { object, value, .. } => Doc::Concat(vec![
+                self.expr(*object),
+                self.token(),
+                self.token(),
+                Doc::text(" "),
+                self.token(),
+                Doc::text(" "),
+                self.expr(*value),
+            ]),
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => Doc::Concat(vec![
+                self.expr(*object),
+                self.token(),
+                self.expr(*index),
+                self.token(),
+                Doc::text(" "),
+                self.token(),
+                Doc::text(" "),
+                self.expr(*value),
+            ]),
+            Expr::Slice {
+                object, start, end, ..
+            } => {
+                let mut docs = vec![self.expr(*object), self.token()];
+                if let Some(start) = start {
+                    docs.push(self.expr(*start));
+                }
+                docs.push(self.token());
+                if let Some(end) = end {
+                    docs.push(self.expr(*end));
+                }
+                docs.push(self.token());
+                Doc::Concat(docs)
+            }
+            Expr::Super { .. } => Doc::Concat(vec![self.token(), self.token(), self.token()]),
+            Expr::Unary { right, .. } => Doc::Concat(vec![self.token(), self.expr(*right)]),
+            Expr::Lit(_) | Expr::This { .. } | Expr::Variable { .. } => self.token(),
+        }
+    }
+
+    // A run of operators of one precedence, like `a + b - c`, as a single
+    // group: when it breaks, every operand after the first goes on its own
+    // line at the same indent. The left spine is walked with a loop, so
+    // long chains don't recurse.
+    fn chain(&mut self, id: ExprId) -> Doc {
+        let exprs = self.exprs;
+        let level = binary(&exprs[id]).map(|(_, operator, _)| precedence(operator));
+        let mut first = id;
+        let mut rights = Vec::new();
+        while let Some((left, operator, right)) = binary(&exprs[first]) {
+            if Some(precedence(operator)) != level {
+                break;
+            }
+            rights.push(right);
+            first = left;
+        }
+
+        let first = self.expr(first);
+        let mut rest = Vec::new();
+        for right in rights.into_iter().rev() {
+            rest.push(Doc::text(" "));
+            rest.push(self.token());
+            rest.push(Doc::Line);
+            rest.push(self.expr(right));
+        }
+        Doc::group(vec![first, Doc::nest(rest)])
+    }
+}
+
+// The operands and operator of a binary or logical expression
+fn binary(expr: &Expr) -> Option<(ExprId, &Token, ExprId)> {
+    match expr {
+        Expr::Binary {
+            left,
+            operator,
+            right,
+        }
+        | Expr::Logical {
+            left,
+            operator,
+            right,
+        } => Some((*left, operator, *right)),
+        _ => None,
+    }
+}
+
+// How tightly an operator binds, as the parser's grammar rules have it
+fn precedence(operator: &Token) -> u8 {
+    match operator.kind {
+        TokenType::Or => 0,
+        TokenType::And => 1,
+        TokenType::BangEqual | TokenType::EqualEqual => 2,
+        TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => 3,
+        TokenType::Minus | TokenType::Plus => 4,
+        _ => 5,
+    }
+}
+
+// How a token is written in source
+fn source_text(token: &Token) -> String {
+    match &token.kind {
+        TokenType::Literal(LiteralKind::Str(s)) => format!("\"{}\"", s),
+        _ => token.to_string(),
+    }
+}
+
+// Structural equality of two syntax trees, ignoring lines and ids
+struct Compare<'a> {
+    left: &'a ExprArena,
+    right: &'a ExprArena,
+}
+
+impl Compare<'_> {
+    fn stmts(&self, a: &[Stmt], b: &[Stmt]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| self.stmt(a, b))
+    }
+
+    fn opt_stmt(&self, a: Option<&Stmt>, b: Option<&Stmt>) -> bool {
+        match (a, b) {
+            (Some(a), Some(b)) => self.stmt(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        }
+    }
+
+    fn stmt(&self, a: &Stmt, b: &Stmt) -> bool {
+        match (a, b) {
+            (Stmt::Block { statements: a, .. }, Stmt::Block { statements: b, .. }) => {
+                self.stmts(a, b)
+            }
+            (Stmt::Break { .. }, Stmt::Break { .. })
+            | (Stmt::Continue { .. }, Stmt::Continue { .. }) => true,
+            (
+                Stmt::Class {
+                    name,
+                    superclass,
+                    methods,
+                },
+                Stmt::Class {
+                    name: name2,
+                    superclass: superclass2,
+                    methods: methods2,
+                },
+            ) => {
+                same_token(name, name2)
+                    && self.opt_expr(*superclass, *superclass2)
+                    && methods.len() == methods2.len()
+                    && methods
+                        .iter()
+                        .zip(methods2)
+                        .all(|(a, b)| self.function(a, b))
+            }
+            (Stmt::Expression { expression: a }, Stmt::Expression { expression: b })
+            | (Stmt::Print { expression: a }, Stmt::Print { expression: b }) => self.expr(*a, *b),
+            (
+                Stmt::For {
+                    initializer,
+                    condition,
+                    increment,
+                    body,
+                    ..
+                },
+                Stmt::For {
+                    initializer: initializer2,
+                    condition: condition2,
+                    increment: increment2,
+                    body: body2,
+                    ..
+                },
+            ) => {
+                self.opt_stmt(initializer.as_deref(), initializer2.as_deref())
+                    && self.opt_expr(*condition, *condition2)
+                    && self.opt_expr(*increment, *increment2)
+                    && self.stmt(body, body2)
+            }
+            (
+                Stmt::ForIn {
+                    name,
+                    iterable,
+                    body,
+                    ..
+                },
+                Stmt::ForIn {
+                    name: name2,
+                    iterable: iterable2,
+                    body: body2,
+                    ..
+                },
+            ) => {
+                same_token(name, name2)
+                    && self.expr(*iterable, *iterable2)
+                    && self.stmt(body, body2)
+            }
+            (Stmt::Function(a), Stmt::Function(b)) => self.function(a, b),
+            (
+                Stmt::If {
+                    condition,
+                    then_branch,
+                    else_branch,
+                },
+                Stmt::If {
+                    condition: condition2,
+                    then_branch: then_branch2,
+                    else_branch: else_branch2,
+                },
+            ) => {
+                self.expr(*condition, *condition2)
+                    && self.stmt(then_branch, then_branch2)
+                    && self.opt_stmt(else_branch.as_deref(), else_branch2.as_deref())
+            }
+            (Stmt::Return { value, .. }, Stmt::Return { value: value2, .. }) => {
+                self.opt_expr(*value, *value2)
+            }
+            (
+                Stmt::Var { name, initializer },
+                Stmt::Var {
+                    name: name2,
+                    initializer: initializer2,
+                },
+            ) => same_token(name, name2) && self.opt_expr(*initializer, *initializer2),
+            (
+                Stmt::While { condition, body },
+                Stmt::While {
+                    condition: condition2,
+                    body: body2,
+                },
+            ) => self.expr(*condition, *condition2) && self.stmt(body, body2),
+            _ => false,
+        }
+    }
+
+    fn function(&self, a: &Function, b: &Function) -> bool {
+        same_token(&a.name, &b.name)
+            && a.params.len() == b.params.len()
+            && a.params
+                .iter()
+                .zip(&b.params)
+                .all(|(a, b)| same_token(a, b))
+            && self.stmts(&a.body, &b.body)
+    }
+
+    fn opt_expr(&self, a: Option<ExprId>, b: Option<ExprId>) -> bool {
+        match (a, b) {
+            (Some(a), Some(b)) => self.expr(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        }
+    }
+
+    fn exprs(&self, a: &[ExprId], b: &[ExprId]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| self.expr(*a, *b))
+    }
+
+    fn expr(&self, a: ExprId, b: ExprId) -> bool {
+        match (&self.left[a], &self.right[b]) {
+            (
+                Expr::Binary {
+                    left,
+                    operator,
+                    right,
+                },
+                Expr::Binary {
+                    left: left2,
+                    operator: operator2,
+                    right: right2,
+                },
+            )
+            | (
+                Expr::Logical {
+                    left,
+                    operator,
+                    right,
+                },
+                Expr::Logical {
+                    left: left2,
+                    operator: operator2,
+                    right: right2,
+                },
+            ) => {
+                same_token(operator, operator2)
+                    && self.expr(*left, *left2)
+                    && self.expr(*right, *right2)
+            }
+            (
+                Expr::Assign { name, value },
+                Expr::Assign {
+                    name: name2,
+                    value: value2,
+                },
+            ) => same_token(name, name2) && self.expr(*value, *value2),
+            (
+                Expr::Call {
+                    callee, arguments, ..
+                },
+                Expr::Call {
+                    callee: callee2,
+                    arguments: arguments2,
+                    ..
+                },
+            ) => self.expr(*callee, *callee2) && self.exprs(arguments, arguments2),
+            (
+                Expr::Get { object, name },
+                Expr::Get {
+                    object: object2,
+                    name: name2,
+                },
+            ) => same_token(name, name2) && self.expr(*object, *object2),
+            (
+                Expr::Grouping { expression },
+                Expr::Grouping {
+                    expression: expression2,
+                },
+            ) => self.expr(*expression, *expression2),
+            (
+                Expr::Index { object, index, .. },
+                Expr::Index {
+                    object: object2,
+                    index: index2,
+                    ..
+                },
+            ) => self.expr(*object, *object2) && self.expr(*index, *index2),
+            (Expr::Lit(a), Expr::Lit(b)) => same_token(a, b),
+            (
+                Expr::List { elements, .. },
+                Expr::List {
+                    elements: elements2,
+                    ..
+                },
+            ) => self.exprs(elements, elements2),
+            (
+                Expr::Map { entries, .. },
+                Expr::Map {
+                    entries: entries2,
+                    ..
+                },
+            ) => {
+                entries.len() == entries2.len()
+                    && entries
+                        .iter()
+                        .zip(entries2)
+                        .all(|(a, b)| self.expr(a.0, b.0) && self.expr(a.1, b.1))
+            }
+            (
+                Expr::Set {
+                    object,
+                    name,
+                    value,
+                },
+                Expr::Set {
+                    object: object2,
+                    name: name2,
+                    value: value2,
+                },
+            ) => {
+                same_token(name, name2)
+                    && self.expr(*object, *object2)
+                    && self.expr(*value, *value2)
+            }
+            (
+                Expr::SetIndex {
+                    object,
+                    index,
+                    value,
+                    ..
+                },
+                Expr::SetIndex {
+                    object: object2,
+                    index: index2,
+                    value: value2,
+                    ..
+                },
+            ) => {
+                self.expr(*object, *object2)
+                    && self.expr(*index, *index2)
+                    && self.expr(*value, *value2)
+            }
+            (
+                Expr::Slice {
+                    object, start, end, ..
+                },
+                Expr::Slice {
+                    object: object2,
+                    start: start2,
+                    end: end2,
+                    ..
+                },
+            ) => {
+                self.expr(*object, *object2)
+                    && self.opt_expr(*start, *start2)
+                    && self.opt_expr(*end, *end2)
+            }
+            (
+                Expr::Super { method, .. },
+                Expr::Super {
+                    method: method2, ..
+                },
+            ) => same_token(method, method2),
+            (Expr::This { .. }, Expr::This { .. }) => true,
+            (
+                Expr::Unary { operator, right },
+                Expr::Unary {
+                    operator: operator2,
+                    right: right2,
+                },
+            ) => same_token(operator, operator2) && self.expr(*right, *right2),
+            (Expr::Variable { name }, Expr::Variable { name: name2 }) => same_token(name, name2),
+            _ => false,
+        }
+    }
+}
+
+fn same_token(a: &Token, b: &Token) -> bool {
+    a.kind == b.kind
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn narrow(src: &str, width: usize) -> String {
+        format_with(src, &Style { indent: 2, width }).unwrap()
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(
+            format("var  x=1+2 ;fun f(a,b){return a*-b;}\nif(x>1)print x;else{print\"no\";}")
+                .unwrap(),
+            concat!(
+                "var x = 1 + 2;\n",
+                "fun f(a, b) {\n",
+                "  return a * -b;\n",
+                "}\n",
+                "if (x > 1) print x;\n",
+                "else {\n",
+                "  print \"no\";\n",
+                "}\n",
+            )
+        );
+        assert_eq!(
+            format("class A<B{init(){super.init();this.x=nil;}}\nfor(var i=0;i<3;i=i+1){}for(;;)print 1;")
+                .unwrap(),
+            concat!(
+                "class A < B {\n",
+                "  init() {\n",
+                "    super.init();\n",
+                "    this.x = nil;\n",
+                "  }\n",
+                "}\n",
+                "for (var i = 0; i < 3; i = i + 1) {}\n",
+                "for (;;) print 1;\n",
+            )
+        );
+        assert_eq!(
+            format("var xs=[1,[ ]];xs[ 0 ]=xs[1:];print xs[:-1][0];").unwrap(),
+            "var xs = [1, []];\nxs[0] = xs[1:];\nprint xs[:-1][0];\n"
+        );
+        assert_eq!(
+            format("var m={\"a\":1,2:{}};{m[\"a\"]=m;}").unwrap(),
+            "var m = {\"a\": 1, 2: {}};\n{\n  m[\"a\"] = m;\n}\n"
+        );
+        assert_eq!(
+            format("for(var  x in range(3)){print x;}").unwrap(),
+            "for (var x in range(3)) {\n  print x;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        let src = concat!(
+            "// header\n",
+            "\n",
+            "\n",
+            "print 1; // one\n",
+            "{\n",
+            "      /* inside */\n",
+            "  print 2;\n",
+            "\n",
+            "  // trailing\n",
+            "}\n",
+            "f(a, // first\n",
+            "  b);\n",
+        );
+        let formatted = format(src).unwrap();
+        assert_eq!(
+            formatted,
+            concat!(
+                "// header\n",
+                "\n",
+                "print 1; // one\n",
+                "{\n",
+                "  /* inside */\n",
+                "  print 2;\n",
+                "\n",
+                "  // trailing\n",
+                "}\n",
+                "f(\n",
+                "  a, // first\n",
+                "  b\n",
+                ");\n",
+            )
+        );
+        assert_eq!(format(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_wrapping() {
+        assert_eq!(
+            narrow("print first + second + third;", 24),
+            "print first +\n  second +\n  third;\n"
+        );
+        assert_eq!(
+            narrow("print a * b + c * d - (e - f) or g;", 16),
+            "print a * b +\n  c * d -\n  (e - f) or\n  g;\n"
+        );
+        assert_eq!(
+            narrow("call(first, second, third);", 20),
+            "call(\n  first,\n  second,\n  third\n);\n"
+        );
+        let fits = "call(first, second);\n";
+        assert_eq!(narrow(fits, 20), fits);
+    }
+
+    #[test]
+    fn test_long_chain() {
+        // As long as the parser allows, on a stack much smaller than one
+        // level per operator would need
+        let terms: Vec<String> = (0..499).map(|i| format!("\"{}\"", i)).collect();
+        let src = format!("print {};", terms.join(" + "));
+        let formatted = std::thread::Builder::new()
+            .stack_size(512 << 10)
+            .spawn(move || format(&src).unwrap())
+            .unwrap()
+            .join()
+            .unwrap();
+        let lines: Vec<&str> = formatted.lines().collect();
+        assert_eq!(lines.len(), 499);
+        assert_eq!(lines[0], "print \"0\" +");
+        assert_eq!(lines[1], "  \"1\" +");
+        assert_eq!(lines[498], "  \"498\";");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(format("print ;"), Err(FormatError::Syntax(_))));
+        assert_eq!(format("").unwrap(), "");
+    }
+}
@@ -0,0 +1,138 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::ast;
+use crate::capability::Capability;
+use crate::environment::Environment;
+use crate::error::RuntimeError;
+use crate::interpreter::Interpreter;
+use crate::resolver::Locals;
+use crate::value::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Exact(usize),
+    // Variadic, with a minimum number of arguments
+    AtLeast(usize),
+    // Inclusive bounds, for optional trailing arguments
+    Between(usize, usize),
+}
+
+impl Arity {
+    pub fn accepts(self, count: usize) -> bool {
+        match self {
+            Arity::Exact(n) => count == n,
+            Arity::AtLeast(n) => count >= n,
+            Arity::Between(min, max) => (min..=max).contains(&count),
+        }
+    }
+}
+
+impl From<usize> for Arity {
+    fn from(n: usize) -> Self {
+        Arity::Exact(n)
+    }
+}
+
+pub type NativeFn = dyn Fn(&[Value]) -> Result<Value, RuntimeError>;
+
+// For built-ins that call back into scripts, like `map` on a list
+pub(crate) type CallbackFn = dyn Fn(&mut Interpreter, &[Value]) -> Result<Value, RuntimeError>;
+
+enum NativeBody {
+    Plain(Box<NativeFn>),
+    Callback(Box<CallbackFn>),
+}
+
+// A host function callable from scripts
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: Arity,
+    // What the interpreter has to be granted before this may run
+    pub(crate) capability: Option<Capability>,
+    function: NativeBody,
+}
+
+impl NativeFunction {
+    pub fn new<F>(name: &str, arity: Arity, function: F) -> Self
+    where
+        F: Fn(&[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        NativeFunction {
+            name: name.to_string(),
+            arity,
+            capability: None,
+            function: NativeBody::Plain(Box::new(function)),
+        }
+    }
+
+    pub(crate) fn with_callbacks<F>(name: &str, arity: Arity, function: F) -> Self
+    where
+        F: Fn(&mut Interpreter, &[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        NativeFunction {
+            name: name.to_string(),
+            arity,
+            capability: None,
+            function: NativeBody::Callback(Box::new(function)),
+        }
+    }
+
+    pub(crate) fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        args: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        match &self.function {
+            NativeBody::Plain(function) => function(args),
+            NativeBody::Callback(function) => function(interpreter, args),
+        }
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+// A function declared in Lox, along with the scope it closes over
+pub struct LoxFunction {
+    pub(crate) declaration: Rc<ast::Function>,
+    // Where the declaration's expressions live
+    pub(crate) exprs: Rc<ast::ExprArena>,
+    pub(crate) locals: Rc<Locals>,
+    pub(crate) closure: Rc<RefCell<Environment>>,
+    pub(crate) is_initializer: bool,
+}
+
+impl LoxFunction {
+    // A copy of this method with `this` bound to `instance`
+    pub(crate) fn bind(&self, instance: Value) -> LoxFunction {
+        let mut env = Environment::with_enclosing(Rc::clone(&self.closure));
+        env.define("this", instance);
+        LoxFunction {
+            declaration: Rc::clone(&self.declaration),
+            exprs: Rc::clone(&self.exprs),
+            locals: Rc::clone(&self.locals),
+            closure: Rc::new(RefCell::new(env)),
+            is_initializer: self.is_initializer,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.declaration.name.name()
+    }
+
+    pub fn arity(&self) -> Arity {
+        Arity::Exact(self.declaration.params.len())
+    }
+}
+
+// The closure usually contains the function itself, so don't recurse into it
+impl fmt::Debug for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.name())
+    }
+}
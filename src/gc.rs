@@ -0,0 +1,457 @@
+// Cycle collector. Values are reference counted, which frees everything but
+// cycles: an instance holding one of its own bound methods, a closure stored
+// in a variable it captures, a list that contains itself. Every function,
+// class, instance, list and map is registered here when it's created, as
+// is every scope a function closes over, and a collection finds the ones
+// only reachable from each other.
+//
+// The collector never needs to know the roots. For each tracked object it
+// counts the references held by other tracked objects; an object with more
+// strong references than that is referenced from outside the heap (the
+// interpreter, a value in the middle of being evaluated, a native, the
+// embedder) and is live, as is everything reachable from it. What's left is
+// garbage, and is emptied to break its cycles, which lets reference counting
+// free it.
+//
+// References the collector can't see into, such as those captured by a
+// native function or held by a native class, look external, so their
+// targets are kept. That errs towards leaking, never towards freeing
+// something that's still used.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::mem;
+use std::rc::{Rc, Weak};
+
+use crate::class::{LoxClass, LoxInstance};
+use crate::environment::Environment;
+use crate::function::LoxFunction;
+use crate::map::{Key, LoxMap};
+use crate::value::Value;
+
+// Objects allocated between collections, at least
+const MIN_THRESHOLD: usize = 1_000;
+
+// How the interpreter schedules collections
+#[derive(Debug, Clone)]
+pub struct GcOptions {
+    // Collect whenever anything has been allocated since the last
+    // collection, to shake out bugs that depend on when collections happen
+    pub stress: bool,
+    // The next collection comes once this many times the objects that
+    // survived the last one have been allocated
+    pub growth_factor: f64,
+}
+
+impl Default for GcOptions {
+    fn default() -> Self {
+        GcOptions {
+            stress: false,
+            growth_factor: 2.0,
+        }
+    }
+}
+
+// Totals over every collection an interpreter has run
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GcStats {
+    pub collections: usize,
+    // Objects found unreachable
+    pub freed: usize,
+    // Objects still alive after the last collection
+    pub live: usize,
+}
+
+enum Object {
+    Environment(Weak<RefCell<Environment>>),
+    Function(Weak<LoxFunction>),
+    Class(Weak<LoxClass>),
+    Instance(Weak<RefCell<LoxInstance>>),
+    List(Weak<RefCell<Vec<Value>>>),
+    Map(Weak<RefCell<LoxMap>>),
+}
+
+// A tracked object kept alive while the collector looks at it
+enum Strong {
+    Environment(Rc<RefCell<Environment>>),
+    Function(Rc<LoxFunction>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<LoxMap>>),
+}
+
+// Objects are tracked per thread, since values can't leave the thread they
+// were made on. Interpreters on the same thread share the heap.
+struct Heap {
+    objects: RefCell<Vec<Object>>,
+    // Objects registered since the last collection
+    allocated: Cell<usize>,
+    threshold: Cell<usize>,
+}
+
+thread_local! {
+    static HEAP: Heap = const { Heap {
+        objects: RefCell::new(Vec::new()),
+        allocated: Cell::new(0),
+        threshold: Cell::new(MIN_THRESHOLD),
+    } };
+}
+
+// A scope tracked from the start, rather than once a function closes over
+// it, so what it holds counts towards `live_bytes`
+pub(crate) fn environment(env: Environment) -> Rc<RefCell<Environment>> {
+    env.tracked.set(true);
+    let env = Rc::new(RefCell::new(env));
+    track(Object::Environment(Rc::downgrade(&env)));
+    env
+}
+
+pub(crate) fn function(function: LoxFunction) -> Rc<LoxFunction> {
+    capture(&function.closure);
+    let function = Rc::new(function);
+    track(Object::Function(Rc::downgrade(&function)));
+    function
+}
+
+pub(crate) fn class(class: LoxClass) -> Rc<LoxClass> {
+    let class = Rc::new(class);
+    track(Object::Class(Rc::downgrade(&class)));
+    class
+}
+
+pub(crate) fn instance(instance: LoxInstance) -> Rc<RefCell<LoxInstance>> {
+    let instance = Rc::new(RefCell::new(instance));
+    track(Object::Instance(Rc::downgrade(&instance)));
+    instance
+}
+
+pub(crate) fn list(values: Vec<Value>) -> Rc<RefCell<Vec<Value>>> {
+    let list = Rc::new(RefCell::new(values));
+    track(Object::List(Rc::downgrade(&list)));
+    list
+}
+
+pub(crate) fn map(map: LoxMap) -> Rc<RefCell<LoxMap>> {
+    let map = Rc::new(RefCell::new(map));
+    track(Object::Map(Rc::downgrade(&map)));
+    map
+}
+
+// Scopes are only tracked once a function closes over them or a scope
+// inside them, since that's the only way they can end up in a cycle. Until
+// then they're only referenced by the interpreter, and by scopes inside
+// them, and reference counting alone frees them.
+fn capture(env: &Rc<RefCell<Environment>>) {
+    let mut env = Rc::clone(env);
+    loop {
+        let enclosing = {
+            // One that's being changed is left untracked, which can only
+            // keep it alive for longer
+            let scope = match env.try_borrow() {
+                Ok(scope) => scope,
+                Err(_) => return,
+            };
+            if scope.tracked.replace(true) {
+                return;
+            }
+            track(Object::Environment(Rc::downgrade(&env)));
+            scope.enclosing().cloned()
+        };
+        match enclosing {
+            Some(enclosing) => env = enclosing,
+            None => return,
+        }
+    }
+}
+
+fn track(object: Object) {
+    HEAP.with(|heap| {
+        heap.objects.borrow_mut().push(object);
+        heap.allocated.set(heap.allocated.get() + 1);
+    });
+}
+
+// Whether enough has been allocated to be worth collecting
+pub(crate) fn due(options: &GcOptions) -> bool {
+    HEAP.with(|heap| {
+        let allocated = heap.allocated.get();
+        allocated > 0 && (options.stress || allocated >= heap.threshold.get())
+    })
+}
+
+// Free the objects only reachable from each other. Returns how many were
+// freed and how many are still alive.
+pub(crate) fn collect(options: &GcOptions) -> (usize, usize) {
+    let mut objects = HEAP.with(|heap| heap.objects.take());
+    objects.retain(|object| object.strong_count() > 0);
+
+    let index: HashMap<usize, usize> = objects
+        .iter()
+        .enumerate()
+        .map(|(i, object)| (object.address(), i))
+        .collect();
+
+    // References from inside the heap, per object. One that can't be looked
+    // into right now because it's being changed is treated as live.
+    let mut internal = vec![0; objects.len()];
+    let mut live = vec![false; objects.len()];
+    let counts: Vec<usize> = objects.iter().map(Object::strong_count).collect();
+    for (i, object) in objects.iter().enumerate() {
+        let visited = object.upgrade().is_some_and(|strong| {
+            strong.children(&mut |address| {
+                if let Some(&j) = index.get(&address) {
+                    internal[j] += 1;
+                }
+            })
+        });
+        if !visited {
+            live[i] = true;
+        }
+    }
+
+    let mut pending: Vec<usize> = (0..objects.len())
+        .filter(|&i| live[i] || counts[i] > internal[i])
+        .collect();
+    for &i in &pending {
+        live[i] = true;
+    }
+    while let Some(i) = pending.pop() {
+        if let Some(strong) = objects[i].upgrade() {
+            strong.children(&mut |address| {
+                if let Some(&j) = index.get(&address) {
+                    if !live[j] {
+                        live[j] = true;
+                        pending.push(j);
+                    }
+                }
+            });
+        }
+    }
+
+    // Hold on to all the garbage while it's emptied, so none of it is freed
+    // halfway through
+    let garbage: Vec<Strong> = objects
+        .iter()
+        .zip(&live)
+        .filter(|(_, &live)| !live)
+        .filter_map(|(object, _)| object.upgrade())
+        .collect();
+    let freed = garbage.len();
+    garbage.iter().for_each(Strong::empty);
+    drop(garbage);
+
+    objects.retain(|object| object.strong_count() > 0);
+    let survivors = objects.len();
+    HEAP.with(|heap| {
+        // Anything registered while the garbage was dropped
+        let mut registered = heap.objects.replace(Vec::new());
+        objects.append(&mut registered);
+        heap.objects.replace(objects);
+        heap.allocated.set(0);
+        let threshold = (survivors as f64 * options.growth_factor) as usize;
+        heap.threshold.set(threshold.max(MIN_THRESHOLD));
+    });
+
+    (freed, survivors)
+}
+
+// Roughly how much memory the tracked objects still alive take up,
+// including the strings they hold. Collect first to leave out cycles.
+pub(crate) fn live_bytes() -> usize {
+    HEAP.with(|heap| {
+        heap.objects
+            .borrow()
+            .iter()
+            .filter_map(Object::upgrade)
+            .map(|strong| strong.bytes())
+            .sum()
+    })
+}
+
+impl Object {
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Environment(weak) => weak.strong_count(),
+            Object::Function(weak) => weak.strong_count(),
+            Object::Class(weak) => weak.strong_count(),
+            Object::Instance(weak) => weak.strong_count(),
+            Object::List(weak) => weak.strong_count(),
+            Object::Map(weak) => weak.strong_count(),
+        }
+    }
+
+    fn address(&self) -> usize {
+        match self {
+            Object::Environment(weak) => weak.as_ptr() as *const () as usize,
+            Object::Function(weak) => weak.as_ptr() as *const () as usize,
+            Object::Class(weak) => weak.as_ptr() as *const () as usize,
+            Object::Instance(weak) => weak.as_ptr() as *const () as usize,
+            Object::List(weak) => weak.as_ptr() as *const () as usize,
+            Object::Map(weak) => weak.as_ptr() as *const () as usize,
+        }
+    }
+
+    fn upgrade(&self) -> Option<Strong> {
+        Some(match self {
+            Object::Environment(weak) => Strong::Environment(weak.upgrade()?),
+            Object::Function(weak) => Strong::Function(weak.upgrade()?),
+            Object::Class(weak) => Strong::Class(weak.upgrade()?),
+            Object::Instance(weak) => Strong::Instance(weak.upgrade()?),
+            Object::List(weak) => Strong::List(weak.upgrade()?),
+            Object::Map(weak) => Strong::Map(weak.upgrade()?),
+        })
+    }
+}
+
+impl Strong {
+    // Calls `visit` with the address of every object this one references.
+    // False if it's borrowed mutably and can't be looked into.
+    fn children(&self, visit: &mut dyn FnMut(usize)) -> bool {
+        match self {
+            Strong::Environment(env) => match env.try_borrow() {
+                Ok(env) => {
+                    env.values()
+                        .for_each(|(_, value)| value_child(value, visit));
+                    if let Some(enclosing) = env.enclosing() {
+                        visit(address(enclosing));
+                    }
+                    true
+                }
+                Err(_) => false,
+            },
+            Strong::Function(function) => {
+                visit(address(&function.closure));
+                true
+            }
+            Strong::Class(class) => {
+                if let Some(superclass) = &class.superclass {
+                    visit(address(superclass));
+                }
+                class
+                    .methods
+                    .values()
+                    .for_each(|method| visit(address(method)));
+                true
+            }
+            Strong::Instance(instance) => match instance.try_borrow() {
+                Ok(instance) => {
+                    visit(address(&instance.class));
+                    instance
+                        .fields
+                        .values()
+                        .for_each(|value| value_child(value, visit));
+                    true
+                }
+                Err(_) => false,
+            },
+            Strong::List(list) => match list.try_borrow() {
+                Ok(list) => {
+                    list.iter().for_each(|value| value_child(value, visit));
+                    true
+                }
+                Err(_) => false,
+            },
+            Strong::Map(map) => match map.try_borrow() {
+                Ok(map) => {
+                    map.iter().for_each(|(_, value)| value_child(value, visit));
+                    true
+                }
+                Err(_) => false,
+            },
+        }
+    }
+
+    // One that's being changed counts as empty
+    fn bytes(&self) -> usize {
+        let entry = |name: &String, value: &Value| {
+            mem::size_of::<(String, Value)>() + name.len() + value_bytes(value)
+        };
+        match self {
+            Strong::Environment(env) => env.try_borrow().map_or(0, |env| {
+                let values: usize = env.values().map(|(name, value)| entry(name, value)).sum();
+                mem::size_of::<Environment>() + values
+            }),
+            Strong::Function(_) => mem::size_of::<LoxFunction>(),
+            Strong::Class(class) => {
+                let method = mem::size_of::<(String, Rc<LoxFunction>)>();
+                mem::size_of::<LoxClass>() + method * class.methods.len()
+            }
+            Strong::Instance(instance) => instance.try_borrow().map_or(0, |instance| {
+                let fields: usize = instance.fields.iter().map(|(n, v)| entry(n, v)).sum();
+                mem::size_of::<LoxInstance>() + fields
+            }),
+            Strong::List(list) => list.try_borrow().map_or(0, |list| {
+                list.iter()
+                    .map(|value| mem::size_of::<Value>() + value_bytes(value))
+                    .sum()
+            }),
+            Strong::Map(map) => map.try_borrow().map_or(0, |map| {
+                map.iter()
+                    .map(|(key, value)| {
+                        let key = match key {
+                            Key::Str(s) => s.len(),
+                            _ => 0,
+                        };
+                        mem::size_of::<(Key, Value)>() + key + value_bytes(value)
+                    })
+                    .sum()
+            }),
+        }
+    }
+
+    // Functions and classes can't be changed, but every cycle runs through
+    // a scope, an instance, a list or a map, and emptying those is enough.
+    // The contents are dropped after the borrow ends.
+    fn empty(&self) {
+        match self {
+            Strong::Environment(env) => {
+                let taken = env.try_borrow_mut().map(|mut env| mem::take(&mut *env));
+                drop(taken);
+            }
+            Strong::Instance(instance) => {
+                let taken = instance
+                    .try_borrow_mut()
+                    .map(|mut instance| mem::take(&mut instance.fields));
+                drop(taken);
+            }
+            Strong::List(list) => {
+                let taken = list.try_borrow_mut().map(|mut list| mem::take(&mut *list));
+                drop(taken);
+            }
+            Strong::Map(map) => {
+                let taken = map.try_borrow_mut().map(|mut map| mem::take(&mut *map));
+                drop(taken);
+            }
+            Strong::Function(_) | Strong::Class(_) => {}
+        }
+    }
+}
+
+fn value_child(value: &Value, visit: &mut dyn FnMut(usize)) {
+    match value {
+        Value::Function(function) => visit(address(function)),
+        Value::Class(class) => visit(address(class)),
+        Value::Instance(instance) => visit(address(instance)),
+        Value::List(list) => visit(address(list)),
+        Value::Map(map) => visit(address(map)),
+        Value::Nil
+        | Value::Bool(_)
+        | Value::Number(_)
+        | Value::Str(_)
+        | Value::Native(_)
+        | Value::Range(_) => {}
+    }
+}
+
+// What a value owns beyond its own size
+fn value_bytes(value: &Value) -> usize {
+    match value {
+        Value::Str(s) => s.len(),
+        _ => 0,
+    }
+}
+
+fn address<T>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc) as *const () as usize
+}
@@ -0,0 +1,371 @@
+// Syntax highlighting that knows what names mean: beyond what the scanner
+// can tell, identifiers are classified by the declaration they resolve to,
+// so a parameter, a local and a global look different.
+//
+// When the source doesn't parse, identifiers get what the neighbouring
+// tokens say about them, and the rest is classified as usual.
+
+use std::collections::HashSet;
+
+use crate::ast::Expr;
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use crate::symbols::{Position, SymbolKind, Symbols};
+use crate::token::{LiteralKind, Token, TokenType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Highlight {
+    Keyword,
+    Number,
+    String,
+    Comment,
+    Function,
+    Parameter,
+    Local,
+    Global,
+    Class,
+    Method,
+    Property,
+    // `this` and `super`
+    This,
+}
+
+impl Highlight {
+    pub const ALL: [Highlight; 12] = [
+        Highlight::Keyword,
+        Highlight::Number,
+        Highlight::String,
+        Highlight::Comment,
+        Highlight::Function,
+        Highlight::Parameter,
+        Highlight::Local,
+        Highlight::Global,
+        Highlight::Class,
+        Highlight::Method,
+        Highlight::Property,
+        Highlight::This,
+    ];
+
+    // Also its CSS class in `html` output
+    pub fn name(self) -> &'static str {
+        match self {
+            Highlight::Keyword => "keyword",
+            Highlight::Number => "number",
+            Highlight::String => "string",
+            Highlight::Comment => "comment",
+            Highlight::Function => "function",
+            Highlight::Parameter => "parameter",
+            Highlight::Local => "local",
+            Highlight::Global => "global",
+            Highlight::Class => "class",
+            Highlight::Method => "method",
+            Highlight::Property => "property",
+            Highlight::This => "this",
+        }
+    }
+
+    // SGR parameters for `ansi` output
+    fn ansi(self) -> &'static str {
+        match self {
+            Highlight::Keyword => "35",
+            Highlight::Number => "33",
+            Highlight::String => "32",
+            Highlight::Comment => "90",
+            Highlight::Function => "1;34",
+            Highlight::Parameter => "3",
+            Highlight::Local => "31",
+            Highlight::Global => "1",
+            Highlight::Class => "1;36",
+            Highlight::Method => "34",
+            Highlight::Property => "36",
+            Highlight::This => "3;35",
+        }
+    }
+}
+
+// A classified stretch of source, as byte offsets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub highlight: Highlight,
+    pub start: usize,
+    pub end: usize,
+}
+
+// The spans of `src` worth highlighting, in order. Punctuation, operators
+// and anything that didn't scan are left out.
+pub fn classify(src: &str) -> Vec<Span> {
+    let mut scanner = Scanner::new(src);
+    let tokens = scanner.scan_tokens().clone();
+    let program = Parser::new(tokens.clone()).parse().ok();
+    let symbols = program.as_ref().map(Symbols::analyze);
+    // The method in `super.method` is one even when the superclass isn't
+    // declared in the file
+    let super_methods: HashSet<Position> = program
+        .iter()
+        .flat_map(|program| program.exprs.iter())
+        .filter_map(|(_, expr)| match expr {
+            Expr::Super { method, .. } => Some(Position::of(method)),
+            _ => None,
+        })
+        .collect();
+
+    let mut spans = Vec::new();
+    let mut comments = scanner.comments().iter().peekable();
+    for (i, token) in tokens.iter().enumerate() {
+        while let Some(comment) = comments.next_if(|c| c.offset < token.offset) {
+            spans.push(Span {
+                highlight: Highlight::Comment,
+                start: comment.offset,
+                end: comment.offset + comment.text.len(),
+            });
+        }
+
+        let previous = i.checked_sub(1).map(|i| &tokens[i].kind);
+        let highlight = if super_methods.contains(&Position::of(token)) {
+            Some(Highlight::Method)
+        } else {
+            classify_token(token, previous, symbols.as_ref())
+        };
+        if let Some(highlight) = highlight {
+            spans.push(Span {
+                highlight,
+                start: token.offset,
+                end: token.offset + token.len,
+            });
+        }
+    }
+    spans
+}
+
+fn classify_token(
+    token: &Token,
+    previous: Option<&TokenType>,
+    symbols: Option<&Symbols>,
+) -> Option<Highlight> {
+    Some(match &token.kind {
+        TokenType::Literal(LiteralKind::Number(_)) => Highlight::Number,
+        TokenType::Literal(LiteralKind::Str(_)) => Highlight::String,
+        TokenType::Literal(LiteralKind::Identifier(_)) => {
+            let symbol = symbols.and_then(|s| s.at(Position::of(token)));
+            match (symbol, previous) {
+                (Some(symbol), _) => match symbol.kind {
+                    SymbolKind::Function => Highlight::Function,
+                    SymbolKind::Parameter => Highlight::Parameter,
+                    SymbolKind::Class => Highlight::Class,
+                    SymbolKind::Method => Highlight::Method,
+                    SymbolKind::Variable if symbol.global => Highlight::Global,
+                    SymbolKind::Variable => Highlight::Local,
+                },
+                (None, Some(TokenType::Dot)) => Highlight::Property,
+                // Natives, and names nothing declares
+                (None, _) if symbols.is_some() => Highlight::Global,
+                (None, Some(TokenType::Fun)) => Highlight::Function,
+                (None, Some(TokenType::Class)) | (None, Some(TokenType::Less)) => Highlight::Class,
+                (None, _) => return None,
+            }
+        }
+        TokenType::This | TokenType::Super => Highlight::This,
+        TokenType::And
+        | TokenType::Break
+        | TokenType::Class
+        | TokenType::Continue
+        | TokenType::Else
+        | TokenType::False
+        | TokenType::For
+        | TokenType::Fun
+        | TokenType::If
+        | TokenType::In
+        | TokenType::Nil
+        | TokenType::Or
+        | TokenType::Print
+        | TokenType::Return
+        | TokenType::True
+        | TokenType::Var
+        | TokenType::While => Highlight::Keyword,
+        _ => return None,
+    })
+}
+
+// `src` with each span wrapped in an SGR escape, for a terminal
+pub fn ansi(src: &str) -> String {
+    let mut out = String::new();
+    let mut pos = 0;
+    for span in classify(src) {
+        out.push_str(&src[pos..span.start]);
+        out.push_str(&format!(
+            "\x1b[{}m{}\x1b[0m",
+            span.highlight.ansi(),
+            &src[span.start..span.end]
+        ));
+        pos = span.end;
+    }
+    out.push_str(&src[pos..]);
+    out
+}
+
+const STYLE: &str = "\
+body { margin: 0; background: #fdfdfd; }
+pre.lox { margin: 0; padding: 1em; color: #24292e; font: 14px/1.4 monospace; }
+.keyword { color: #a626a4; }
+.number { color: #b76b01; }
+.string { color: #50a14f; }
+.comment { color: #a0a1a7; font-style: italic; }
+.function { color: #4078f2; font-weight: bold; }
+.parameter { font-style: italic; }
+.local { color: #e45649; }
+.global { font-weight: bold; }
+.class { color: #0184bc; font-weight: bold; }
+.method { color: #4078f2; }
+.property { color: #0184bc; }
+.this { color: #a626a4; font-style: italic; }";
+
+// A standalone HTML page showing `src`, with a `<span>` classed by name
+// around each span
+pub fn html(src: &str, title: &str) -> String {
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str(&format!("<title>{}</title>\n", escape(title)));
+    out.push_str(&format!("<style>\n{}\n</style>\n", STYLE));
+    out.push_str("</head>\n<body>\n<pre class=\"lox\">");
+
+    let mut pos = 0;
+    for span in classify(src) {
+        out.push_str(&escape(&src[pos..span.start]));
+        out.push_str(&format!(
+            "<span class=\"{}\">{}</span>",
+            span.highlight.name(),
+            escape(&src[span.start..span.end])
+        ));
+        pos = span.end;
+    }
+    out.push_str(&escape(&src[pos..]));
+
+    out.push_str("</pre>\n</body>\n</html>\n");
+    out
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each span as its text and highlight name
+    fn spans(src: &str) -> Vec<(&str, &'static str)> {
+        classify(src)
+            .into_iter()
+            .map(|s| (&src[s.start..s.end], s.highlight.name()))
+            .collect()
+    }
+
+    #[test]
+    fn test_classify() {
+        let src = concat!(
+            "var n = 1; // one\n",
+            "class A < B { go(x) { var y = x; this.go(clock()); return super.f; } }\n",
+            "fun f() { return \"s\" + n.size; }\n",
+        );
+        assert_eq!(
+            spans(src),
+            [
+                ("var", "keyword"),
+                ("n", "global"),
+                ("1", "number"),
+                ("// one", "comment"),
+                ("class", "keyword"),
+                ("A", "class"),
+                ("B", "global"),
+                ("go", "method"),
+                ("x", "parameter"),
+                ("var", "keyword"),
+                ("y", "local"),
+                ("x", "parameter"),
+                ("this", "this"),
+                ("go", "method"),
+                ("clock", "global"),
+                ("return", "keyword"),
+                ("super", "this"),
+                ("f", "method"),
+                ("fun", "keyword"),
+                ("f", "function"),
+                ("return", "keyword"),
+                ("\"s\"", "string"),
+                ("n", "global"),
+                ("size", "property"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_super() {
+        assert_eq!(
+            spans("class A { f() {} }\nclass B < A { f() { super.f(); } }"),
+            [
+                ("class", "keyword"),
+                ("A", "class"),
+                ("f", "method"),
+                ("class", "keyword"),
+                ("B", "class"),
+                ("A", "class"),
+                ("f", "method"),
+                ("super", "this"),
+                ("f", "method"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unparsed() {
+        assert_eq!(
+            spans("fun f( { a.b /* c */"),
+            [
+                ("fun", "keyword"),
+                ("f", "function"),
+                ("b", "property"),
+                ("/* c */", "comment"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_output() {
+        let src = "print a < \"<é>\";";
+        assert_eq!(
+            ansi(src),
+            "\x1b[35mprint\x1b[0m \x1b[1ma\x1b[0m < \x1b[32m\"<é>\"\x1b[0m;"
+        );
+        let html = html(src, "a&b");
+        assert!(html.contains("<title>a&amp;b</title>"));
+        assert!(html.contains(concat!(
+            "<pre class=\"lox\"><span class=\"keyword\">print</span> ",
+            "<span class=\"global\">a</span> &lt; ",
+            "<span class=\"string\">&quot;&lt;é&gt;&quot;</span>;</pre>"
+        )));
+    }
+
+    #[test]
+    fn test_styles() {
+        for highlight in &Highlight::ALL {
+            let rule = format!("\n.{} {{", highlight.name());
+            assert!(STYLE.contains(&rule), "no CSS for {:?}", highlight);
+        }
+
+        let src = "{ var x; x; }";
+        assert!(ansi(src).contains("\x1b[31mx\x1b[0m; \x1b[31mx\x1b[0m;"));
+        let html = html(src, "locals");
+        assert!(html.contains(".local { color: #e45649; }"));
+        assert!(html.contains("<span class=\"local\">x</span>; <span class=\"local\">x</span>;"));
+    }
+}
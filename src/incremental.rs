@@ -0,0 +1,632 @@
+// Keeping a script scanned and parsed while it's edited, for editors.
+//
+// An edit rescans from the last token that can't have been affected and
+// stops as soon as the new tokens line up with the old ones again, which
+// is as soon as a token starts at the same place in the unchanged text
+// after the edit. The tokens from there on are kept and moved.
+//
+// Parsing works a top-level declaration at a time. Declarations before the
+// rescanned tokens are kept as they are. Parsing restarts at the first one
+// that touches them and stops at the first declaration boundary in the
+// kept tokens, and the declarations from there on are kept and moved.
+//
+// So an edit costs about as much as the stretch of tokens it changes. Most
+// edits change a token or two, but one that opens or closes a string or
+// block comment changes how everything after it scans. Once rescanning
+// spreads that far, the document is scanned and parsed from scratch
+// instead, which costs less than splicing and reparsing to the end.
+
+use std::mem;
+use std::ops::Range;
+
+use crate::ast::{Expr, ExprArena, ExprId, Function, Program, Stmt};
+use crate::error::SyntaxError;
+use crate::parser::Parser;
+use crate::scanner::{Comment, Scanner};
+use crate::token::{Token, TokenType};
+use crate::visit::{walk_expr_mut, walk_function_mut, walk_program_mut, walk_stmt_mut, VisitorMut};
+
+// Bytes an edit may rescan past itself before the document starts over:
+// the length of the source over `RESCAN_SHARE`, and at least `MIN_RESCAN`,
+// as small scripts are never worth starting over for
+const RESCAN_SHARE: usize = 2;
+const MIN_RESCAN: usize = 16 * 1024;
+
+// Replaces a byte range of the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Document {
+    src: String,
+    tokens: Vec<Token>,
+    comments: Vec<Comment>,
+    declarations: Vec<Declaration>,
+}
+
+#[derive(Debug, Clone)]
+struct Declaration {
+    // Byte offset and line of its first token
+    start: usize,
+    line: u32,
+    parsed: Result<Program, Vec<SyntaxError>>,
+    // Lines and bytes its tree has yet to be moved by. Moving every tree
+    // after an edit would take most of the time an edit does, so it waits
+    // until the tree is asked for.
+    pending: Option<Shift>,
+}
+
+impl Document {
+    pub fn new(src: &str) -> Document {
+        let mut scanner = Scanner::new(src);
+        let tokens = scanner.scan_tokens().clone();
+        let mut document = Document {
+            src: src.to_string(),
+            tokens,
+            comments: scanner.comments().to_vec(),
+            declarations: Vec::new(),
+        };
+        document.reparse(0, None);
+        document
+    }
+
+    pub fn source(&self) -> &str {
+        &self.src
+    }
+
+    // As `Scanner::scan_tokens` would return them for the current source
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
+    }
+
+    // Each top-level declaration that parsed, as a program of its own, in
+    // order
+    pub fn programs(&mut self) -> impl Iterator<Item = &Program> {
+        for declaration in &mut self.declarations {
+            declaration.catch_up();
+        }
+        self.declarations
+            .iter()
+            .filter_map(|d| d.parsed.as_ref().ok())
+    }
+
+    // Everything wrong with the source, as `Parser::parse` would report it
+    pub fn errors(&self) -> Vec<SyntaxError> {
+        let mut errors: Vec<SyntaxError> = self
+            .tokens
+            .iter()
+            .filter_map(|t| match &t.kind {
+                TokenType::Error(message) => Some(SyntaxError::new(t.line, "", message)),
+                _ => None,
+            })
+            .collect();
+        for declaration in &self.declarations {
+            if let Err(e) = &declaration.parsed {
+                errors.extend(e.iter().cloned());
+            }
+        }
+        errors.sort_by_key(|e| e.line);
+        errors
+    }
+
+    // Panics if the range isn't within the source, on character boundaries.
+    // Takes time in proportion to how far the change to the tokens reaches,
+    // up to what starting over costs. Tokens after the change are moved one
+    // by one, so edits near the start of a long script cost a little more.
+    pub fn edit(&mut self, edit: &Edit) {
+        self.src.replace_range(edit.range.clone(), &edit.text);
+        let bytes = edit.text.len() as isize - edit.range.len() as isize;
+        match self.relex(edit, bytes) {
+            Some((restart, resync)) => self.reparse(restart, resync),
+            None => *self = Document::new(&mem::take(&mut self.src)),
+        }
+    }
+
+    // Rescans what the edit may have changed. Returns the offset rescanning
+    // started from, and where in the old source the old tokens were picked
+    // up again with how to move them; or `None`, having changed nothing but
+    // the source, when the change spreads too far to be worth following.
+    fn relex(&mut self, edit: &Edit, bytes: isize) -> Option<(usize, Option<(usize, Shift)>)> {
+        // Restart a token before the first one the edit touches, as how a
+        // token scans depends on the two characters after it. Strings over
+        // several lines don't know their column, so can't be restarted at.
+        let touched = self
+            .tokens
+            .partition_point(|t| t.offset + t.len < edit.range.start);
+        let restart = (0..touched).rev().find(|&i| self.tokens[i].column != 0);
+        let (index, mut scanner) = match restart {
+            Some(i) => {
+                let t = &self.tokens[i];
+                (i, Scanner::resume(&self.src, t.offset, t.line, t.column))
+            }
+            None => (0, Scanner::new(&self.src)),
+        };
+        let offset = restart.map_or(0, |i| self.tokens[i].offset);
+
+        let edited_end = edit.range.start + edit.text.len();
+        let rescan_limit = edited_end + (self.src.len() / RESCAN_SHARE).max(MIN_RESCAN);
+        let mut scanned = Vec::new();
+        let mut resync = None;
+        loop {
+            let token = scanner.scan_token().clone();
+            if token.offset + token.len > rescan_limit {
+                return None;
+            }
+            if token.kind == TokenType::EOF {
+                scanned.push(token);
+                break;
+            }
+            if token.offset >= edited_end {
+                let old_offset = (token.offset as isize - bytes) as usize;
+                let j = self.tokens.partition_point(|t| t.offset < old_offset);
+                if let Some(old) = self.tokens.get(j) {
+                    if old.offset == old_offset && old.len == token.len && old.kind == token.kind {
+                        let shift = Shift {
+                            line: old.line,
+                            lines: i64::from(token.line) - i64::from(old.line),
+                            columns: i64::from(token.column) - i64::from(old.column),
+                            bytes,
+                        };
+                        resync = Some((j, shift));
+                        break;
+                    }
+                }
+            }
+            scanned.push(token);
+        }
+
+        // Splice the new tokens and comments in, moving the ones after
+        let kept = resync.as_ref().map_or(self.tokens.len(), |(j, _)| *j);
+        let resync_offset = resync.as_ref().map(|(j, _)| self.tokens[*j].offset);
+        let added = scanned.len() as isize - (kept - index) as isize;
+        if let Some((_, shift)) = &resync {
+            for token in &mut self.tokens[kept..] {
+                shift.token(token);
+            }
+        }
+        self.tokens.splice(index..kept, scanned);
+
+        let first = self.comments.partition_point(|c| c.offset < offset);
+        let last = resync_offset.map_or(self.comments.len(), |o| {
+            self.comments.partition_point(|c| c.offset < o)
+        });
+        if let Some((_, shift)) = &resync {
+            for comment in &mut self.comments[last..] {
+                shift.comment(comment, added);
+            }
+        }
+        let comments = scanner.comments().iter().cloned().map(|mut c| {
+            c.token_index += index;
+            c
+        });
+        self.comments.splice(first..last, comments);
+
+        Some((offset, resync_offset.zip(resync.map(|(_, shift)| shift))))
+    }
+
+    // Reparses the declarations that rescanning from `restart` may have
+    // changed. With `resync`, tokens from that offset in the old source on
+    // are unchanged but for being moved by the shift.
+    fn reparse(&mut self, restart: usize, resync: Option<(usize, Shift)>) {
+        // A declaration is kept when its tokens, and the one after that
+        // parsing looked at, come before the rescanned ones
+        let first = self
+            .declarations
+            .partition_point(|d| d.start < restart)
+            .saturating_sub(1);
+        let start = match self.declarations.get(first) {
+            Some(d) if d.start < restart => d.start,
+            _ => 0,
+        };
+        let start_token = self.tokens.partition_point(|t| t.offset < start);
+
+        // The first declaration that might be kept after the rescanned tokens
+        let reusable = match &resync {
+            Some((offset, _)) => self
+                .declarations
+                .partition_point(|d| d.start < *offset)
+                .max(first + 1),
+            None => self.declarations.len(),
+        };
+        let moved = |d: &Declaration| match &resync {
+            Some((_, shift)) => (d.start as isize + shift.bytes) as usize,
+            None => d.start,
+        };
+
+        let mut candidate = reusable;
+        loop {
+            // Parse up to the candidate's first token, or to the end
+            let end_token = match self.declarations.get(candidate) {
+                Some(d) => self.tokens.partition_point(|t| t.offset < moved(d)),
+                None => self.tokens.len() - 1,
+            };
+            let mut starts = Vec::new();
+            let mut tokens = Vec::new();
+            for token in &self.tokens[start_token..=end_token] {
+                if !token.is_error() {
+                    starts.push((token.offset, token.line));
+                    tokens.push(token.clone());
+                }
+            }
+            let stop = tokens.len() - 1;
+            if candidate < self.declarations.len() {
+                let last = &self.tokens[end_token];
+                let mut eof = Token::new(TokenType::EOF, last.line);
+                eof.offset = last.offset;
+                tokens.push(eof);
+            }
+
+            let mut parser = Parser::new(tokens);
+            let mut parsed = Vec::new();
+            while parser.position() < stop {
+                let (start, line) = starts[parser.position()];
+                match parser.parse_declaration() {
+                    Some(result) => parsed.push(Declaration {
+                        start,
+                        line,
+                        parsed: result,
+                        pending: None,
+                    }),
+                    None => break,
+                }
+            }
+
+            if parser.position() == stop || candidate == self.declarations.len() {
+                if let Some((_, shift)) = &resync {
+                    for declaration in &mut self.declarations[candidate..] {
+                        shift.declaration(declaration);
+                    }
+                }
+                self.declarations.splice(first..candidate, parsed);
+                return;
+            }
+
+            // The last declaration ran on into the candidate; try one further
+            // along, twice as far each time
+            candidate = (candidate + (candidate - reusable) + 1).min(self.declarations.len());
+        }
+    }
+}
+
+// How to move what comes after an edit to where the edit left it
+#[derive(Debug, Clone, Copy)]
+struct Shift {
+    // The line the first moved token was on, where columns change too
+    line: u32,
+    lines: i64,
+    columns: i64,
+    bytes: isize,
+}
+
+impl Shift {
+    fn line(&self, line: u32) -> u32 {
+        (i64::from(line) + self.lines) as u32
+    }
+
+    fn token(&self, token: &mut Token) {
+        // Strings over several lines have no column to move
+        if token.line == self.line && token.column != 0 {
+            token.column = (i64::from(token.column) + self.columns) as u32;
+        }
+        token.line = self.line(token.line);
+        token.offset = (token.offset as isize + self.bytes) as usize;
+    }
+
+    fn comment(&self, comment: &mut Comment, tokens: isize) {
+        comment.line = self.line(comment.line);
+        comment.end_line = self.line(comment.end_line);
+        comment.offset = (comment.offset as isize + self.bytes) as usize;
+        comment.token_index = (comment.token_index as isize + tokens) as usize;
+    }
+
+    fn declaration(&self, declaration: &mut Declaration) {
+        let columns = declaration.line == self.line;
+        declaration.start = (declaration.start as isize + self.bytes) as usize;
+        declaration.line = self.line(declaration.line);
+        match &mut declaration.parsed {
+            Ok(_) if columns => {
+                declaration.catch_up();
+                declaration.walk(*self);
+            }
+            // It starts after the line whose columns change, so only lines
+            // and bytes move, and those add up. No token is on line 0.
+            Ok(_) => {
+                let pending = declaration.pending.get_or_insert(Shift {
+                    line: 0,
+                    lines: 0,
+                    columns: 0,
+                    bytes: 0,
+                });
+                pending.lines += self.lines;
+                pending.bytes += self.bytes;
+            }
+            Err(errors) => {
+                for error in errors {
+                    error.line = self.line(error.line);
+                }
+            }
+        }
+    }
+}
+
+impl Declaration {
+    fn catch_up(&mut self) {
+        if let Some(shift) = self.pending.take() {
+            self.walk(shift);
+        }
+    }
+
+    fn walk(&mut self, shift: Shift) {
+        if let Ok(program) = &mut self.parsed {
+            walk_program_mut(&mut ShiftTokens(shift), program);
+        }
+    }
+}
+
+// Applies a `Shift` to every token in a syntax tree
+struct ShiftTokens(Shift);
+
+impl VisitorMut for ShiftTokens {
+    fn visit_stmt_mut(&mut self, exprs: &mut ExprArena, stmt: &mut Stmt) {
+        match stmt {
+            Stmt::Block { brace, .. } => self.0.token(brace),
+            Stmt::Class { name, .. } | Stmt::Var { name, .. } => self.0.token(name),
+            Stmt::Break { keyword }
+            | Stmt::Continue { keyword }
+            | Stmt::For { keyword, .. }
+            | Stmt::Return { keyword, .. } => self.0.token(keyword),
+            Stmt::ForIn { keyword, name, .. } => {
+                self.0.token(keyword);
+                self.0.token(name);
+            }
+            _ => {}
+        }
+        walk_stmt_mut(self, exprs, stmt)
+    }
+
+    fn visit_expr_mut(&mut self, exprs: &mut ExprArena, id: ExprId) {
+        match &mut exprs[id] {
+            Expr::Binary { operator, .. }
+            | Expr::Logical { operator, .. }
+            | Expr::Unary { operator, .. } => self.0.token(operator),
+            Expr::Assign { name, .. }
+            | Expr::Get { name, .. }
+            | Expr::Set { name, .. }
+            | Expr::Variable { name } => self.0.token(name),
+            Expr::Call { paren, .. } => self.0.token(paren),
+            Expr::Index { bracket, .. }
+            | Expr::List { bracket, .. }
+            | Expr::SetIndex { bracket, .. }
+            | Expr::Slice { bracket, .. } => self.0.token(bracket),
+            Expr::Map { brace, .. } => self.0.token(brace),
+            Expr::Lit(token) | Expr::This { keyword: token } => self.0.token(token),
+            Expr::Super { keyword, method } => {
+                self.0.token(keyword);
+                self.0.token(method);
+            }
+            Expr::Grouping { .. } => {}
+        }
+        walk_expr_mut(self, exprs, id)
+    }
+
+    fn visit_function_mut(&mut self, exprs: &mut ExprArena, function: &mut Function) {
+        self.0.token(&mut function.name);
+        for param in &mut function.params {
+            self.0.token(param);
+        }
+        self.0.token(&mut function.brace);
+        walk_function_mut(self, exprs, function)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json;
+
+    const SRC: &str = "\
+// Counts up
+var count = 0;
+fun bump(by) {
+  count = count + by; /* in place */
+  return count;
+}
+
+class Counter {
+  init() { this.n = 0; }
+  up() { this.n = this.n + 1; }
+}
+print bump(2) + 1.5;
+print \"two
+lines\";
+";
+
+    // The document after an edit must be the one scanning and parsing the
+    // edited source from scratch gives
+    fn check(document: &mut Document) {
+        let mut fresh = Document::new(document.source());
+        let positions = |d: &Document| -> Vec<(TokenType, u32, u32, usize, usize)> {
+            d.tokens()
+                .iter()
+                .map(|t| (t.kind.clone(), t.line, t.column, t.offset, t.len))
+                .collect()
+        };
+        assert_eq!(
+            positions(document),
+            positions(&fresh),
+            "{:?}",
+            document.source()
+        );
+        assert_eq!(document.comments(), fresh.comments());
+
+        let programs =
+            |d: &mut Document| -> Vec<String> { d.programs().map(json::program).collect() };
+        assert_eq!(
+            programs(document),
+            programs(&mut fresh),
+            "{:?}",
+            document.source()
+        );
+        let errors =
+            |d: &Document| -> Vec<String> { d.errors().iter().map(|e| e.to_string()).collect() };
+        assert_eq!(errors(document), errors(&fresh), "{:?}", document.source());
+    }
+
+    fn edit(document: &mut Document, find: &str, replacement: &str) {
+        let start = document.source().find(find).unwrap();
+        document.edit(&Edit {
+            range: start..start + find.len(),
+            text: replacement.to_string(),
+        });
+        check(document);
+    }
+
+    #[test]
+    fn test_edits() {
+        let mut document = Document::new(SRC);
+        check(&mut document);
+        assert_eq!(document.programs().count(), 5);
+
+        // Within a token, across tokens and lines, and into a comment
+        edit(&mut document, "count + by", "count + by * 2");
+        edit(&mut document, "bump(2)", "bump(\n  20\n)");
+        edit(&mut document, "in place", "in\nplace");
+        edit(&mut document, "1.5", "1.");
+        edit(&mut document, "1.", "1.25");
+        edit(&mut document, "// Counts up\n", "");
+
+        // Breaking a declaration so it runs on into the next ones, then
+        // mending it
+        edit(&mut document, "return count;\n}", "return count;\n");
+        assert!(!document.errors().is_empty());
+        edit(&mut document, "return count;\n", "return count;\n}");
+        assert!(document.errors().is_empty());
+
+        // Opening a string that swallows the rest, and closing it again
+        edit(&mut document, "class Counter", "\"class Counter");
+        edit(&mut document, "\"class Counter", "class Counter");
+        edit(&mut document, "this.n + 1", "this.n + 1 +");
+        edit(&mut document, "1 +;", "1;");
+        edit(&mut document, "print \"two\nlines\";", "");
+        edit(&mut document, "", "print 0;");
+        assert_eq!(document.programs().count(), 5);
+    }
+
+    #[test]
+    fn test_random_edits() {
+        // Small pieces of Lox, spliced in at random with a fixed seed
+        const PIECES: &[&str] = &[
+            "",
+            "\n",
+            " ",
+            "\"",
+            "/",
+            "*",
+            "/*",
+            "*/",
+            "//",
+            ";",
+            "{",
+            "}",
+            "(",
+            ")",
+            ".",
+            "1",
+            "2.",
+            "x",
+            "if",
+            "else",
+            "fun f() {",
+            "class",
+            "var y = ",
+            "print",
+            "this",
+            "=",
+            "!",
+            "é",
+        ];
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut random = |n: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % n as u64) as usize
+        };
+
+        let mut document = Document::new(SRC);
+        for i in 0..2000 {
+            let src = document.source();
+            let boundaries: Vec<usize> = (0..=src.len())
+                .filter(|&i| src.is_char_boundary(i))
+                .collect();
+            let start = boundaries[random(boundaries.len())];
+            let end = boundaries[(boundaries.iter().position(|&b| b == start).unwrap()
+                + random(4))
+            .min(boundaries.len() - 1)];
+            let text = PIECES[random(PIECES.len())].to_string();
+            document.edit(&Edit {
+                range: start..end,
+                text,
+            });
+            // Not every time, so moves that wait for the trees add up
+            if i % 3 == 0 {
+                check(&mut document);
+            }
+            if document.source().len() > 2 * SRC.len() {
+                document = Document::new(SRC);
+            }
+        }
+    }
+
+    #[test]
+    fn test_keeps_declarations() {
+        let mut document = Document::new(SRC);
+        let before: Vec<*const ExprArena> =
+            document.programs().map(|p| &*p.exprs as *const _).collect();
+        edit(&mut document, "init()", "init(a)");
+        let after: Vec<*const ExprArena> =
+            document.programs().map(|p| &*p.exprs as *const _).collect();
+
+        // Only the class was parsed again
+        let kept: Vec<bool> = before.iter().zip(&after).map(|(b, a)| b == a).collect();
+        assert_eq!(kept, [true, true, false, true, true]);
+    }
+
+    #[test]
+    fn test_starts_over() {
+        let src = SRC.repeat(MIN_RESCAN * RESCAN_SHARE / SRC.len() + 1);
+        let mut document = Document::new(&src);
+        let arenas = |d: &mut Document| -> Vec<*const ExprArena> {
+            d.programs().map(|p| &*p.exprs as *const _).collect()
+        };
+
+        // Near the end the string swallows too little to start over for
+        let at = src.len() - SRC.len();
+        let before = arenas(&mut document);
+        document.edit(&Edit {
+            range: at..at,
+            text: "\"".to_string(),
+        });
+        check(&mut document);
+        assert_eq!(arenas(&mut document)[0], before[0]);
+        document.edit(&Edit {
+            range: at..at + 1,
+            text: String::new(),
+        });
+        check(&mut document);
+
+        // Near the start it swallows everything, and so does closing it
+        let before = arenas(&mut document);
+        edit(&mut document, "class Counter", "\"class Counter");
+        assert_ne!(arenas(&mut document)[0], before[0]);
+        edit(&mut document, "\"class Counter", "class Counter");
+        assert_eq!(document.source(), src);
+    }
+}
@@ -0,0 +1,1581 @@
+// Tree-walking interpreter
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Instant;
+
+use crate::ast::*;
+use crate::capability::{Capabilities, Capability};
+use crate::class::{LoxClass, LoxInstance, NativeClass, NativeConstructor};
+use crate::environment::Environment;
+use crate::error::{Error, Limit, RuntimeError};
+use crate::function::{Arity, LoxFunction, NativeFunction};
+use crate::gc::{self, GcOptions, GcStats};
+use crate::limits::{InterruptHandle, Limits};
+use crate::list;
+use crate::loxc;
+use crate::map::{self, Key, LoxMap};
+use crate::natives;
+use crate::parser::Parser;
+use crate::resolver::Locals;
+use crate::scanner::Scanner;
+use crate::token::*;
+use crate::value::{FromValue, IntoValue, Range, Value};
+
+type EvalResult<T> = Result<T, RuntimeError>;
+
+// Where a for-in loop gets its values from
+enum Iteration {
+    // A snapshot taken as the loop starts
+    Values(std::vec::IntoIter<Value>),
+    // An object with `next()`, and maybe `done`
+    Iterator(Rc<RefCell<LoxInstance>>),
+    // The index of the number to give next
+    Range { range: Range, next: usize },
+}
+
+// Ways out of a statement other than running off its end
+enum Unwind {
+    Error(RuntimeError),
+    Return(Value),
+    Break,
+    Continue,
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(e: RuntimeError) -> Self {
+        Unwind::Error(e)
+    }
+}
+
+type ExecResult = Result<(), Unwind>;
+
+// How often, in steps, the clock is read to enforce `Limits::timeout`
+const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
+
+pub struct Interpreter {
+    globals: Rc<RefCell<Environment>>,
+    environment: Rc<RefCell<Environment>>,
+    // Expressions of the code running right now, and where its variables
+    // are declared
+    exprs: Rc<ExprArena>,
+    locals: Rc<Locals>,
+    limits: Limits,
+    interrupt: InterruptHandle,
+    capabilities: Capabilities,
+    output: Box<dyn Write>,
+    gc: GcOptions,
+    gc_stats: GcStats,
+
+    // Usage counted against `limits` during the current run
+    steps: u64,
+    // The live heap when it was last measured, plus what's been allocated
+    // since, freed or not
+    heap_bytes: usize,
+    call_depth: usize,
+    // Address of a local in `interpret`, to measure the native stack from
+    stack_base: usize,
+    started: Instant,
+    line: u32,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        let globals = gc::environment(Environment::new());
+        let mut interpreter = Interpreter {
+            environment: Rc::clone(&globals),
+            globals,
+            exprs: Rc::default(),
+            locals: Rc::default(),
+            limits: Limits::default(),
+            interrupt: InterruptHandle::default(),
+            capabilities: Capabilities::none(),
+            output: Box::new(io::stdout()),
+            gc: GcOptions::default(),
+            gc_stats: GcStats::default(),
+            steps: 0,
+            heap_bytes: 0,
+            call_depth: 0,
+            stack_base: 0,
+            started: Instant::now(),
+            line: 0,
+        };
+
+        natives::define_globals(&mut interpreter);
+
+        interpreter
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // Scan, parse and run `src`. Globals it defines stay around for later
+    // calls. Evaluates to the value of the final expression statement, or
+    // nil if the program doesn't end with one.
+    pub fn eval_str(&mut self, src: &str) -> Result<Value, Error> {
+        let tokens = Scanner::new(src).scan_tokens().to_vec();
+        let program = Parser::new(tokens).parse()?;
+        Ok(self.interpret(&program)?)
+    }
+
+    // Evaluate one REPL entry. An entry that ends with an expression and no
+    // ';' evaluates to `Some` of its value, so it can be echoed; otherwise
+    // this is `None`.
+    pub fn eval_repl(&mut self, src: &str) -> Result<Option<Value>, Error> {
+        let tokens = Scanner::new(src).scan_tokens().to_vec();
+        let (program, trailing_expression) = Parser::new(tokens).parse_repl()?;
+        let value = self.interpret(&program)?;
+        Ok(if trailing_expression {
+            Some(value)
+        } else {
+            None
+        })
+    }
+
+    // Run what `loxc::compile` wrote
+    pub fn eval_compiled(&mut self, bytes: &[u8]) -> Result<Value, Error> {
+        let program = loxc::load(bytes)?;
+        Ok(self.interpret(&program)?)
+    }
+
+    // A script, or a compiled one
+    pub fn run_file<P: AsRef<Path>>(&mut self, path: P) -> Result<Value, Error> {
+        let bytes = fs::read(path)?;
+        if loxc::is_compiled(&bytes) {
+            return self.eval_compiled(&bytes);
+        }
+        let src =
+            String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.eval_str(&src)
+    }
+
+    pub fn interpret(&mut self, program: &Program) -> EvalResult<Value> {
+        self.steps = 0;
+        self.heap_bytes = 0;
+        self.call_depth = 0;
+        self.stack_base = stack_address();
+        self.started = Instant::now();
+        // An interrupt sent while nothing was running was meant for a run
+        // that's already over
+        self.interrupt.take();
+        self.environment = Rc::clone(&self.globals);
+        self.exprs = Rc::clone(&program.exprs);
+        self.locals = Rc::clone(&program.locals);
+
+        let mut last = Value::Nil;
+
+        for stmt in &program.statements {
+            last = match stmt {
+                Stmt::Expression { expression } => self.evaluate(*expression)?,
+                _ => match self.execute(stmt) {
+                    Ok(()) => Value::Nil,
+                    Err(Unwind::Error(e)) => return Err(e),
+                    // The parser rejects `return` outside of a function, and
+                    // `break` and `continue` outside of a loop
+                    Err(Unwind::Return(_)) | Err(Unwind::Break) | Err(Unwind::Continue) => {
+                        unreachable!()
+                    }
+                },
+            };
+        }
+
+        Ok(last)
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    // Applies from the next run on
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    // A handle other threads can use to abort whatever this interpreter is
+    // running
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    pub fn set_gc_options(&mut self, options: GcOptions) {
+        self.gc = options;
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.gc_stats
+    }
+
+    // Free values that are only reachable from each other. Runs by itself
+    // as scripts allocate; returns how many objects were freed.
+    pub fn collect_garbage(&mut self) -> usize {
+        let (freed, live) = gc::collect(&self.gc);
+        self.gc_stats.collections += 1;
+        self.gc_stats.freed += freed;
+        self.gc_stats.live = live;
+        freed
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    // Built-ins that reach outside the interpreter fail with a runtime error
+    // unless their capability is granted here. Nothing is granted by default.
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    // Where `print` writes to, stdout by default
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    // Every global with its value, sorted by name
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        let mut globals: Vec<(String, Value)> = self
+            .globals
+            .borrow()
+            .values()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals
+    }
+
+    // Forget everything scripts have defined, as well as natives and classes
+    // registered from Rust, leaving only the built-ins. Limits,
+    // capabilities and output are kept.
+    pub fn reset(&mut self) {
+        self.globals = gc::environment(Environment::new());
+        self.environment = Rc::clone(&self.globals);
+        natives::define_globals(self);
+    }
+
+    // `None` if the global is undefined or doesn't convert to `T`
+    pub fn get_global<T: FromValue>(&self, name: &str) -> Option<T> {
+        T::from_value(&self.globals.borrow().get(name)?)
+    }
+
+    pub fn set_global<T: IntoValue>(&mut self, name: &str, value: T) {
+        self.globals.borrow_mut().define(name, value.into_value());
+    }
+
+    // Expose a Rust closure to scripts as a global function. Arguments are
+    // checked against `arity` before `function` runs; errors it returns are
+    // reported at the line of the call.
+    pub fn define_native<A, F>(&mut self, name: &str, arity: A, function: F)
+    where
+        A: Into<Arity>,
+        F: Fn(&[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        let native = NativeFunction::new(name, arity.into(), function);
+        self.globals
+            .borrow_mut()
+            .define(name, Value::Native(Rc::new(native)));
+    }
+
+    pub(crate) fn define_io_native<A, F>(
+        &mut self,
+        name: &str,
+        arity: A,
+        capability: Capability,
+        function: F,
+    ) where
+        A: Into<Arity>,
+        F: Fn(&[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        let mut native = NativeFunction::new(name, arity.into(), function);
+        native.capability = Some(capability);
+        self.globals
+            .borrow_mut()
+            .define(name, Value::Native(Rc::new(native)));
+    }
+
+    // Expose the Rust type `T` to scripts as a global class. Calling the class
+    // checks the arguments against `arity` and passes them to
+    // `T::construct`.
+    pub fn define_class<T, A>(&mut self, name: &str, arity: A)
+    where
+        T: NativeClass,
+        A: Into<Arity>,
+    {
+        let native = NativeConstructor {
+            arity: arity.into(),
+            construct: Box::new(|args| Ok(Box::new(T::construct(args)?) as Box<dyn NativeClass>)),
+        };
+        let class = LoxClass {
+            name: name.to_string(),
+            superclass: None,
+            methods: HashMap::new(),
+            native: Some(native),
+        };
+        self.globals
+            .borrow_mut()
+            .define(name, Value::Class(gc::class(class)));
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> ExecResult {
+        match stmt {
+            Stmt::Block { statements, .. } => {
+                let env = Environment::with_enclosing(Rc::clone(&self.environment));
+                let env = self.scope(env)?;
+                self.execute_block(statements, env)
+            }
+            Stmt::Break { .. } => Err(Unwind::Break),
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                let superclass = match superclass {
+                    Some(superclass) => match self.evaluate(*superclass)? {
+                        Value::Class(class) => Some(class),
+                        _ => {
+                            return Err(RuntimeError::new(
+                                self.exprs.line(*superclass),
+                                "Superclass must be a class.",
+                            )
+                            .into())
+                        }
+                    },
+                    None => None,
+                };
+
+                // Methods close over a scope holding `super`
+                let closure = match &superclass {
+                    Some(superclass) => {
+                        let mut env = Environment::with_enclosing(Rc::clone(&self.environment));
+                        env.define("super", Value::Class(Rc::clone(superclass)));
+                        Rc::new(RefCell::new(env))
+                    }
+                    None => Rc::clone(&self.environment),
+                };
+
+                let methods = methods
+                    .iter()
+                    .map(|method| {
+                        let function = LoxFunction {
+                            declaration: Rc::clone(method),
+                            exprs: Rc::clone(&self.exprs),
+                            locals: Rc::clone(&self.locals),
+                            closure: Rc::clone(&closure),
+                            is_initializer: method.name.name() == "init",
+                        };
+                        (method.name.name().to_string(), gc::function(function))
+                    })
+                    .collect();
+
+                self.allocate(mem::size_of::<LoxClass>())?;
+                let class = LoxClass {
+                    name: name.name().to_string(),
+                    superclass,
+                    methods,
+                    native: None,
+                };
+                self.environment
+                    .borrow_mut()
+                    .define(name.name(), Value::Class(gc::class(class)));
+                Ok(())
+            }
+            Stmt::Continue { .. } => Err(Unwind::Continue),
+            Stmt::Expression { expression } => {
+                self.evaluate(*expression)?;
+                Ok(())
+            }
+            Stmt::For {
+                keyword,
+                initializer,
+                condition,
+                increment,
+                body,
+            } => match initializer {
+                // The initializer's variable is scoped to the loop
+                Some(initializer) => {
+                    let env = Environment::with_enclosing(Rc::clone(&self.environment));
+                    let env = self.scope(env)?;
+                    let previous = mem::replace(&mut self.environment, env);
+                    let result = self
+                        .execute(initializer)
+                        .and_then(|_| self.execute_for(keyword, *condition, *increment, body));
+                    self.environment = previous;
+                    result
+                }
+                None => self.execute_for(keyword, *condition, *increment, body),
+            },
+            Stmt::ForIn {
+                keyword,
+                name,
+                iterable,
+                body,
+            } => {
+                let iterable = self.evaluate(*iterable)?;
+                let iteration = self.iteration(iterable, keyword.line)?;
+                self.execute_for_in(keyword, name, iteration, body)
+            }
+            Stmt::Function(declaration) => {
+                self.allocate(mem::size_of::<LoxFunction>())?;
+                let function = LoxFunction {
+                    declaration: Rc::clone(declaration),
+                    exprs: Rc::clone(&self.exprs),
+                    locals: Rc::clone(&self.locals),
+                    closure: Rc::clone(&self.environment),
+                    is_initializer: false,
+                };
+                let function = Value::Function(gc::function(function));
+                self.environment
+                    .borrow_mut()
+                    .define(declaration.name.name(), function);
+                Ok(())
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if self.evaluate(*condition)?.is_truthy() {
+                    self.execute(then_branch)
+                } else if let Some(else_branch) = else_branch {
+                    self.execute(else_branch)
+                } else {
+                    Ok(())
+                }
+            }
+            Stmt::Print { expression } => {
+                let value = self.evaluate(*expression)?;
+                writeln!(self.output, "{}", value).map_err(|e| {
+                    let line = self.exprs.line(*expression);
+                    RuntimeError::new(line, &format!("Could not print: {}.", e))
+                })?;
+                Ok(())
+            }
+            Stmt::Return { value, .. } => {
+                let value = match value {
+                    Some(expr) => self.evaluate(*expr)?,
+                    None => Value::Nil,
+                };
+                Err(Unwind::Return(value))
+            }
+            Stmt::Var { name, initializer } => {
+                let value = match initializer {
+                    Some(expr) => self.evaluate(*expr)?,
+                    None => Value::Nil,
+                };
+                self.environment.borrow_mut().define(name.name(), value);
+                Ok(())
+            }
+            Stmt::While { condition, body } => {
+                while self.evaluate(*condition)?.is_truthy() {
+                    match self.execute(body) {
+                        Ok(()) | Err(Unwind::Continue) => {}
+                        Err(Unwind::Break) => break,
+                        Err(unwind) => return Err(unwind),
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn execute_for(
+        &mut self,
+        keyword: &Token,
+        condition: Option<ExprId>,
+        increment: Option<ExprId>,
+        body: &Stmt,
+    ) -> ExecResult {
+        loop {
+            // Counts as a step even when every clause is empty
+            self.tick(keyword.line)?;
+            if let Some(condition) = condition {
+                if !self.evaluate(condition)?.is_truthy() {
+                    return Ok(());
+                }
+            }
+            match self.execute(body) {
+                // The increment runs after `continue` as well
+                Ok(()) | Err(Unwind::Continue) => {}
+                Err(Unwind::Break) => return Ok(()),
+                Err(unwind) => return Err(unwind),
+            }
+            if let Some(increment) = increment {
+                self.evaluate(increment)?;
+            }
+        }
+    }
+
+    // Each pass gets a fresh variable, so closures capture that pass's value
+    fn execute_for_in(
+        &mut self,
+        keyword: &Token,
+        name: &Token,
+        mut iteration: Iteration,
+        body: &Stmt,
+    ) -> ExecResult {
+        loop {
+            self.tick(keyword.line)?;
+            let value = match self.next_value(&mut iteration, keyword.line)? {
+                Some(value) => value,
+                None => return Ok(()),
+            };
+
+            let mut env = Environment::with_enclosing(Rc::clone(&self.environment));
+            env.define(name.name(), value);
+            let env = self.scope(env)?;
+            match self.execute_block(std::slice::from_ref(body), env) {
+                Ok(()) | Err(Unwind::Continue) => {}
+                Err(Unwind::Break) => return Ok(()),
+                Err(unwind) => return Err(unwind),
+            }
+        }
+    }
+
+    // Lists give their elements, maps their keys in insertion order,
+    // strings their characters and ranges their numbers. Instances hand over an iterator from their
+    // `iterator()` method, or a collection to iterate instead.
+    fn iteration(&mut self, iterable: Value, line: u32) -> EvalResult<Iteration> {
+        let values: Vec<Value> = match iterable {
+            Value::List(values) => values.borrow().clone(),
+            Value::Map(entries) => entries
+                .borrow()
+                .iter()
+                .map(|(key, _)| key.to_value())
+                .collect(),
+            Value::Str(s) => s.chars().map(|c| Value::Str(c.to_string())).collect(),
+            Value::Range(range) => return Ok(Iteration::Range { range, next: 0 }),
+            Value::Instance(instance) => {
+                let method = property(&instance, "iterator").ok_or_else(|| not_iterable(line))?;
+                return match self.call(method, Vec::new(), line)? {
+                    Value::Instance(iterator) => Ok(Iteration::Iterator(iterator)),
+                    value @ Value::List(_)
+                    | value @ Value::Map(_)
+                    | value @ Value::Str(_)
+                    | value @ Value::Range(_) => self.iteration(value, line),
+                    _ => Err(RuntimeError::new(
+                        line,
+                        "iterator() must return an object, list, map, string or range.",
+                    )),
+                };
+            }
+            _ => return Err(not_iterable(line)),
+        };
+        self.allocate(mem::size_of::<Value>() * values.len())?;
+        Ok(Iteration::Values(values.into_iter()))
+    }
+
+    // `None` once the iteration is over. An iterator is over when its `done`
+    // field or method is truthy; one without `done` is over when `next()`
+    // returns nil.
+    fn next_value(&mut self, iteration: &mut Iteration, line: u32) -> EvalResult<Option<Value>> {
+        let iterator = match iteration {
+            Iteration::Values(values) => return Ok(values.next()),
+            Iteration::Range { range, next } => {
+                let number = range.get(*next);
+                *next += 1;
+                return Ok(number.map(Value::Number));
+            }
+            Iteration::Iterator(iterator) => Rc::clone(iterator),
+        };
+
+        let has_done = match property(&iterator, "done") {
+            Some(done) => {
+                let done = match done {
+                    Value::Function(_) | Value::Native(_) => self.call(done, Vec::new(), line)?,
+                    done => done,
+                };
+                if done.is_truthy() {
+                    return Ok(None);
+                }
+                true
+            }
+            None => false,
+        };
+
+        let next = property(&iterator, "next")
+            .ok_or_else(|| RuntimeError::new(line, "Iterator must have a next() method."))?;
+        match self.call(next, Vec::new(), line)? {
+            Value::Nil if !has_done => Ok(None),
+            value => Ok(Some(value)),
+        }
+    }
+
+    fn execute_block(&mut self, statements: &[Stmt], env: Rc<RefCell<Environment>>) -> ExecResult {
+        let previous = std::mem::replace(&mut self.environment, env);
+        let result = statements.iter().try_for_each(|s| self.execute(s));
+        self.environment = previous;
+        result
+    }
+
+    fn evaluate(&mut self, id: ExprId) -> EvalResult<Value> {
+        // Hold on to the arena, calls swap in the callee's
+        let exprs = Rc::clone(&self.exprs);
+        self.tick(exprs.line(id))?;
+
+        match &exprs[id] {
+            Expr::Assign { name, value } => {
+                let value = self.evaluate(*value)?;
+                let assigned = match self.locals.depth(id) {
+                    Some(depth) => {
+                        self.environment
+                            .borrow_mut()
+                            .assign_at(depth, name.name(), value.clone())
+                    }
+                    None => self.globals.borrow_mut().assign(name.name(), value.clone()),
+                };
+                if assigned {
+                    Ok(value)
+                } else {
+                    Err(undefined_variable(name))
+                }
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                let left = self.evaluate(*left)?;
+                let right = self.evaluate(*right)?;
+                let value = binary(operator, left, right)?;
+                if let Value::Str(s) = &value {
+                    self.allocate(s.len())?;
+                }
+                Ok(value)
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => {
+                let callee = self.evaluate(*callee)?;
+                let args = arguments
+                    .iter()
+                    .map(|arg| self.evaluate(*arg))
+                    .collect::<EvalResult<Vec<Value>>>()?;
+                self.call(callee, args, paren.line)
+            }
+            Expr::Get { object, name } => match self.evaluate(*object)? {
+                Value::Instance(instance) => get_property(&instance, name),
+                Value::List(values) => {
+                    list::method(&values, name.name()).ok_or_else(|| undefined_property(name))
+                }
+                Value::Map(entries) => {
+                    map::method(&entries, name.name()).ok_or_else(|| undefined_property(name))
+                }
+                _ => Err(RuntimeError::new(
+                    name.line,
+                    "Only instances have properties.",
+                )),
+            },
+            Expr::Grouping { expression } => self.evaluate(*expression),
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                let object = self.evaluate(*object)?;
+                let index = self.evaluate(*index)?;
+                match object {
+                    Value::List(values) => {
+                        list::get(&values.borrow(), &index).map_err(|e| e.at_line(bracket.line))
+                    }
+                    Value::Map(entries) => {
+                        let key = Key::from_value(&index).map_err(|e| e.at_line(bracket.line))?;
+                        entries.borrow().get(&key).cloned().ok_or_else(|| {
+                            RuntimeError::new(bracket.line, &format!("Undefined key '{}'.", key))
+                        })
+                    }
+                    _ => Err(RuntimeError::new(
+                        bracket.line,
+                        "Only lists and maps can be indexed.",
+                    )),
+                }
+            }
+            Expr::Lit(t) => Ok(literal(t)),
+            Expr::List { elements, .. } => {
+                let values = elements
+                    .iter()
+                    .map(|element| self.evaluate(*element))
+                    .collect::<EvalResult<Vec<Value>>>()?;
+                self.allocate(mem::size_of::<Value>() * values.len())?;
+                Ok(Value::list(values))
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                let left = self.evaluate(*left)?;
+
+                // Short-circuit, returning the deciding operand itself
+                if operator.kind == TokenType::Or {
+                    if left.is_truthy() {
+                        return Ok(left);
+                    }
+                } else if !left.is_truthy() {
+                    return Ok(left);
+                }
+
+                self.evaluate(*right)
+            }
+            Expr::Map { entries, .. } => {
+                let mut map = LoxMap::new();
+                for (key, value) in entries {
+                    let line = exprs.line(*key);
+                    let key = Key::from_value(&self.evaluate(*key)?).map_err(|e| e.at_line(line))?;
+                    let value = self.evaluate(*value)?;
+                    map.insert(key, value);
+                }
+                self.allocate(mem::size_of::<(Key, Value)>() * map.len())?;
+                Ok(Value::map(map))
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                let instance = match self.evaluate(*object)? {
+                    Value::Instance(instance) => instance,
+                    _ => return Err(RuntimeError::new(name.line, "Only instances have fields.")),
+                };
+                let value = self.evaluate(*value)?;
+                self.allocate(mem::size_of::<Value>() + name.name().len())?;
+
+                let mut instance = instance.borrow_mut();
+                let handled = match &mut instance.native {
+                    Some(native) => native
+                        .set(name.name(), &value)
+                        .map_err(|e| e.at_line(name.line))?,
+                    None => false,
+                };
+                if !handled {
+                    instance
+                        .fields
+                        .insert(name.name().to_string(), value.clone());
+                }
+                Ok(value)
+            }
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                let object = self.evaluate(*object)?;
+                let index = self.evaluate(*index)?;
+                let value = self.evaluate(*value)?;
+                match object {
+                    Value::List(values) => {
+                        list::set(&mut values.borrow_mut(), &index, value.clone())
+                            .map_err(|e| e.at_line(bracket.line))?;
+                        Ok(value)
+                    }
+                    Value::Map(entries) => {
+                        let key = Key::from_value(&index).map_err(|e| e.at_line(bracket.line))?;
+                        if !entries.borrow().contains_key(&key) {
+                            self.allocate(mem::size_of::<(Key, Value)>())?;
+                        }
+                        entries.borrow_mut().insert(key, value.clone());
+                        Ok(value)
+                    }
+                    _ => Err(RuntimeError::new(
+                        bracket.line,
+                        "Only lists and maps can be indexed.",
+                    )),
+                }
+            }
+            Expr::Slice {
+                object,
+                bracket,
+                start,
+                end,
+            } => {
+                let object = self.evaluate(*object)?;
+                let start = start.map(|start| self.evaluate(start)).transpose()?;
+                let end = end.map(|end| self.evaluate(end)).transpose()?;
+                match object {
+                    Value::List(values) => {
+                        let sliced = list::slice(&values.borrow(), start.as_ref(), end.as_ref())
+                            .map_err(|e| e.at_line(bracket.line))?;
+                        self.allocate(mem::size_of::<Value>() * sliced.len())?;
+                        Ok(Value::list(sliced))
+                    }
+                    _ => Err(RuntimeError::new(bracket.line, "Only lists can be sliced.")),
+                }
+            }
+            // `this` is bound in the scope just inside the one with `super`
+            Expr::Super { keyword, method } => {
+                let depth = match self.locals.depth(id) {
+                    Some(depth) => depth,
+                    // The parser only allows `super` inside a subclass
+                    None => unreachable!(),
+                };
+                let env = self.environment.borrow();
+                let superclass = match env.get_at(depth, "super") {
+                    Some(Value::Class(class)) => class,
+                    _ => unreachable!(),
+                };
+                let instance = match env.get_at(depth - 1, "this") {
+                    Some(Value::Instance(instance)) => instance,
+                    _ => unreachable!(),
+                };
+
+                match superclass.find_method(method.name()) {
+                    Some(m) => Ok(Value::Function(gc::function(
+                        m.bind(Value::Instance(instance)),
+                    ))),
+                    None => native_method(&instance, method.name()).ok_or_else(|| {
+                        RuntimeError::new(
+                            keyword.line,
+                            &format!("Undefined property '{}'.", method.name()),
+                        )
+                    }),
+                }
+            }
+            Expr::This { keyword } => self
+                .lookup(id, "this")
+                .ok_or_else(|| undefined_variable(keyword)),
+            Expr::Unary { operator, right } => {
+                let right = self.evaluate(*right)?;
+                match (&operator.kind, right) {
+                    (TokenType::Bang, r) => Ok(Value::Bool(!r.is_truthy())),
+                    (TokenType::Minus, Value::Number(n)) => Ok(Value::Number(-n)),
+                    _ => Err(RuntimeError::new(
+                        operator.line,
+                        "Operand must be a number.",
+                    )),
+                }
+            }
+            Expr::Variable { name } => self
+                .lookup(id, name.name())
+                .ok_or_else(|| undefined_variable(name)),
+        }
+    }
+
+    // Locals are where the resolver found them; anything else is a global
+    fn lookup(&self, id: ExprId, name: &str) -> Option<Value> {
+        match self.locals.depth(id) {
+            Some(depth) => self.environment.borrow().get_at(depth, name),
+            None => self.globals.borrow().get(name),
+        }
+    }
+
+    // Count one step against the limits, and check for interrupts
+    fn tick(&mut self, line: u32) -> EvalResult<()> {
+        self.line = line;
+        self.steps += 1;
+
+        if let Some(max) = self.limits.max_steps {
+            if self.steps > max {
+                return Err(self.limit_exceeded(Limit::Steps));
+            }
+        }
+        if let Some(max) = self.limits.max_stack_bytes {
+            if self.stack_base.abs_diff(stack_address()) > max {
+                return Err(self.limit_exceeded(Limit::CallDepth));
+            }
+        }
+        if self.interrupt.take() {
+            return Err(self.limit_exceeded(Limit::Interrupted));
+        }
+        if let Some(timeout) = self.limits.timeout {
+            if self.steps.is_multiple_of(TIMEOUT_CHECK_INTERVAL) && self.started.elapsed() > timeout
+            {
+                return Err(self.limit_exceeded(Limit::Timeout));
+            }
+        }
+        if gc::due(&self.gc) {
+            self.collect_garbage();
+        }
+
+        Ok(())
+    }
+
+    // Past the limit, the count is corrected to what's actually still alive,
+    // and only if that's over too is it an error
+    pub(crate) fn allocate(&mut self, bytes: usize) -> EvalResult<()> {
+        self.heap_bytes += bytes;
+
+        match self.limits.max_heap_bytes {
+            Some(max) if self.heap_bytes > max => {
+                self.collect_garbage();
+                self.heap_bytes = gc::live_bytes();
+                if self.heap_bytes > max {
+                    return Err(self.limit_exceeded(Limit::Heap));
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // A new scope. With a heap limit, every scope is tracked so the strings
+    // in it are counted as live.
+    fn scope(&mut self, env: Environment) -> EvalResult<Rc<RefCell<Environment>>> {
+        self.allocate(mem::size_of::<Environment>())?;
+        Ok(match self.limits.max_heap_bytes {
+            Some(_) => gc::environment(env),
+            None => Rc::new(RefCell::new(env)),
+        })
+    }
+
+    fn limit_exceeded(&self, limit: Limit) -> RuntimeError {
+        RuntimeError::LimitExceeded {
+            line: self.line,
+            limit,
+        }
+    }
+
+    pub(crate) fn call(&mut self, callee: Value, args: Vec<Value>, line: u32) -> EvalResult<Value> {
+        let arity = match &callee {
+            Value::Function(function) => function.arity(),
+            Value::Native(native) => native.arity,
+            Value::Class(class) => class.arity(),
+            _ => {
+                return Err(RuntimeError::new(
+                    line,
+                    "Can only call functions and classes.",
+                ))
+            }
+        };
+        check_arity(arity, args.len(), line)?;
+
+        match callee {
+            Value::Function(function) => {
+                if let Some(max) = self.limits.max_call_depth {
+                    if self.call_depth >= max {
+                        return Err(self.limit_exceeded(Limit::CallDepth));
+                    }
+                }
+                let mut env = Environment::with_enclosing(Rc::clone(&function.closure));
+                for (param, arg) in function.declaration.params.iter().zip(args) {
+                    env.define(param.name(), arg);
+                }
+                let env = self.scope(env)?;
+
+                self.call_depth += 1;
+                let exprs = mem::replace(&mut self.exprs, Rc::clone(&function.exprs));
+                let locals = mem::replace(&mut self.locals, Rc::clone(&function.locals));
+                let result = self.execute_block(&function.declaration.body, env);
+                self.exprs = exprs;
+                self.locals = locals;
+                self.call_depth -= 1;
+                match result {
+                    // Initializers always hand back the new instance
+                    Ok(()) | Err(Unwind::Return(_)) if function.is_initializer => {
+                        Ok(function.closure.borrow().get("this").unwrap_or(Value::Nil))
+                    }
+                    Ok(()) => Ok(Value::Nil),
+                    Err(Unwind::Return(value)) => Ok(value),
+                    Err(Unwind::Error(e)) => Err(e),
+                    // Loops can't reach out of a function
+                    Err(Unwind::Break) | Err(Unwind::Continue) => unreachable!(),
+                }
+            }
+            Value::Class(class) => {
+                let native = match class.native() {
+                    Some(native) => {
+                        check_arity(native.arity, args.len(), line)?;
+                        Some((native.construct)(&args).map_err(|e| e.at_line(line))?)
+                    }
+                    None => None,
+                };
+
+                self.allocate(mem::size_of::<LoxInstance>())?;
+                let instance = Value::Instance(gc::instance(LoxInstance {
+                    class: Rc::clone(&class),
+                    fields: HashMap::new(),
+                    native,
+                }));
+
+                if let Some(init) = class.find_method("init") {
+                    let init = init.bind(instance.clone());
+                    self.call(Value::Function(gc::function(init)), args, line)?;
+                }
+
+                Ok(instance)
+            }
+            Value::Native(native) => {
+                if let Some(cap) = native.capability {
+                    if !self.capabilities.contains(cap) {
+                        let message = format!(
+                            "{}() needs the '{}' capability, which isn't granted.",
+                            native.name, cap
+                        );
+                        return Err(RuntimeError::new(line, &message));
+                    }
+                }
+
+                native.call(self, &args).map_err(|e| e.at_line(line))
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+// Roughly where the top of the native stack is
+fn stack_address() -> usize {
+    let local = 0u8;
+    &local as *const u8 as usize
+}
+
+fn check_arity(arity: Arity, count: usize, line: u32) -> EvalResult<()> {
+    if arity.accepts(count) {
+        return Ok(());
+    }
+
+    let message = match arity {
+        Arity::Exact(n) => format!("Expected {} arguments but got {}.", n, count),
+        Arity::AtLeast(n) => format!("Expected at least {} arguments but got {}.", n, count),
+        Arity::Between(min, max) => format!(
+            "Expected {} to {} arguments but got {}.",
+            min, max, count
+        ),
+    };
+    Err(RuntimeError::new(line, &message))
+}
+
+fn get_property(instance: &Rc<RefCell<LoxInstance>>, name: &Token) -> EvalResult<Value> {
+    property(instance, name.name()).ok_or_else(|| undefined_property(name))
+}
+
+// Fields shadow native properties, which shadow methods
+fn property(instance: &Rc<RefCell<LoxInstance>>, name: &str) -> Option<Value> {
+    let inst = instance.borrow();
+
+    if let Some(value) = inst.fields.get(name) {
+        return Some(value.clone());
+    }
+    if let Some(value) = inst.native.as_ref().and_then(|n| n.get(name)) {
+        return Some(value);
+    }
+    if let Some(method) = inst.class.find_method(name) {
+        let bound = method.bind(Value::Instance(Rc::clone(instance)));
+        return Some(Value::Function(gc::function(bound)));
+    }
+    drop(inst);
+
+    native_method(instance, name)
+}
+
+// A native method bound to `instance`, wrapped up as a native function
+fn native_method(instance: &Rc<RefCell<LoxInstance>>, name: &str) -> Option<Value> {
+    let arity = instance.borrow().native.as_ref()?.method_arity(name)?;
+    let bound = Rc::clone(instance);
+    let method = name.to_string();
+
+    let function = NativeFunction::new(name, arity, move |args| {
+        match bound.borrow_mut().native.as_mut() {
+            Some(native) => native.call(&method, args),
+            None => unreachable!(),
+        }
+    });
+    Some(Value::Native(Rc::new(function)))
+}
+
+fn literal(t: &Token) -> Value {
+    match &t.kind {
+        TokenType::True => Value::Bool(true),
+        TokenType::False => Value::Bool(false),
+        TokenType::Literal(LiteralKind::Number(n)) => Value::Number(*n),
+        TokenType::Literal(LiteralKind::Str(s)) => Value::Str(s.clone()),
+        _ => Value::Nil,
+    }
+}
+
+fn binary(operator: &Token, left: Value, right: Value) -> EvalResult<Value> {
+    use Value::*;
+
+    let value = match (&operator.kind, left, right) {
+        (TokenType::EqualEqual, l, r) => Bool(l == r),
+        (TokenType::BangEqual, l, r) => Bool(l != r),
+        (TokenType::Plus, Number(l), Number(r)) => Number(l + r),
+        (TokenType::Plus, Str(l), Str(r)) => Str(l + &r),
+        (TokenType::Plus, _, _) => {
+            return Err(RuntimeError::new(
+                operator.line,
+                "Operands must be two numbers or two strings.",
+            ))
+        }
+        (TokenType::Minus, Number(l), Number(r)) => Number(l - r),
+        (TokenType::Star, Number(l), Number(r)) => Number(l * r),
+        (TokenType::Slash, Number(l), Number(r)) => Number(l / r),
+        (TokenType::Greater, Number(l), Number(r)) => Bool(l > r),
+        (TokenType::GreaterEqual, Number(l), Number(r)) => Bool(l >= r),
+        (TokenType::Less, Number(l), Number(r)) => Bool(l < r),
+        (TokenType::LessEqual, Number(l), Number(r)) => Bool(l <= r),
+        _ => {
+            return Err(RuntimeError::new(
+                operator.line,
+                "Operands must be numbers.",
+            ))
+        }
+    };
+
+    Ok(value)
+}
+
+fn undefined_property(name: &Token) -> RuntimeError {
+    RuntimeError::new(name.line, &format!("Undefined property '{}'.", name.name()))
+}
+
+fn not_iterable(line: u32) -> RuntimeError {
+    RuntimeError::new(
+        line,
+        "Can only iterate over lists, maps, strings and objects with an iterator() method.",
+    )
+}
+
+fn undefined_variable(name: &Token) -> RuntimeError {
+    RuntimeError::new(name.line, &format!("Undefined variable '{}'.", name.name()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::OutputBuffer;
+    use std::time::Duration;
+
+    fn eval(src: &str) -> Value {
+        Interpreter::new().eval_str(src).unwrap()
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(eval("-(1 + 2) * 4 / 2;"), Value::Number(-6.0));
+        assert_eq!(eval(r#""lu" + "xor";"#), Value::Str("luxor".to_string()));
+        assert_eq!(eval("1 < 2 == !nil;"), Value::Bool(true));
+    }
+
+    #[test]
+    fn test_logical() {
+        assert_eq!(eval(r#"nil or "yes";"#), Value::Str("yes".to_string()));
+        assert_eq!(eval("false and undefined;"), Value::Bool(false));
+    }
+
+    #[test]
+    fn test_scopes_and_loops() {
+        let src = "
+var total = 0;
+for (var i = 0; i < 5; i = i + 1) {
+    var total = i;
+}
+var i = 0;
+while (i < 4) { total = total + i; i = i + 1; }
+total;";
+        assert_eq!(eval(src), Value::Number(6.0));
+    }
+
+    #[test]
+    fn test_globals() {
+        let mut interp = Interpreter::new();
+        interp.set_global("limit", 10.0);
+        interp.eval_str("var doubled = limit * 2;").unwrap();
+        assert_eq!(interp.get_global::<f64>("doubled"), Some(20.0));
+        assert_eq!(interp.get_global::<String>("doubled"), None);
+        assert_eq!(interp.get_global::<f64>("missing"), None);
+    }
+
+    #[test]
+    fn test_functions() {
+        let src = "
+fun make_counter() {
+    var i = 0;
+    fun count() {
+        i = i + 1;
+        return i;
+    }
+    return count;
+}
+var counter = make_counter();
+counter();
+counter();";
+        assert_eq!(eval(src), Value::Number(2.0));
+    }
+
+    #[test]
+    fn test_natives() {
+        let mut interp = Interpreter::new();
+        interp.define_native("sum", Arity::AtLeast(1), |args| {
+            let total = args.iter().try_fold(0.0, |acc, arg| match arg {
+                Value::Number(n) => Ok(acc + n),
+                _ => Err(RuntimeError::from("Arguments must be numbers.")),
+            })?;
+            Ok(Value::Number(total))
+        });
+        assert_eq!(
+            interp.eval_str("sum(1, 2, 3);").unwrap(),
+            Value::Number(6.0)
+        );
+
+        match interp.eval_str("\n\nsum(1, nil);") {
+            Err(Error::Runtime(e)) => {
+                assert_eq!(e, RuntimeError::new(3, "Arguments must be numbers."))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        match interp.eval_str("sum();") {
+            Err(Error::Runtime(e)) => {
+                assert_eq!(e.message(), "Expected at least 1 arguments but got 0.")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_classes() {
+        let src = r#"
+class Shape {
+    init(name) { this.name = name; }
+    describe() { return this.name + " with " + this.sides() + " sides"; }
+    sides() { return "no"; }
+}
+class Square < Shape {
+    init() { super.init("square"); }
+    sides() { return "four"; }
+}
+Square().describe();"#;
+        assert_eq!(eval(src), Value::Str("square with four sides".to_string()));
+    }
+
+    struct Counter {
+        count: f64,
+    }
+
+    impl NativeClass for Counter {
+        fn construct(args: &[Value]) -> Result<Self, RuntimeError> {
+            match args {
+                [] => Ok(Counter { count: 0.0 }),
+                [Value::Number(n)] => Ok(Counter { count: *n }),
+                _ => Err("Start must be a number.".into()),
+            }
+        }
+
+        fn get(&self, name: &str) -> Option<Value> {
+            match name {
+                "count" => Some(Value::Number(self.count)),
+                _ => None,
+            }
+        }
+
+        fn method_arity(&self, name: &str) -> Option<Arity> {
+            match name {
+                "increment" => Some(Arity::Exact(0)),
+                _ => None,
+            }
+        }
+
+        fn call(&mut self, _name: &str, _args: &[Value]) -> Result<Value, RuntimeError> {
+            self.count += 1.0;
+            Ok(Value::Number(self.count))
+        }
+    }
+
+    #[test]
+    fn test_native_class() {
+        let mut interp = Interpreter::new();
+        interp.define_class::<Counter, _>("Counter", Arity::AtLeast(0));
+
+        let src = "var c = Counter(); c.increment(); c.increment(); c.count;";
+        assert_eq!(interp.eval_str(src).unwrap(), Value::Number(2.0));
+
+        let src = "
+class Loud < Counter {
+    increment() {
+        this.label = \"loud\";
+        return super.increment() * 10;
+    }
+}
+var l = Loud(4);
+l.increment();";
+        assert_eq!(interp.eval_str(src).unwrap(), Value::Number(50.0));
+        assert_eq!(
+            interp.eval_str("l.label;").unwrap(),
+            Value::Str("loud".to_string())
+        );
+
+        match interp.eval_str("\nCounter(\"x\");") {
+            Err(Error::Runtime(e)) => {
+                assert_eq!(e, RuntimeError::new(2, "Start must be a number."))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    fn limit_hit(interp: &mut Interpreter, src: &str) -> Limit {
+        match interp.eval_str(src) {
+            Err(Error::Runtime(RuntimeError::LimitExceeded { limit, .. })) => limit,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut interp = Interpreter::new();
+        // Recursion is bounded even with the default limits
+        assert_eq!(
+            limit_hit(&mut interp, "fun f() { f(); } f();"),
+            Limit::CallDepth
+        );
+
+        interp.set_limits(Limits {
+            max_steps: Some(10_000),
+            ..Limits::default()
+        });
+        assert_eq!(limit_hit(&mut interp, "while (true) {}"), Limit::Steps);
+        // Counters start over on every run
+        assert_eq!(interp.eval_str("1 + 1;").unwrap(), Value::Number(2.0));
+
+        interp.set_limits(Limits {
+            max_call_depth: Some(64),
+            ..Limits::default()
+        });
+        assert_eq!(
+            limit_hit(&mut interp, "fun f(n) { return f(n + 1); } f(0);"),
+            Limit::CallDepth
+        );
+
+        interp.set_limits(Limits {
+            max_heap_bytes: Some(1 << 16),
+            ..Limits::default()
+        });
+        assert_eq!(
+            limit_hit(&mut interp, r#"var s = "x"; while (true) { s = s + s; }"#),
+            Limit::Heap
+        );
+        assert_eq!(
+            limit_hit(&mut interp, r#"var l = []; while (true) l.push("x");"#),
+            Limit::Heap
+        );
+        // Only what's still alive counts
+        interp.set_limits(Limits {
+            max_heap_bytes: Some(1 << 20),
+            ..Limits::default()
+        });
+        let src = r#"
+            var s;
+            for (var i = 0; i < 100000; i = i + 1) {
+                s = "a" + "b";
+                var garbage = [s];
+                garbage.push(garbage);
+            }
+            s;
+        "#;
+        assert_eq!(interp.eval_str(src).unwrap(), Value::Str("ab".to_string()));
+
+        interp.set_limits(Limits {
+            timeout: Some(Duration::from_millis(10)),
+            ..Limits::default()
+        });
+        assert_eq!(limit_hit(&mut interp, "for (;;) {}"), Limit::Timeout);
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut interp = Interpreter::new();
+        let handle = interp.interrupt_handle();
+        let t = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            handle.interrupt();
+        });
+
+        assert_eq!(
+            limit_hit(&mut interp, "while (true) {}"),
+            Limit::Interrupted
+        );
+        t.join().unwrap();
+
+        // Interrupting between runs doesn't stop the next one
+        interp.interrupt_handle().interrupt();
+        assert_eq!(interp.eval_str("1 + 1;").unwrap(), Value::Number(2.0));
+    }
+
+    #[test]
+    fn test_collect_garbage() {
+        let mut interp = Interpreter::new();
+        interp
+            .eval_str("class A { m() {} } var a = A(); a.me = a; a.m = a.m;")
+            .unwrap();
+        let instance = match interp.get_global::<Value>("a") {
+            Some(Value::Instance(instance)) => Rc::downgrade(&instance),
+            other => panic!("unexpected {:?}", other),
+        };
+
+        // Still reachable from a global
+        interp.collect_garbage();
+        assert!(instance.upgrade().is_some());
+
+        interp.eval_str("a = nil;").unwrap();
+        assert!(instance.upgrade().is_some());
+        assert!(interp.collect_garbage() > 0);
+        assert!(instance.upgrade().is_none());
+        assert_eq!(interp.gc_stats().collections, 2);
+
+        // Collecting between every step doesn't free anything in use
+        interp.set_gc_options(GcOptions {
+            stress: true,
+            ..GcOptions::default()
+        });
+        let src = r#"
+            fun counter() {
+                var n = 0;
+                fun count() { n = n + 1; return n; }
+                return count;
+            }
+            var c = counter();
+            var l = [c];
+            l.push(l);
+            c(); c();
+            l[1][0]() + {"a": l}["a"].len();
+        "#;
+        assert_eq!(interp.eval_str(src).unwrap(), Value::Number(5.0));
+        assert!(interp.gc_stats().collections > 2);
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let mut interp = Interpreter::new();
+        match interp.eval_str("clock();") {
+            Err(Error::Runtime(e)) => assert_eq!(
+                e.message(),
+                "clock() needs the 'clock' capability, which isn't granted."
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        interp.set_capabilities(Capabilities::none().grant(Capability::Clock));
+        assert!(interp.eval_str("clock() > 0;").unwrap().is_truthy());
+        assert!(interp.eval_str(r#"readFile("Cargo.toml");"#).is_err());
+    }
+
+    #[test]
+    fn test_output() {
+        let buffer = OutputBuffer::new();
+        let mut interp = Interpreter::new();
+        interp.set_output(Box::new(buffer.clone()));
+
+        interp
+            .eval_str("print 1 + 2; print \"three\"; print nil;")
+            .unwrap();
+        assert_eq!(buffer.contents(), "3\nthree\nnil\n");
+    }
+
+    #[test]
+    fn test_eval_repl() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_repl("var a = 1;").unwrap(), None);
+        assert_eq!(
+            interp.eval_repl("fun inc(x) { return x + a; }").unwrap(),
+            None
+        );
+        assert_eq!(
+            interp.eval_repl("a = 2; inc(1)").unwrap(),
+            Some(Value::Number(3.0))
+        );
+        assert_eq!(interp.eval_repl("inc(1);").unwrap(), None);
+        assert!(interp.eval_repl("print 1").is_err());
+        // Only a bare expression at the top level may leave off its ';'
+        assert!(interp.eval_repl("if (true) 1").is_err());
+        assert!(interp.eval_repl("{ 1 }").is_err());
+        assert!(interp.eval_repl("while (false) 1").is_err());
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut interp = Interpreter::new();
+        interp.eval_str("var a = 1;").unwrap();
+        assert_eq!(
+            interp
+                .globals()
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a", "clock", "getenv", "range", "readFile", "readLine", "writeFile"]
+        );
+
+        interp.reset();
+        assert_eq!(interp.get_global::<f64>("a"), None);
+        assert!(interp.get_global::<Value>("clock").is_some());
+    }
+
+    #[test]
+    fn test_runtime_error() {
+        match Interpreter::new().eval_str("\n-\"a\";") {
+            Err(Error::Runtime(e)) => {
+                assert_eq!(e, RuntimeError::new(2, "Operand must be a number."))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        match Interpreter::new().eval_str("var = 1;\n(1 + ;") {
+            Err(Error::Syntax(errors)) => {
+                let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                assert_eq!(
+                    messages,
+                    vec![
+                        "[line 1] Error at '=': Expect variable name.",
+                        "[line 2] Error at ';': Expect expression.",
+                    ]
+                );
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_too_much_nesting() {
+        let deep = 100_000;
+        for src in &[
+            format!("{}1{};", "(".repeat(deep), ")".repeat(deep)),
+            format!("{}{}", "{".repeat(deep), "}".repeat(deep)),
+            format!("{}1;", "!".repeat(deep)),
+        ] {
+            match Interpreter::new().eval_str(src) {
+                Err(Error::Syntax(errors)) => {
+                    assert_eq!(errors.len(), 1);
+                    assert_eq!(errors[0].message, "Too much nesting.");
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+
+        for src in &[
+            format!("1{};", " + 1".repeat(deep)),
+            format!("clock{};", "()".repeat(deep)),
+        ] {
+            match Interpreter::new().eval_str(src) {
+                Err(Error::Syntax(errors)) => {
+                    assert_eq!(errors.len(), 1);
+                    assert_eq!(errors[0].message, "Expression is too long.");
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_long_chain() {
+        // Flat chains don't count as nesting. Evaluating one still recurses,
+        // which takes more stack than a test thread has in a debug build.
+        let thread = std::thread::Builder::new().stack_size(16 << 20);
+        let handle = thread.spawn(|| {
+            let mut interp = Interpreter::new();
+            interp.set_limits(Limits {
+                max_stack_bytes: Some(8 << 20),
+                ..Limits::default()
+            });
+            let src = format!("\"\"{};", " + \"a\"".repeat(400));
+            interp.eval_str(&src).unwrap().to_string()
+        });
+        assert_eq!(handle.unwrap().join().unwrap(), "a".repeat(400));
+    }
+}
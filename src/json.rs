@@ -0,0 +1,394 @@
+// Syntax trees as JSON, for tools that would rather not parse S-expressions.
+//
+// Every node is an object with a "type" and the "line" of the token that
+// best identifies it, plus one member per child. Expressions also carry their arena "id", so tools
+// can line them up with anything keyed on `ExprId`. Tokens that only name
+// something (variables, properties, parameters) become plain strings;
+// operators become their source text.
+
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::ast::{Expr, ExprArena, ExprId, Function, Program, Stmt};
+use crate::token::{LiteralKind, Token, TokenType};
+
+// A whole program as a JSON array of statements
+pub fn program(program: &Program) -> String {
+    let mut out = String::new();
+    write_stmts(&mut out, &program.exprs, &program.statements);
+    out
+}
+
+pub fn stmt(exprs: &ExprArena, stmt: &Stmt) -> String {
+    let mut out = String::new();
+    write_stmt(&mut out, exprs, stmt);
+    out
+}
+
+pub fn expr(exprs: &ExprArena, id: ExprId) -> String {
+    let mut out = String::new();
+    write_expr(&mut out, exprs, id);
+    out
+}
+
+// Builds one node; members are written in the order they're added
+struct Node<'a> {
+    out: &'a mut String,
+    exprs: &'a ExprArena,
+}
+
+impl<'a> Node<'a> {
+    fn new(out: &'a mut String, exprs: &'a ExprArena, kind: &str, line: u32) -> Self {
+        let _ = write!(out, "{{\"type\":\"{}\",\"line\":{}", kind, line);
+        Node { out, exprs }
+    }
+
+    fn key(&mut self, key: &str) -> &mut String {
+        let _ = write!(self.out, ",\"{}\":", key);
+        self.out
+    }
+
+    fn string(mut self, key: &str, value: &str) -> Self {
+        write_string(self.key(key), value);
+        self
+    }
+
+    fn token(self, key: &str, token: &Token) -> Self {
+        self.string(key, &token.to_string())
+    }
+
+    fn opt_token(self, key: &str, token: Option<&Token>) -> Self {
+        match token {
+            Some(token) => self.token(key, token),
+            None => self.raw(key, "null"),
+        }
+    }
+
+    fn expr(mut self, key: &str, id: ExprId) -> Self {
+        let exprs = self.exprs;
+        write_expr(self.key(key), exprs, id);
+        self
+    }
+
+    fn opt_expr(self, key: &str, id: Option<ExprId>) -> Self {
+        match id {
+            Some(id) => self.expr(key, id),
+            None => self.raw(key, "null"),
+        }
+    }
+
+    fn exprs(mut self, key: &str, ids: &[ExprId]) -> Self {
+        let exprs = self.exprs;
+        let out = self.key(key);
+        out.push('[');
+        for (i, id) in ids.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write_expr(out, exprs, *id);
+        }
+        out.push(']');
+        self
+    }
+
+    fn stmt(mut self, key: &str, stmt: &Stmt) -> Self {
+        let exprs = self.exprs;
+        write_stmt(self.key(key), exprs, stmt);
+        self
+    }
+
+    fn opt_stmt(self, key: &str, stmt: Option<&Stmt>) -> Self {
+        match stmt {
+            Some(stmt) => self.stmt(key, stmt),
+            None => self.raw(key, "null"),
+        }
+    }
+
+    fn stmts(mut self, key: &str, stmts: &[Stmt]) -> Self {
+        let exprs = self.exprs;
+        write_stmts(self.key(key), exprs, stmts);
+        self
+    }
+
+    fn functions(mut self, key: &str, functions: &[Rc<Function>]) -> Self {
+        let exprs = self.exprs;
+        let out = self.key(key);
+        out.push('[');
+        for (i, function) in functions.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write_function(out, exprs, function);
+        }
+        out.push(']');
+        self
+    }
+
+    fn names(mut self, key: &str, tokens: &[Token]) -> Self {
+        let out = self.key(key);
+        out.push('[');
+        for (i, token) in tokens.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write_string(out, &token.to_string());
+        }
+        out.push(']');
+        self
+    }
+
+    fn raw(mut self, key: &str, value: &str) -> Self {
+        self.key(key).push_str(value);
+        self
+    }
+
+    fn end(self) {
+        self.out.push('}');
+    }
+}
+
+fn write_stmts(out: &mut String, exprs: &ExprArena, stmts: &[Stmt]) {
+    out.push('[');
+    for (i, stmt) in stmts.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_stmt(out, exprs, stmt);
+    }
+    out.push(']');
+}
+
+fn write_function(out: &mut String, exprs: &ExprArena, function: &Function) {
+    Node::new(out, exprs, "Function", function.name.line)
+        .token("name", &function.name)
+        .names("params", &function.params)
+        .stmts("body", &function.body)
+        .end()
+}
+
+fn write_stmt(out: &mut String, exprs: &ExprArena, stmt: &Stmt) {
+    let line = stmt.line(exprs);
+    match stmt {
+        Stmt::Block { statements, .. } => Node::new(out, exprs, "Block", line)
+            .stmts("statements", statements)
+            .end(),
+        Stmt::Break { .. } => Node::new(out, exprs, "Break", line).end(),
+        Stmt::Continue { .. } => Node::new(out, exprs, "Continue", line).end(),
+        Stmt::Class {
+            name,
+            superclass,
+            methods,
+        } => Node::new(out, exprs, "Class", line)
+            .token("name", name)
+            .opt_token(
+                "superclass",
+                superclass.and_then(|id| exprs.variable_name(id)),
+            )
+            .functions("methods", methods)
+            .end(),
+        Stmt::Expression { expression } => Node::new(out, exprs, "Expression", line)
+            .expr("expression", *expression)
+            .end(),
+        Stmt::For {
+            initializer,
+            condition,
+            increment,
+            body,
+            ..
+        } => Node::new(out, exprs, "For", line)
+            .opt_stmt("initializer", initializer.as_deref())
+            .opt_expr("condition", *condition)
+            .opt_expr("increment", *increment)
+            .stmt("body", body)
+            .end(),
+        Stmt::ForIn {
+            name,
+            iterable,
+            body,
+            ..
+        } => Node::new(out, exprs, "ForIn", line)
+            .token("name", name)
+            .expr("iterable", *iterable)
+            .stmt("body", body)
+            .end(),
+        Stmt::Function(function) => write_function(out, exprs, function),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => Node::new(out, exprs, "If", line)
+            .expr("condition", *condition)
+            .stmt("then", then_branch)
+            .opt_stmt("else", else_branch.as_deref())
+            .end(),
+        Stmt::Print { expression } => Node::new(out, exprs, "Print", line)
+            .expr("expression", *expression)
+            .end(),
+        Stmt::Return { value, .. } => Node::new(out, exprs, "Return", line)
+            .opt_expr("value", *value)
+            .end(),
+        Stmt::Var { name, initializer } => Node::new(out, exprs, "Var", line)
+            .token("name", name)
+            .opt_expr("initializer", *initializer)
+            .end(),
+        Stmt::While { condition, body } => Node::new(out, exprs, "While", line)
+            .expr("condition", *condition)
+            .stmt("body", body)
+            .end(),
+    }
+}
+
+fn write_expr(out: &mut String, exprs: &ExprArena, id: ExprId) {
+    let line = exprs.line(id);
+    let node = |out, kind| Node::new(out, exprs, kind, line).raw("id", &id.index().to_string());
+    match &exprs[id] {
+        Expr::Binary {
+            left,
+            operator,
+            right,
+        } => node(out, "Binary")
+            .token("operator", operator)
+            .expr("left", *left)
+            .expr("right", *right)
+            .end(),
+        Expr::Assign { name, value } => node(out, "Assign")
+            .token("name", name)
+            .expr("value", *value)
+            .end(),
+        Expr::Call {
+            callee, arguments, ..
+        } => node(out, "Call")
+            .expr("callee", *callee)
+            .exprs("arguments", arguments)
+            .end(),
+        Expr::Get { object, name } => node(out, "Get")
+            .expr("object", *object)
+            .token("name", name)
+            .end(),
+        Expr::Grouping { expression } => {
+            node(out, "Grouping").expr("expression", *expression).end()
+        }
+        Expr::Index { object, index, .. } => node(out, "Index")
+            .expr("object", *object)
+            .expr("index", *index)
+            .end(),
+        Expr::Lit(t) => node(out, "Literal").raw("value", &literal(t)).end(),
+        Expr::List { elements, .. } => node(out, "List").exprs("elements", elements).end(),
+        Expr::Logical {
+            left,
+            operator,
+            right,
+        } => node(out, "Logical")
+            .token("operator", operator)
+            .expr("left", *left)
+            .expr("right", *right)
+            .end(),
+        // Keys and values line up by position
+        Expr::Map { entries, .. } => {
+            let (keys, values): (Vec<ExprId>, Vec<ExprId>) = entries.iter().copied().unzip();
+            node(out, "Map")
+                .exprs("keys", &keys)
+                .exprs("values", &values)
+                .end()
+        }
+        Expr::Set {
+            object,
+            name,
+            value,
+        } => node(out, "Set")
+            .expr("object", *object)
+            .token("name", name)
+            .expr("value", *value)
+            .end(),
+        Expr::SetIndex {
+            object,
+            index,
+            value,
+            ..
+        } => node(out, "SetIndex")
+            .expr("object", *object)
+            .expr("index", *index)
+            .expr("value", *value)
+            .end(),
+        Expr::Slice {
+            object, start, end, ..
+        } => node(out, "Slice")
+            .expr("object", *object)
+            .opt_expr("start", *start)
+            .opt_expr("end", *end)
+            .end(),
+        Expr::Super { method, .. } => node(out, "Super").token("method", method).end(),
+        Expr::This { .. } => node(out, "This").end(),
+        Expr::Unary { operator, right } => node(out, "Unary")
+            .token("operator", operator)
+            .expr("right", *right)
+            .end(),
+        Expr::Variable { name } => node(out, "Variable").token("name", name).end(),
+    }
+}
+
+fn literal(token: &Token) -> String {
+    match &token.kind {
+        TokenType::Literal(LiteralKind::Number(n)) => n.to_string(),
+        TokenType::Literal(LiteralKind::Str(s)) => {
+            let mut out = String::new();
+            write_string(&mut out, s);
+            out
+        }
+        TokenType::True => "true".to_string(),
+        TokenType::False => "false".to_string(),
+        _ => "null".to_string(),
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn to_json(src: &str) -> String {
+        let tokens = Scanner::new(src).scan_tokens().to_vec();
+        program(&Parser::new(tokens).parse().unwrap())
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(
+            to_json("print -1 + \"a\\b\";"),
+            concat!(
+                r#"[{"type":"Print","line":1,"expression":"#,
+                r#"{"type":"Binary","line":1,"id":3,"operator":"+","#,
+                r#""left":{"type":"Unary","line":1,"id":1,"operator":"-","#,
+                r#""right":{"type":"Literal","line":1,"id":0,"value":1}},"#,
+                r#""right":{"type":"Literal","line":1,"id":2,"value":"a\\b"}}}]"#
+            )
+        );
+        assert_eq!(
+            to_json("var x;\nfun f(a) { return; }"),
+            concat!(
+                r#"[{"type":"Var","line":1,"name":"x","initializer":null},"#,
+                r#"{"type":"Function","line":2,"name":"f","params":["a"],"#,
+                r#""body":[{"type":"Return","line":2,"value":null}]}]"#
+            )
+        );
+    }
+}
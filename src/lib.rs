@@ -0,0 +1,38 @@
+pub mod ast;
+pub mod capability;
+pub mod class;
+pub mod error;
+pub mod format;
+pub mod function;
+pub mod gc;
+pub mod highlight;
+pub mod incremental;
+pub mod interpreter;
+pub mod json;
+pub mod limits;
+pub mod lint;
+pub mod loxc;
+pub mod lsp;
+pub mod map;
+pub mod output;
+pub mod parser;
+pub mod resolver;
+pub mod scanner;
+pub mod symbols;
+pub mod token;
+pub mod value;
+pub mod visit;
+
+mod environment;
+mod list;
+mod natives;
+
+pub use capability::{Capabilities, Capability};
+pub use class::NativeClass;
+pub use error::{Error, Limit, RuntimeError, SyntaxError};
+pub use function::Arity;
+pub use gc::{GcOptions, GcStats};
+pub use interpreter::Interpreter;
+pub use limits::{InterruptHandle, Limits};
+pub use output::OutputBuffer;
+pub use value::{FromValue, IntoValue, Value};
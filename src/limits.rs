@@ -0,0 +1,59 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Bounds on a single run of the interpreter, i.e. one `eval_str`,
+// `run_file` or `interpret` call. `None` means unlimited. By default only
+// the stack is bounded, so runaway recursion is a runtime error rather than
+// a crash.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    // Expressions evaluated
+    pub max_steps: Option<u64>,
+    // Approximate bytes of live strings, scopes, functions, classes,
+    // instances, lists and maps, including what earlier runs left in
+    // globals. Allocations are counted as they happen; once they pass the
+    // limit, garbage is collected and the heap that's left is measured, and
+    // only if that's still over is it an error. Setting this makes every
+    // scope known to the collector, which slows calls down a little.
+    pub max_heap_bytes: Option<usize>,
+    // Nested calls to Lox functions, methods and classes
+    pub max_call_depth: Option<usize>,
+    // Native stack used by the interpreter, counted from where the run
+    // started. Keep this well below the size of the thread's stack; threads
+    // spawned by Rust get 2 MiB unless asked for more.
+    pub max_stack_bytes: Option<usize>,
+    // Wall-clock time
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_steps: None,
+            max_heap_bytes: None,
+            max_call_depth: Some(1000),
+            max_stack_bytes: Some(1 << 20),
+            timeout: None,
+        }
+    }
+}
+
+// Lets another thread stop a running script. The script sees a
+// `LimitExceeded` error at the next expression it evaluates. An interrupt
+// sent while nothing is running is forgotten when the next run starts.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle {
+    flag: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    // Clears the request so later runs aren't affected
+    pub(crate) fn take(&self) -> bool {
+        self.flag.swap(false, Ordering::Relaxed)
+    }
+}
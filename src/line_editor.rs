@@ -0,0 +1,603 @@
+// A small line editor for the REPL: cursor movement, history, reverse
+// search and tab completion. Only used when stdin is a terminal; anything
+// else gets plain buffered reads.
+
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::path::PathBuf;
+
+const HISTORY_FILE: &str = ".luxor_history";
+const MAX_HISTORY: usize = 1000;
+
+pub struct LineEditor {
+    history: Vec<String>,
+    history_path: Option<PathBuf>,
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        let history_path =
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+
+        let mut history: Vec<String> = history_path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|s| s.lines().map(String::from).collect())
+            .unwrap_or_default();
+        if history.len() > MAX_HISTORY {
+            history.drain(..history.len() - MAX_HISTORY);
+        }
+
+        LineEditor {
+            history,
+            history_path,
+        }
+    }
+
+    // Read one line without its terminator, `None` at EOF. Ctrl-C abandons
+    // the line with an `Interrupted` error. `words` are offered as tab
+    // completions.
+    pub fn read_line(&mut self, prompt: &str, words: &[String]) -> io::Result<Option<String>> {
+        let line = match terminal::RawMode::enable() {
+            Some(_raw) => {
+                let stdin = io::stdin();
+                let mut input = stdin.lock();
+                self.edit(&mut input, &mut io::stdout(), prompt, words)?
+            }
+            None => {
+                print!("{}", prompt);
+                io::stdout().flush()?;
+
+                let mut line = String::new();
+                if io::stdin().lock().read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                Some(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+            }
+        };
+
+        if let Some(line) = &line {
+            self.add_history(line);
+        }
+        Ok(line)
+    }
+
+    fn add_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().map(String::as_str) == Some(line) {
+            return;
+        }
+
+        self.history.push(line.to_string());
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+
+        self.save_history();
+    }
+
+    // Rewritten whole rather than appended to, so the file keeps only the
+    // last `MAX_HISTORY` lines too. History is a convenience, losing it
+    // isn't worth an error.
+    fn save_history(&self) {
+        if let Some(path) = &self.history_path {
+            let mut contents = self.history.join("\n");
+            contents.push('\n');
+            let _ = fs::write(path, contents);
+        }
+    }
+
+    fn edit<R: Read, W: Write>(
+        &self,
+        input: &mut R,
+        out: &mut W,
+        prompt: &str,
+        words: &[String],
+    ) -> io::Result<Option<String>> {
+        let mut buf = LineBuffer::default();
+        // Position in history while browsing, `history.len()` is the line
+        // being typed, which is stashed away meanwhile
+        let mut browsing = self.history.len();
+        let mut stash = String::new();
+
+        buf.render(out, prompt)?;
+
+        while let Some(key) = read_key(input)? {
+            match key {
+                Key::Enter => {
+                    write!(out, "\r\n")?;
+                    return Ok(Some(buf.text()));
+                }
+                Key::Ctrl('c') => {
+                    write!(out, "^C\r\n")?;
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                Key::Ctrl('d') if buf.chars.is_empty() => {
+                    write!(out, "\r\n")?;
+                    return Ok(None);
+                }
+                Key::Ctrl('d') | Key::Delete => buf.delete(),
+                Key::Backspace => buf.backspace(),
+                Key::Left | Key::Ctrl('b') => buf.cursor = buf.cursor.saturating_sub(1),
+                Key::Right | Key::Ctrl('f') => buf.cursor = (buf.cursor + 1).min(buf.chars.len()),
+                Key::Home | Key::Ctrl('a') => buf.cursor = 0,
+                Key::End | Key::Ctrl('e') => buf.cursor = buf.chars.len(),
+                Key::Ctrl('k') => buf.chars.truncate(buf.cursor),
+                Key::Ctrl('u') => {
+                    buf.chars.drain(..buf.cursor);
+                    buf.cursor = 0;
+                }
+                Key::Ctrl('w') => buf.delete_word(),
+                Key::Up if browsing > 0 => {
+                    if browsing == self.history.len() {
+                        stash = buf.text();
+                    }
+                    browsing -= 1;
+                    buf.set(&self.history[browsing]);
+                }
+                Key::Down if browsing < self.history.len() => {
+                    browsing += 1;
+                    match self.history.get(browsing) {
+                        Some(line) => buf.set(line),
+                        None => buf.set(&stash),
+                    }
+                }
+                Key::Tab => {
+                    let candidates = buf.complete(words);
+                    if candidates.len() > 1 {
+                        write!(out, "\r\n{}\r\n", candidates.join("  "))?;
+                    }
+                }
+                Key::Ctrl('r') => match self.reverse_search(input, out)? {
+                    Search::Accept(line) => buf.set(&line),
+                    Search::Submit(line) => {
+                        write!(out, "\r\n")?;
+                        return Ok(Some(line));
+                    }
+                    Search::Cancel => (),
+                },
+                Key::Char(c) => buf.insert(c),
+                _ => (),
+            }
+
+            buf.render(out, prompt)?;
+        }
+
+        // Input closed mid-line
+        Ok(if buf.chars.is_empty() {
+            None
+        } else {
+            Some(buf.text())
+        })
+    }
+
+    // Ctrl-R: search history backwards for lines containing the query.
+    // Ctrl-R again moves to the next older match.
+    fn reverse_search<R: Read, W: Write>(&self, input: &mut R, out: &mut W) -> io::Result<Search> {
+        let mut query = String::new();
+        // Index just past the history entry being shown
+        let mut from = self.history.len();
+        let mut found: Option<usize> = None;
+
+        loop {
+            let shown = found.map_or("", |i| self.history[i].as_str());
+            write!(out, "\r(reverse-i-search)`{}': {}\x1b[K", query, shown)?;
+            out.flush()?;
+
+            let key = match read_key(input)? {
+                Some(key) => key,
+                None => return Ok(Search::Cancel),
+            };
+            match key {
+                Key::Char(c) => {
+                    query.push(c);
+                    from = self.history.len();
+                }
+                Key::Backspace => {
+                    query.pop();
+                    from = self.history.len();
+                }
+                Key::Ctrl('r') => from = found.unwrap_or(from),
+                Key::Enter => {
+                    return Ok(
+                        found.map_or(Search::Cancel, |i| Search::Submit(self.history[i].clone()))
+                    )
+                }
+                Key::Ctrl('g') | Key::Ctrl('c') | Key::Escape => return Ok(Search::Cancel),
+                _ => {
+                    return Ok(
+                        found.map_or(Search::Cancel, |i| Search::Accept(self.history[i].clone()))
+                    )
+                }
+            }
+
+            found = self.history[..from]
+                .iter()
+                .rposition(|line| line.contains(&query))
+                .or(if query.is_empty() { None } else { found });
+        }
+    }
+}
+
+enum Search {
+    Accept(String),
+    Submit(String),
+    Cancel,
+}
+
+#[derive(Debug, Default)]
+struct LineBuffer {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl LineBuffer {
+    fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    fn set(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    fn insert(&mut self, c: char) {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    // Delete back to the start of the previous word
+    fn delete_word(&mut self) {
+        let mut start = self.cursor;
+        while start > 0 && self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        self.chars.drain(start..self.cursor);
+        self.cursor = start;
+    }
+
+    // Complete the identifier before the cursor as far as all matching
+    // `words` agree, returning those candidates
+    fn complete(&mut self, words: &[String]) -> Vec<String> {
+        let mut start = self.cursor;
+        while start > 0 && is_ident_char(self.chars[start - 1]) {
+            start -= 1;
+        }
+        let prefix: String = self.chars[start..self.cursor].iter().collect();
+        if prefix.is_empty() {
+            return vec![];
+        }
+
+        let mut candidates: Vec<String> = words
+            .iter()
+            .filter(|w| w.starts_with(&prefix))
+            .cloned()
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+
+        if let Some(first) = candidates.first() {
+            let common = candidates.iter().fold(first.len(), |len, w| {
+                first
+                    .chars()
+                    .zip(w.chars())
+                    .take(len)
+                    .take_while(|(a, b)| a == b)
+                    .count()
+            });
+            for c in first
+                .chars()
+                .skip(prefix.chars().count())
+                .take(common - prefix.chars().count())
+            {
+                self.insert(c);
+            }
+        }
+
+        candidates
+    }
+
+    fn render<W: Write>(&self, out: &mut W, prompt: &str) -> io::Result<()> {
+        // Redraw the whole line, then put the cursor back in place
+        write!(out, "\r{}{}\x1b[K\r", prompt, self.text())?;
+        let column = prompt.chars().count() + self.cursor;
+        if column > 0 {
+            write!(out, "\x1b[{}C", column)?;
+        }
+        out.flush()
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Char(char),
+    Ctrl(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Unknown,
+}
+
+fn read_byte<R: Read>(input: &mut R) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Decode one keypress, including UTF-8 characters and the VT100 escape
+// sequences terminals send for arrows, Home, End and Delete
+fn read_key<R: Read>(input: &mut R) -> io::Result<Option<Key>> {
+    let byte = match read_byte(input)? {
+        Some(b) => b,
+        None => return Ok(None),
+    };
+
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f | 0x08 => Key::Backspace,
+        0x1b => match read_byte(input)? {
+            Some(b'[') | Some(b'O') => match read_byte(input)? {
+                Some(b'A') => Key::Up,
+                Some(b'B') => Key::Down,
+                Some(b'C') => Key::Right,
+                Some(b'D') => Key::Left,
+                Some(b'H') => Key::Home,
+                Some(b'F') => Key::End,
+                Some(d @ b'0'..=b'9') => match read_byte(input)? {
+                    Some(b'~') => match d {
+                        b'1' | b'7' => Key::Home,
+                        b'4' | b'8' => Key::End,
+                        b'3' => Key::Delete,
+                        _ => Key::Unknown,
+                    },
+                    _ => Key::Unknown,
+                },
+                _ => Key::Unknown,
+            },
+            _ => Key::Escape,
+        },
+        0x01..=0x1a => Key::Ctrl((b'a' + byte - 1) as char),
+        0x00..=0x1f => Key::Unknown,
+        _ => {
+            // Gather the rest of a multi-byte UTF-8 sequence
+            let len = match byte {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                match read_byte(input)? {
+                    Some(b) => bytes.push(b),
+                    None => break,
+                }
+            }
+            match std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|s| s.chars().next())
+            {
+                Some(c) => Key::Char(c),
+                None => Key::Unknown,
+            }
+        }
+    };
+
+    Ok(Some(key))
+}
+
+#[cfg(target_os = "linux")]
+mod terminal {
+    use std::os::raw::{c_int, c_uint};
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Termios {
+        c_iflag: c_uint,
+        c_oflag: c_uint,
+        c_cflag: c_uint,
+        c_lflag: c_uint,
+        c_line: u8,
+        c_cc: [u8; 32],
+        c_ispeed: c_uint,
+        c_ospeed: c_uint,
+    }
+
+    extern "C" {
+        fn isatty(fd: c_int) -> c_int;
+        fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+        fn tcsetattr(fd: c_int, optional_actions: c_int, termios: *const Termios) -> c_int;
+    }
+
+    const STDIN: c_int = 0;
+    const STDOUT: c_int = 1;
+    const TCSAFLUSH: c_int = 2;
+
+    const BRKINT: c_uint = 0o2;
+    const INPCK: c_uint = 0o20;
+    const ISTRIP: c_uint = 0o40;
+    const ICRNL: c_uint = 0o400;
+    const IXON: c_uint = 0o2000;
+    const OPOST: c_uint = 0o1;
+    const CS8: c_uint = 0o60;
+    const ISIG: c_uint = 0o1;
+    const ICANON: c_uint = 0o2;
+    const ECHO: c_uint = 0o10;
+    const IEXTEN: c_uint = 0o100000;
+    const VTIME: usize = 5;
+    const VMIN: usize = 6;
+
+    // Puts the terminal in raw mode for as long as it's alive
+    pub struct RawMode {
+        original: Termios,
+    }
+
+    impl RawMode {
+        // `None` unless both stdin and stdout are terminals
+        pub fn enable() -> Option<RawMode> {
+            // Safe: plain libc calls on the standard descriptors, with a
+            // properly laid out termios struct for them to fill in
+            unsafe {
+                if isatty(STDIN) == 0 || isatty(STDOUT) == 0 {
+                    return None;
+                }
+
+                let mut original: Termios = std::mem::zeroed();
+                if tcgetattr(STDIN, &mut original) != 0 {
+                    return None;
+                }
+
+                let mut raw = original;
+                raw.c_iflag &= !(BRKINT | ICRNL | INPCK | ISTRIP | IXON);
+                raw.c_oflag &= !OPOST;
+                raw.c_cflag |= CS8;
+                raw.c_lflag &= !(ECHO | ICANON | IEXTEN | ISIG);
+                raw.c_cc[VMIN] = 1;
+                raw.c_cc[VTIME] = 0;
+                if tcsetattr(STDIN, TCSAFLUSH, &raw) != 0 {
+                    return None;
+                }
+
+                Some(RawMode { original })
+            }
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            // Safe: restores the settings read in `enable`
+            unsafe {
+                tcsetattr(STDIN, TCSAFLUSH, &self.original);
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod terminal {
+    pub struct RawMode;
+
+    impl RawMode {
+        pub fn enable() -> Option<RawMode> {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor(history: &[&str]) -> LineEditor {
+        LineEditor {
+            history: history.iter().map(|s| s.to_string()).collect(),
+            history_path: None,
+        }
+    }
+
+    fn type_keys(editor: &LineEditor, keys: &str, words: &[&str]) -> Option<String> {
+        let words: Vec<String> = words.iter().map(|s| s.to_string()).collect();
+        let mut output = Vec::new();
+        editor
+            .edit(&mut keys.as_bytes(), &mut output, "> ", &words)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_editing() {
+        let e = editor(&[]);
+        // Left twice, insert, End, backspace
+        assert_eq!(
+            type_keys(
+                &e,
+                "prnt 12\x1b[D\x1b[D\x1b[D\x1b[D\x1b[Di\x1b[F\x7f;\r",
+                &[]
+            ),
+            Some("print 1;".to_string())
+        );
+        // Ctrl-A then Ctrl-K clears everything
+        assert_eq!(
+            type_keys(&e, "junk\x01\x0bok\r", &[]),
+            Some("ok".to_string())
+        );
+        assert_eq!(type_keys(&e, "\x04", &[]), None);
+    }
+
+    #[test]
+    fn test_history() {
+        let e = editor(&["var a = 1;", "print a;"]);
+        assert_eq!(
+            type_keys(&e, "\x1b[A\x1b[A\r", &[]),
+            Some("var a = 1;".to_string())
+        );
+        assert_eq!(type_keys(&e, "x\x1b[A\x1b[B\r", &[]), Some("x".to_string()));
+        // Reverse search for "var"
+        assert_eq!(
+            type_keys(&e, "\x12var\r", &[]),
+            Some("var a = 1;".to_string())
+        );
+    }
+
+    #[test]
+    fn test_history_file() {
+        let path = std::env::temp_dir().join(format!("luxor_history_{}", std::process::id()));
+        let lines: Vec<String> = (0..MAX_HISTORY).map(|i| format!("print {};", i)).collect();
+        let mut e = LineEditor {
+            history: lines.clone(),
+            history_path: Some(path.clone()),
+        };
+        e.add_history("print \"last\";");
+
+        let saved = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let saved: Vec<&str> = saved.lines().collect();
+        assert_eq!(saved.len(), MAX_HISTORY);
+        assert_eq!(saved[0], lines[1]);
+        assert_eq!(saved[MAX_HISTORY - 1], "print \"last\";");
+    }
+
+    #[test]
+    fn test_completion() {
+        let e = editor(&[]);
+        let words = ["print", "counter", "count"];
+        assert_eq!(
+            type_keys(&e, "pr\t 1;\r", &words),
+            Some("print 1;".to_string())
+        );
+        assert_eq!(type_keys(&e, "co\tx\r", &words), Some("countx".to_string()));
+    }
+}
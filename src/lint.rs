@@ -0,0 +1,622 @@
+// Lint pass behind `luxor lint`: warnings about code that runs but is
+// probably not what was meant.
+//
+// Every rule is on by default. A `luxor.toml` can turn rules off (or back
+// on) in its `[lint]` section:
+//
+//   [lint]
+//   shadowed-variable = false
+//
+// and a `// lint:allow(rule, ...)` comment silences rules for one line: the
+// line it ends, or the next line when the comment has a line to itself.
+// Variables and parameters whose name starts with `_` are never reported as
+// unused.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::ast::{Expr, ExprArena, ExprId, Function, Stmt};
+use crate::error::{write_diagnostic, SyntaxError};
+use crate::parser::Parser;
+use crate::scanner::{Comment, Scanner};
+use crate::token::{Token, TokenType};
+use crate::visit::{walk_expr, walk_stmt, Visitor};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    UnusedVariable,
+    UnusedParameter,
+    UnreachableCode,
+    ShadowedVariable,
+    ConstantCondition,
+    SelfComparison,
+    AssignmentInCondition,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 7] = [
+        Rule::UnusedVariable,
+        Rule::UnusedParameter,
+        Rule::UnreachableCode,
+        Rule::ShadowedVariable,
+        Rule::ConstantCondition,
+        Rule::SelfComparison,
+        Rule::AssignmentInCondition,
+    ];
+
+    // As written in `luxor.toml` and `lint:allow`
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::UnusedVariable => "unused-variable",
+            Rule::UnusedParameter => "unused-parameter",
+            Rule::UnreachableCode => "unreachable-code",
+            Rule::ShadowedVariable => "shadowed-variable",
+            Rule::ConstantCondition => "constant-condition",
+            Rule::SelfComparison => "self-comparison",
+            Rule::AssignmentInCondition => "assignment-in-condition",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Rule> {
+        Rule::ALL.iter().copied().find(|rule| rule.name() == name)
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub rule: Rule,
+    pub line: u32,
+    pub location: String,
+    pub message: String,
+}
+
+impl Lint {
+    fn new(rule: Rule, line: u32, location: &str, message: &str) -> Self {
+        Lint {
+            rule,
+            line,
+            location: location.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    fn at(rule: Rule, token: &Token, message: &str) -> Self {
+        Lint::new(rule, token.line, &format!(" at '{}'", token), message)
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_diagnostic(f, "Warning", self.line, &self.location, &self.message)?;
+        write!(f, " [{}]", self.rule)
+    }
+}
+
+// Which rules run
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    disabled: HashSet<Rule>,
+}
+
+impl Config {
+    // Reads the `[lint]` section of a `luxor.toml`. Only the small part of
+    // TOML it needs is understood: `[section]` headers, `rule = true|false`
+    // and `#` comments. Other sections are left for other tools.
+    pub fn parse(src: &str) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        let mut in_lint = false;
+
+        for (i, line) in src.lines().enumerate() {
+            let line_no = i as u32 + 1;
+            let error = |message: String| {
+                Err(ConfigError {
+                    line: line_no,
+                    message,
+                })
+            };
+            let line = match line.find('#') {
+                Some(at) => &line[..at],
+                None => line,
+            }
+            .trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(section) = line.strip_prefix('[') {
+                match section.strip_suffix(']') {
+                    Some(section) => in_lint = section.trim() == "lint",
+                    None => return error("Expect ']' after section name.".to_string()),
+                }
+                continue;
+            }
+
+            let (key, value) = match line.find('=') {
+                Some(at) => (line[..at].trim(), line[at + 1..].trim()),
+                None => return error("Expect '=' after key.".to_string()),
+            };
+            if !in_lint {
+                continue;
+            }
+            let rule = match Rule::from_name(key) {
+                Some(rule) => rule,
+                None => return error(format!("Unknown lint rule '{}'.", key)),
+            };
+            match value {
+                "true" => config.enable(rule),
+                "false" => config.disable(rule),
+                _ => return error(format!("Expect true or false for '{}'.", key)),
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn enable(&mut self, rule: Rule) {
+        self.disabled.remove(&rule);
+    }
+
+    pub fn disable(&mut self, rule: Rule) {
+        self.disabled.insert(rule);
+    }
+
+    pub fn is_enabled(&self, rule: Rule) -> bool {
+        !self.disabled.contains(&rule)
+    }
+}
+
+// Where a `Config` is read from
+pub const CONFIG_FILE: &str = "luxor.toml";
+
+// The nearest `luxor.toml`, in `dir` or one of its parents
+pub fn find_config(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub line: u32,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_diagnostic(f, "Error", self.line, "", &self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// Lints for a script, in line order. Scripts that don't parse give their
+// syntax errors instead.
+pub fn lint(src: &str, config: &Config) -> Result<Vec<Lint>, Vec<SyntaxError>> {
+    let mut scanner = Scanner::new(src);
+    let tokens = scanner.scan_tokens().to_vec();
+    let allowed = allowed_rules(&tokens, scanner.comments());
+    let program = Parser::new(tokens).parse()?;
+
+    let mut linter = Linter {
+        lints: Vec::new(),
+        scopes: vec![Vec::new()],
+        unresolved: HashSet::new(),
+    };
+    linter.statements(&program.exprs, &program.statements);
+    linter.end_globals();
+
+    let mut lints: Vec<Lint> = linter
+        .lints
+        .into_iter()
+        .filter(|lint| config.is_enabled(lint.rule))
+        .filter(|lint| {
+            allowed
+                .get(&lint.line)
+                .is_none_or(|rules| !rules.contains(&lint.rule))
+        })
+        .collect();
+    lints.sort_by_key(|lint| lint.line);
+    Ok(lints)
+}
+
+// Rules silenced by `lint:allow` comments, by line
+fn allowed_rules(tokens: &[Token], comments: &[Comment]) -> HashMap<u32, Vec<Rule>> {
+    let mut allowed: HashMap<u32, Vec<Rule>> = HashMap::new();
+    for comment in comments {
+        let names = match comment.text.find("lint:allow(") {
+            Some(at) => &comment.text[at + "lint:allow(".len()..],
+            None => continue,
+        };
+        let names = names.split(')').next().unwrap_or("");
+
+        // A comment after code covers that code, one on its own covers the
+        // line below it
+        let after_code =
+            comment.token_index > 0 && tokens[comment.token_index - 1].line == comment.line;
+        let line = if after_code {
+            comment.line
+        } else {
+            tokens[comment.token_index].line
+        };
+        allowed.entry(line).or_default().extend(
+            names
+                .split(',')
+                .filter_map(|name| Rule::from_name(name.trim())),
+        );
+    }
+    allowed
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Variable,
+    Parameter,
+    // Functions and classes: they shadow and are shadowed, but are never
+    // reported as unused
+    Declaration,
+}
+
+struct Binding {
+    name: Token,
+    kind: Kind,
+    used: bool,
+}
+
+struct Linter {
+    lints: Vec<Lint>,
+    // Innermost last; the first is the global scope
+    scopes: Vec<Vec<Binding>>,
+    // Names read where no declaration was in scope. Globals are looked up
+    // when the code runs, so these may be declared further down.
+    unresolved: HashSet<String>,
+}
+
+impl Linter {
+    fn begin_scope(&mut self) {
+        self.scopes.push(Vec::new());
+    }
+
+    fn end_scope(&mut self) {
+        if let Some(scope) = self.scopes.pop() {
+            self.report_unused(scope);
+        }
+    }
+
+    fn end_globals(&mut self) {
+        let mut globals = std::mem::take(&mut self.scopes[0]);
+        for binding in &mut globals {
+            if self.unresolved.contains(binding.name.name()) {
+                binding.used = true;
+            }
+        }
+        self.report_unused(globals);
+    }
+
+    fn report_unused(&mut self, scope: Vec<Binding>) {
+        for binding in scope {
+            if binding.used || binding.name.name().starts_with('_') {
+                continue;
+            }
+            match binding.kind {
+                Kind::Variable => self.lints.push(Lint::at(
+                    Rule::UnusedVariable,
+                    &binding.name,
+                    "Variable is never used.",
+                )),
+                Kind::Parameter => self.lints.push(Lint::at(
+                    Rule::UnusedParameter,
+                    &binding.name,
+                    "Parameter is never used.",
+                )),
+                Kind::Declaration => {}
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &Token, kind: Kind) {
+        let (current, enclosing) = self.scopes.split_last_mut().unwrap();
+        let shadows = enclosing
+            .iter()
+            .any(|scope| scope.iter().any(|b| b.name.name() == name.name()));
+        current.push(Binding {
+            name: name.clone(),
+            kind,
+            used: false,
+        });
+        if shadows {
+            self.lints.push(Lint::at(
+                Rule::ShadowedVariable,
+                name,
+                "Shadows a variable in an enclosing scope.",
+            ));
+        }
+    }
+
+    fn read(&mut self, name: &Token) {
+        let binding = self.scopes.iter_mut().rev().find_map(|scope| {
+            scope
+                .iter_mut()
+                .rev()
+                .find(|b| b.name.name() == name.name())
+        });
+        match binding {
+            Some(binding) => binding.used = true,
+            None => {
+                self.unresolved.insert(name.name().to_string());
+            }
+        }
+    }
+
+    // A statement list, where anything after a `return`, `break` or
+    // `continue` can't run
+    fn statements(&mut self, exprs: &ExprArena, statements: &[Stmt]) {
+        let mut jumped = None;
+        for stmt in statements {
+            if let Some(keyword) = jumped.take() {
+                self.lints.push(Lint::new(
+                    Rule::UnreachableCode,
+                    stmt.line(exprs),
+                    "",
+                    &format!("Unreachable code after '{}'.", keyword),
+                ));
+            } else if let Stmt::Return { keyword, .. }
+            | Stmt::Break { keyword }
+            | Stmt::Continue { keyword } = stmt
+            {
+                jumped = Some(keyword);
+            }
+            self.visit_stmt(exprs, stmt);
+        }
+    }
+
+    fn check_assignment(&mut self, exprs: &ExprArena, condition: ExprId) {
+        match &exprs[condition] {
+            Expr::Assign { name, .. } | Expr::Set { name, .. } => self.lints.push(Lint::at(
+                Rule::AssignmentInCondition,
+                name,
+                "Assignment in condition; did you mean '=='?",
+            )),
+            _ => {}
+        }
+    }
+}
+
+impl Visitor for Linter {
+    fn visit_stmt(&mut self, exprs: &ExprArena, stmt: &Stmt) {
+        match stmt {
+            Stmt::Block { statements, .. } => {
+                self.begin_scope();
+                self.statements(exprs, statements);
+                self.end_scope();
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                if let Some(superclass) = superclass {
+                    self.visit_expr(exprs, *superclass);
+                }
+                self.declare(name, Kind::Declaration);
+                for method in methods {
+                    self.visit_function(exprs, method);
+                }
+            }
+            Stmt::For { condition, .. } => {
+                if let Some(condition) = condition {
+                    self.check_assignment(exprs, *condition);
+                }
+                self.begin_scope();
+                walk_stmt(self, exprs, stmt);
+                self.end_scope();
+            }
+            Stmt::ForIn {
+                name,
+                iterable,
+                body,
+                ..
+            } => {
+                self.visit_expr(exprs, *iterable);
+                self.begin_scope();
+                self.declare(name, Kind::Variable);
+                self.visit_stmt(exprs, body);
+                self.end_scope();
+            }
+            Stmt::Function(function) => {
+                self.declare(&function.name, Kind::Declaration);
+                self.visit_function(exprs, function);
+            }
+            Stmt::If { condition, .. } => {
+                self.check_assignment(exprs, *condition);
+                if is_constant(exprs, *condition) {
+                    self.lints.push(Lint::new(
+                        Rule::ConstantCondition,
+                        exprs.line(*condition),
+                        "",
+                        "Condition of 'if' is always the same.",
+                    ));
+                }
+                walk_stmt(self, exprs, stmt);
+            }
+            Stmt::Var { name, initializer } => {
+                if let Some(initializer) = initializer {
+                    self.visit_expr(exprs, *initializer);
+                }
+                self.declare(name, Kind::Variable);
+            }
+            Stmt::While { condition, .. } => {
+                self.check_assignment(exprs, *condition);
+                walk_stmt(self, exprs, stmt);
+            }
+            _ => walk_stmt(self, exprs, stmt),
+        }
+    }
+
+    fn visit_expr(&mut self, exprs: &ExprArena, id: ExprId) {
+        match &exprs[id] {
+            // Assigning a variable doesn't count as using it
+            Expr::Variable { name } => self.read(name),
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } if is_comparison(operator) && same_place(exprs, *left, *right) => {
+                self.lints.push(Lint::at(
+                    Rule::SelfComparison,
+                    operator,
+                    "Comparing a value with itself.",
+                ));
+            }
+            _ => {}
+        }
+        walk_expr(self, exprs, id)
+    }
+
+    fn visit_function(&mut self, exprs: &ExprArena, function: &Function) {
+        self.begin_scope();
+        for param in &function.params {
+            self.declare(param, Kind::Parameter);
+        }
+        self.statements(exprs, &function.body);
+        self.end_scope();
+    }
+}
+
+// Built from literals alone, so it always evaluates the same
+fn is_constant(exprs: &ExprArena, id: ExprId) -> bool {
+    match &exprs[id] {
+        Expr::Lit(_) => true,
+        Expr::Grouping { expression }
+        | Expr::Unary {
+            right: expression, ..
+        } => is_constant(exprs, *expression),
+        Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+            is_constant(exprs, *left) && is_constant(exprs, *right)
+        }
+        _ => false,
+    }
+}
+
+fn is_comparison(operator: &Token) -> bool {
+    matches!(
+        operator.kind,
+        TokenType::EqualEqual
+            | TokenType::BangEqual
+            | TokenType::Less
+            | TokenType::LessEqual
+            | TokenType::Greater
+            | TokenType::GreaterEqual
+    )
+}
+
+// Both sides name the same variable or property. Anything that could have
+// side effects, like a call, doesn't count.
+fn same_place(exprs: &ExprArena, a: ExprId, b: ExprId) -> bool {
+    match (&exprs[a], &exprs[b]) {
+        (Expr::Variable { name: a }, Expr::Variable { name: b }) => a.name() == b.name(),
+        (Expr::This { .. }, Expr::This { .. }) => true,
+        (
+            Expr::Get { object, name },
+            Expr::Get {
+                object: object2,
+                name: name2,
+            },
+        ) => name.name() == name2.name() && same_place(exprs, *object, *object2),
+        (Expr::Grouping { expression }, _) => same_place(exprs, *expression, b),
+        (_, Expr::Grouping { expression }) => same_place(exprs, a, *expression),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lints(src: &str) -> Vec<String> {
+        lint(src, &Config::default())
+            .unwrap()
+            .iter()
+            .map(|lint| lint.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_rules() {
+        assert_eq!(
+            lints("var a = 1;\nfun f(x, _y) {\n  return 1;\n  print a;\n}\nf(1, 2);"),
+            vec![
+                "[line 2] Warning at 'x': Parameter is never used. [unused-parameter]",
+                "[line 4] Warning: Unreachable code after 'return'. [unreachable-code]",
+            ]
+        );
+        assert_eq!(
+            lints("var a = 1;\n{\n  var a = 2;\n  print a;\n}\nvar b;"),
+            vec![
+                "[line 1] Warning at 'a': Variable is never used. [unused-variable]",
+                "[line 3] Warning at 'a': Shadows a variable in an enclosing scope. [shadowed-variable]",
+                "[line 6] Warning at 'b': Variable is never used. [unused-variable]",
+            ]
+        );
+        assert_eq!(
+            lints("var x = 1;\nif (x == x) print 1;\nif (!(1 > 2)) print 2;\nwhile (x = false) {}"),
+            vec![
+                "[line 2] Warning at '==': Comparing a value with itself. [self-comparison]",
+                "[line 3] Warning: Condition of 'if' is always the same. [constant-condition]",
+                "[line 4] Warning at 'x': Assignment in condition; did you mean '=='? [assignment-in-condition]",
+            ]
+        );
+        assert_eq!(
+            lints("while (true) {\n  break;\n  print 1;\n}"),
+            vec!["[line 3] Warning: Unreachable code after 'break'. [unreachable-code]"]
+        );
+        assert_eq!(
+            lints("var x = [1];
+for (var x in x) print 1;"),
+            vec![
+                "[line 2] Warning at 'x': Shadows a variable in an enclosing scope. [shadowed-variable]",
+                "[line 2] Warning at 'x': Variable is never used. [unused-variable]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_globals_resolve_late() {
+        // `g` is declared after the function that reads it
+        assert!(lints("fun f() { return g; }\nvar g = 1;\nprint f();").is_empty());
+    }
+
+    #[test]
+    fn test_config() {
+        let config = Config::parse(
+            "# lints\n[fmt]\nwidth = 100\n\n[lint]\nunused-variable = false # noisy\n",
+        )
+        .unwrap();
+        assert!(!config.is_enabled(Rule::UnusedVariable));
+        assert!(config.is_enabled(Rule::ShadowedVariable));
+        assert!(lint("var a;", &config).unwrap().is_empty());
+
+        assert_eq!(
+            Config::parse("[lint]\nbogus = true")
+                .unwrap_err()
+                .to_string(),
+            "[line 2] Error: Unknown lint rule 'bogus'."
+        );
+        assert!(Config::parse("[lint]\nunused-variable = maybe").is_err());
+    }
+
+    #[test]
+    fn test_allow_comments() {
+        let src = "var a; // lint:allow(unused-variable)\n// lint:allow(unused-variable, shadowed-variable)\nvar b;\nvar c;";
+        assert_eq!(
+            lints(src),
+            vec!["[line 4] Warning at 'c': Variable is never used. [unused-variable]"]
+        );
+    }
+}
@@ -0,0 +1,292 @@
+// Lists: indexing, slicing and the methods scripts can call on them
+
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+use crate::error::RuntimeError;
+use crate::function::{Arity, NativeFunction};
+use crate::interpreter::Interpreter;
+use crate::value::Value;
+
+type List = Rc<RefCell<Vec<Value>>>;
+
+// The element at `index`, counting back from the end when it's negative
+pub(crate) fn get(values: &[Value], index: &Value) -> Result<Value, RuntimeError> {
+    let i = element(index, values.len())?;
+    Ok(values[i].clone())
+}
+
+pub(crate) fn set(values: &mut [Value], index: &Value, value: Value) -> Result<(), RuntimeError> {
+    let i = element(index, values.len())?;
+    values[i] = value;
+    Ok(())
+}
+
+// The elements from `start` up to but not including `end`. Missing or nil
+// bounds mean the whole way to that end, and bounds past either end are
+// clamped, so slicing never fails on a valid integer.
+pub(crate) fn slice(
+    values: &[Value],
+    start: Option<&Value>,
+    end: Option<&Value>,
+) -> Result<Vec<Value>, RuntimeError> {
+    let bound = |value: Option<&Value>, default: usize| -> Result<usize, RuntimeError> {
+        match value {
+            None | Some(Value::Nil) => Ok(default),
+            Some(value) => {
+                let i = from_end(integer(value, "Slice bound")?, values.len());
+                Ok(i.clamp(0, values.len() as i64) as usize)
+            }
+        }
+    };
+    let start = bound(start, 0)?;
+    let end = bound(end, values.len())?;
+    Ok(values[start..end.max(start)].to_vec())
+}
+
+// The method `name` bound to `list`, `None` if lists have no such method
+pub(crate) fn method(list: &List, name: &str) -> Option<Value> {
+    let arity = match name {
+        "len" | "pop" | "reverse" => Arity::Exact(0),
+        "contains" | "filter" | "index_of" | "map" | "push" | "remove" => Arity::Exact(1),
+        "insert" => Arity::Exact(2),
+        "sort" => Arity::Between(0, 1),
+        "reduce" => Arity::Between(1, 2),
+        _ => return None,
+    };
+
+    let list = Rc::clone(list);
+    let method = name.to_string();
+    let function = NativeFunction::with_callbacks(name, arity, move |interpreter, args| {
+        call(interpreter, &list, &method, args)
+    });
+    Some(Value::Native(Rc::new(function)))
+}
+
+// Callbacks run on a copy of the elements, so they're free to change the
+// list itself
+fn call(
+    interpreter: &mut Interpreter,
+    list: &List,
+    method: &str,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    match method {
+        "len" => Ok(Value::Number(list.borrow().len() as f64)),
+        "push" => {
+            interpreter.allocate(mem::size_of::<Value>())?;
+            list.borrow_mut().push(args[0].clone());
+            Ok(Value::Nil)
+        }
+        "pop" => list
+            .borrow_mut()
+            .pop()
+            .ok_or_else(|| "Can't pop from an empty list.".into()),
+        "insert" => {
+            // Inserting at the length appends
+            let len = list.borrow().len();
+            let i = from_end(integer(&args[0], "List index")?, len);
+            if i < 0 || i > len as i64 {
+                return Err("List index out of bounds.".into());
+            }
+            interpreter.allocate(mem::size_of::<Value>())?;
+            list.borrow_mut().insert(i as usize, args[1].clone());
+            Ok(Value::Nil)
+        }
+        "remove" => {
+            let mut values = list.borrow_mut();
+            let i = element(&args[0], values.len())?;
+            Ok(values.remove(i))
+        }
+        "contains" => Ok(Value::Bool(list.borrow().contains(&args[0]))),
+        // nil when it isn't there
+        "index_of" => Ok(list
+            .borrow()
+            .iter()
+            .position(|value| *value == args[0])
+            .map_or(Value::Nil, |i| Value::Number(i as f64))),
+        "reverse" => {
+            list.borrow_mut().reverse();
+            Ok(Value::Nil)
+        }
+        "sort" => {
+            let values = list.borrow().clone();
+            let sorted = match args.first() {
+                Some(comparator) => merge_sort(values, &mut |a, b| {
+                    let args = vec![a.clone(), b.clone()];
+                    match interpreter.call(comparator.clone(), args, 0)? {
+                        Value::Number(n) => Ok(n < 0.0),
+                        _ => Err("Comparator must return a number.".into()),
+                    }
+                })?,
+                None => merge_sort(values, &mut |a, b| match (a, b) {
+                    (Value::Number(a), Value::Number(b)) => Ok(a < b),
+                    (Value::Str(a), Value::Str(b)) => Ok(a < b),
+                    _ => Err("Can only sort numbers or strings without a comparator.".into()),
+                })?,
+            };
+            *list.borrow_mut() = sorted;
+            Ok(Value::Nil)
+        }
+        "map" => {
+            let values = list.borrow().clone();
+            interpreter.allocate(mem::size_of::<Value>() * values.len())?;
+            let mut mapped = Vec::with_capacity(values.len());
+            for value in values {
+                mapped.push(interpreter.call(args[0].clone(), vec![value], 0)?);
+            }
+            Ok(Value::list(mapped))
+        }
+        "filter" => {
+            let values = list.borrow().clone();
+            let mut kept = Vec::new();
+            for value in values {
+                if interpreter
+                    .call(args[0].clone(), vec![value.clone()], 0)?
+                    .is_truthy()
+                {
+                    interpreter.allocate(mem::size_of::<Value>())?;
+                    kept.push(value);
+                }
+            }
+            Ok(Value::list(kept))
+        }
+        // Without an initial value, the first element is one
+        "reduce" => {
+            let mut values = list.borrow().clone().into_iter();
+            let mut acc = match args.get(1) {
+                Some(initial) => initial.clone(),
+                None => values
+                    .next()
+                    .ok_or("Can't reduce an empty list with no initial value.")?,
+            };
+            for value in values {
+                acc = interpreter.call(args[0].clone(), vec![acc, value], 0)?;
+            }
+            Ok(acc)
+        }
+        _ => unreachable!(),
+    }
+}
+
+// Stable, and finishes even when `less` isn't a consistent ordering, as a
+// script's comparator needn't be
+fn merge_sort<F>(mut values: Vec<Value>, less: &mut F) -> Result<Vec<Value>, RuntimeError>
+where
+    F: FnMut(&Value, &Value) -> Result<bool, RuntimeError>,
+{
+    if values.len() <= 1 {
+        return Ok(values);
+    }
+    let right = values.split_off(values.len() / 2);
+    let left = merge_sort(values, less)?;
+    let right = merge_sort(right, less)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        let next = if less(r, l)? { &mut right } else { &mut left };
+        merged.extend(next.next());
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+// Where `index` points in a list of `len` elements
+fn element(index: &Value, len: usize) -> Result<usize, RuntimeError> {
+    let i = from_end(integer(index, "List index")?, len);
+    if i < 0 || i >= len as i64 {
+        return Err("List index out of bounds.".into());
+    }
+    Ok(i as usize)
+}
+
+fn integer(value: &Value, what: &str) -> Result<i64, RuntimeError> {
+    match value {
+        Value::Number(n) if n.fract() == 0.0 => Ok(*n as i64),
+        _ => Err(format!("{} must be an integer.", what).into()),
+    }
+}
+
+// Negative indexes count back from the end
+fn from_end(index: i64, len: usize) -> i64 {
+    if index < 0 {
+        index + len as i64
+    } else {
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbers(values: &[f64]) -> Vec<Value> {
+        values.iter().map(|n| Value::Number(*n)).collect()
+    }
+
+    #[test]
+    fn test_index() {
+        let values = numbers(&[1.0, 2.0, 3.0]);
+        assert_eq!(get(&values, &Value::Number(0.0)), Ok(Value::Number(1.0)));
+        assert_eq!(get(&values, &Value::Number(-1.0)), Ok(Value::Number(3.0)));
+        assert_eq!(
+            get(&values, &Value::Number(3.0)),
+            Err("List index out of bounds.".into())
+        );
+        assert_eq!(
+            get(&values, &Value::Number(-4.0)),
+            Err("List index out of bounds.".into())
+        );
+        assert_eq!(
+            get(&values, &Value::Number(0.5)),
+            Err("List index must be an integer.".into())
+        );
+    }
+
+    #[test]
+    fn test_slice() {
+        let values = numbers(&[1.0, 2.0, 3.0, 4.0]);
+        let n = Value::Number;
+        assert_eq!(
+            slice(&values, Some(&n(1.0)), Some(&n(3.0))),
+            Ok(numbers(&[2.0, 3.0]))
+        );
+        assert_eq!(
+            slice(&values, None, Some(&n(-1.0))),
+            Ok(numbers(&[1.0, 2.0, 3.0]))
+        );
+        assert_eq!(
+            slice(&values, Some(&n(-2.0)), None),
+            Ok(numbers(&[3.0, 4.0]))
+        );
+        assert_eq!(
+            slice(&values, Some(&n(-9.0)), Some(&n(9.0))),
+            Ok(values.clone())
+        );
+        assert_eq!(slice(&values, Some(&n(3.0)), Some(&n(1.0))), Ok(vec![]));
+        assert_eq!(slice(&values, Some(&Value::Nil), None), Ok(values.clone()));
+    }
+
+    #[test]
+    fn test_merge_sort() {
+        let values = numbers(&[3.0, 1.0, 2.0, 1.0]);
+        let sorted = merge_sort(values.clone(), &mut |a, b| match (a, b) {
+            (Value::Number(a), Value::Number(b)) => Ok(a < b),
+            _ => unreachable!(),
+        });
+        assert_eq!(sorted, Ok(numbers(&[1.0, 1.0, 2.0, 3.0])));
+
+        // An ordering that contradicts itself still gives every element back
+        let mut flip = false;
+        let shuffled = merge_sort(values, &mut |_, _| {
+            flip = !flip;
+            Ok(flip)
+        })
+        .unwrap();
+        assert_eq!(shuffled.len(), 4);
+    }
+}
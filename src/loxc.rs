@@ -0,0 +1,1278 @@
+// Compiled scripts: the `.loxc` files `luxor compile` writes, which run
+// without being scanned or parsed again.
+//
+// There's no bytecode, so what's compiled is the parsed program: the
+// expression arena, every function and the top-level statements, laid out
+// flat. Counts, indexes and lines are LEB128 varints, other numbers are
+// little-endian:
+//
+//   magic      `MAGIC`
+//   version    u16, `VERSION`
+//   constants  count, then each a tag and its value: 0 and an f64, or 1 and
+//              a string as its length and UTF-8. Literals and names point
+//              here.
+//   lines      count of runs, then each a line and how many tokens in a row
+//              are on it, covering every token after it in file order
+//   exprs      count, then each node after its operands: a tag and its
+//              fields. A token is its kind, then a constant for literals
+//              and names; operands are indexes of earlier nodes.
+//   functions  count, then each: name, parameters, body, closing brace.
+//              Bodies refer to functions by index, only to earlier ones.
+//   script     the top-level statements, count first
+//   checksum   u32, CRC-32 of everything before it
+//
+// Tags number the variants of `Expr` and `Stmt` in the order they're
+// declared. A file of another version is turned away rather than guessed
+// at, and one that loads has been checked for everything the parser
+// checks, so it runs like the program it was compiled from.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+use crate::ast::{Expr, ExprArena, ExprId, Function, Program, Stmt};
+use crate::parser::{ClassKind, FunctionKind};
+use crate::resolver;
+use crate::token::{LiteralKind, Token, TokenType};
+use crate::visit::{walk_expr, walk_function, walk_stmt, Visitor};
+
+// Not valid UTF-8, so no script can start with it
+pub const MAGIC: &[u8; 5] = b"\x89LOXC";
+
+// Goes up whenever the layout changes
+pub const VERSION: u16 = 1;
+
+// How tall a loaded tree may be, counting statements, expressions and
+// functions declared inside each other. Parsed programs stay well below it;
+// it keeps a crafted file from overflowing the stack of the passes that
+// recurse into the tree.
+const MAX_DEPTH: usize = 4_000;
+
+// Kinds of token that are nothing more, by their code in the file
+const KINDS: [TokenType; 42] = [
+    TokenType::LeftParen,
+    TokenType::RightParen,
+    TokenType::LeftBrace,
+    TokenType::RightBrace,
+    TokenType::LeftBracket,
+    TokenType::RightBracket,
+    TokenType::Colon,
+    TokenType::Comma,
+    TokenType::Dot,
+    TokenType::Minus,
+    TokenType::Plus,
+    TokenType::Semicolon,
+    TokenType::Slash,
+    TokenType::Star,
+    TokenType::Bang,
+    TokenType::BangEqual,
+    TokenType::Equal,
+    TokenType::EqualEqual,
+    TokenType::Greater,
+    TokenType::GreaterEqual,
+    TokenType::Less,
+    TokenType::LessEqual,
+    TokenType::And,
+    TokenType::Break,
+    TokenType::Class,
+    TokenType::Continue,
+    TokenType::Else,
+    TokenType::False,
+    TokenType::Fun,
+    TokenType::For,
+    TokenType::If,
+    TokenType::In,
+    TokenType::Nil,
+    TokenType::Or,
+    TokenType::Print,
+    TokenType::Return,
+    TokenType::Super,
+    TokenType::This,
+    TokenType::True,
+    TokenType::Var,
+    TokenType::While,
+    TokenType::EOF,
+];
+
+// Codes of the kinds followed by a constant
+const STRING: u8 = 64;
+const NUMBER: u8 = 65;
+const IDENTIFIER: u8 = 66;
+const ERROR: u8 = 67;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadError {
+    // Doesn't start with `MAGIC`
+    NotCompiled,
+    // Written for another version of the format
+    Version(u16),
+    // Damaged, or not something `compile` wrote
+    Corrupted(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::NotCompiled => f.write_str("Not a compiled script."),
+            LoadError::Version(version) => write!(
+                f,
+                "Compiled script is format version {}, but this luxor reads version {}. \
+                 Compile it again.",
+                version, VERSION
+            ),
+            LoadError::Corrupted(why) => write!(f, "Compiled script is corrupted: {}.", why),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+fn corrupted(why: &str) -> LoadError {
+    LoadError::Corrupted(why.to_string())
+}
+
+pub fn is_compiled(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn compile(program: &Program) -> Vec<u8> {
+    let mut writer = Writer {
+        indexes: vec![None; program.exprs.len()],
+        ..Writer::default()
+    };
+    writer.varint(program.exprs.len());
+    // Not in id order: an assignment is pushed before the value it assigns
+    for (id, _) in program.exprs.iter() {
+        writer.visit_expr(&program.exprs, id);
+    }
+    // Counted once they've all been found
+    let exprs = mem::take(&mut writer.out);
+    writer.functions(&program.statements);
+    let functions = mem::replace(&mut writer.out, exprs);
+    writer.varint(writer.function_ids.len());
+    writer.out.extend(functions);
+    writer.stmts(&program.statements);
+
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    varint(&mut out, writer.constants.len());
+    for constant in &writer.constants {
+        match constant {
+            Constant::Number(n) => {
+                out.push(0);
+                out.extend_from_slice(&n.to_le_bytes());
+            }
+            Constant::Str(s) => {
+                out.push(1);
+                varint(&mut out, s.len());
+                out.extend_from_slice(s.as_bytes());
+            }
+        }
+    }
+    let mut runs: Vec<(u32, usize)> = Vec::new();
+    for &line in &writer.lines {
+        match runs.last_mut() {
+            Some((last, count)) if *last == line => *count += 1,
+            _ => runs.push((line, 1)),
+        }
+    }
+    varint(&mut out, runs.len());
+    for (line, count) in runs {
+        varint(&mut out, line as usize);
+        varint(&mut out, count);
+    }
+    out.extend(writer.out);
+
+    let checksum = crc32(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+// Checks and unpacks what `compile` wrote
+pub fn load(bytes: &[u8]) -> Result<Program, LoadError> {
+    if !is_compiled(bytes) {
+        return Err(LoadError::NotCompiled);
+    }
+    let start = MAGIC.len() + 2;
+    let version = match bytes.get(MAGIC.len()..start) {
+        Some(version) => u16::from_le_bytes([version[0], version[1]]),
+        None => return Err(corrupted("it ends early")),
+    };
+    if version != VERSION {
+        return Err(LoadError::Version(version));
+    }
+    let end = match bytes.len().checked_sub(4) {
+        Some(end) if end >= start => end,
+        _ => return Err(corrupted("it ends early")),
+    };
+    let (contents, checksum) = bytes.split_at(end);
+    if crc32(contents) != u32::from_le_bytes(checksum.try_into().unwrap()) {
+        return Err(corrupted("its checksum doesn't match"));
+    }
+
+    let mut reader = Reader {
+        bytes: contents,
+        pos: start,
+        constants: Vec::new(),
+        runs: Vec::new(),
+        run: 0,
+        used: 0,
+        exprs: ExprArena::new(),
+        nodes: Vec::new(),
+        functions: Vec::new(),
+    };
+    reader.constants()?;
+    reader.lines()?;
+    for _ in 0..reader.varint()? {
+        reader.expr()?;
+    }
+    for _ in 0..reader.varint()? {
+        reader.function()?;
+    }
+    let statements = reader.stmts(0, &mut 0)?;
+    if reader.pos != contents.len() {
+        return Err(corrupted("there's more after the script"));
+    }
+    if reader.line().is_ok() {
+        return Err(corrupted(
+            "its line table has lines for tokens it doesn't have",
+        ));
+    }
+
+    let exprs = reader.exprs;
+    let mut checker = Checker {
+        function: FunctionKind::None,
+        class: ClassKind::None,
+        loops: 0,
+    };
+    for stmt in &statements {
+        checker.visit_stmt(&exprs, stmt)?;
+    }
+    let (locals, errors) = resolver::resolve(&exprs, &statements);
+    if let Some(e) = errors.first() {
+        return Err(LoadError::Corrupted(e.to_string()));
+    }
+
+    Ok(Program {
+        exprs: Rc::new(exprs),
+        statements,
+        locals: Rc::new(locals),
+    })
+}
+
+// CRC-32 as in zlib and PNG
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn varint(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+enum Constant {
+    Number(f64),
+    Str(String),
+}
+
+#[derive(Default)]
+struct Writer {
+    out: Vec<u8>,
+    // Of every token written, in order
+    lines: Vec<u32>,
+    constants: Vec<Constant>,
+    strings: HashMap<String, usize>,
+    // By their bits, so -0 and NaN keep theirs
+    numbers: HashMap<u64, usize>,
+    // Index in the file of each node, by id, once it's written
+    indexes: Vec<Option<usize>>,
+    written: usize,
+    // Index of each function written so far
+    function_ids: HashMap<*const Function, usize>,
+}
+
+impl Visitor for Writer {
+    fn visit_expr(&mut self, exprs: &ExprArena, id: ExprId) {
+        if self.indexes[id.index()].is_none() {
+            walk_expr(self, exprs, id);
+            self.expr(&exprs[id]);
+            self.indexes[id.index()] = Some(self.written);
+            self.written += 1;
+        }
+    }
+}
+
+impl Writer {
+    fn byte(&mut self, byte: u8) {
+        self.out.push(byte);
+    }
+
+    fn varint(&mut self, n: usize) {
+        varint(&mut self.out, n);
+    }
+
+    fn string(&mut self, s: &str) -> usize {
+        if let Some(&index) = self.strings.get(s) {
+            return index;
+        }
+        let index = self.constants.len();
+        self.constants.push(Constant::Str(s.to_string()));
+        self.strings.insert(s.to_string(), index);
+        index
+    }
+
+    fn number(&mut self, n: f64) -> usize {
+        let constants = &mut self.constants;
+        *self.numbers.entry(n.to_bits()).or_insert_with(|| {
+            constants.push(Constant::Number(n));
+            constants.len() - 1
+        })
+    }
+
+    fn token(&mut self, token: &Token) {
+        self.lines.push(token.line);
+        let (code, constant) = match &token.kind {
+            TokenType::Literal(LiteralKind::Str(s)) => (STRING, Some(self.string(s))),
+            TokenType::Literal(LiteralKind::Number(n)) => (NUMBER, Some(self.number(*n))),
+            TokenType::Literal(LiteralKind::Identifier(s)) => (IDENTIFIER, Some(self.string(s))),
+            TokenType::Error(s) => (ERROR, Some(self.string(s))),
+            kind => (KINDS.iter().position(|k| k == kind).unwrap() as u8, None),
+        };
+        self.byte(code);
+        if let Some(constant) = constant {
+            self.varint(constant);
+        }
+    }
+
+    fn id(&mut self, id: ExprId) {
+        self.varint(self.index(id));
+    }
+
+    fn index(&self, id: ExprId) -> usize {
+        self.indexes[id.index()].unwrap()
+    }
+
+    fn ids(&mut self, ids: &[ExprId]) {
+        self.varint(ids.len());
+        for &id in ids {
+            self.id(id);
+        }
+    }
+
+    // 0 for none, else one more than the index
+    fn optional_id(&mut self, id: Option<ExprId>) {
+        self.varint(id.map_or(0, |id| self.index(id) + 1));
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                self.byte(0);
+                self.id(*left);
+                self.token(operator);
+                self.id(*right);
+            }
+            Expr::Assign { name, value } => {
+                self.byte(1);
+                self.token(name);
+                self.id(*value);
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => {
+                self.byte(2);
+                self.id(*callee);
+                self.token(paren);
+                self.ids(arguments);
+            }
+            Expr::Get { object, name } => {
+                self.byte(3);
+                self.id(*object);
+                self.token(name);
+            }
+            Expr::Grouping { expression } => {
+                self.byte(4);
+                self.id(*expression);
+            }
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                self.byte(5);
+                self.id(*object);
+                self.token(bracket);
+                self.id(*index);
+            }
+            Expr::Lit(token) => {
+                self.byte(6);
+                self.token(token);
+            }
+            Expr::List { bracket, elements } => {
+                self.byte(7);
+                self.token(bracket);
+                self.ids(elements);
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                self.byte(8);
+                self.id(*left);
+                self.token(operator);
+                self.id(*right);
+            }
+            Expr::Map { brace, entries } => {
+                self.byte(9);
+                self.token(brace);
+                self.varint(entries.len());
+                for &(key, value) in entries {
+                    self.id(key);
+                    self.id(value);
+                }
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.byte(10);
+                self.id(*object);
+                self.token(name);
+                self.id(*value);
+            }
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                self.byte(11);
+                self.id(*object);
+                self.token(bracket);
+                self.id(*index);
+                self.id(*value);
+            }
+            Expr::Slice {
+                object,
+                bracket,
+                start,
+                end,
+            } => {
+                self.byte(12);
+                self.id(*object);
+                self.token(bracket);
+                self.optional_id(*start);
+                self.optional_id(*end);
+            }
+            Expr::Super { keyword, method } => {
+                self.byte(13);
+                self.token(keyword);
+                self.token(method);
+            }
+            Expr::This { keyword } => {
+                self.byte(14);
+                self.token(keyword);
+            }
+            Expr::Unary { operator, right } => {
+                self.byte(15);
+                self.token(operator);
+                self.id(*right);
+            }
+            Expr::Variable { name } => {
+                self.byte(16);
+                self.token(name);
+            }
+        }
+    }
+
+    fn stmts(&mut self, statements: &[Stmt]) {
+        self.varint(statements.len());
+        for stmt in statements {
+            self.stmt(stmt);
+        }
+    }
+
+    fn optional_stmt(&mut self, stmt: Option<&Stmt>) {
+        match stmt {
+            Some(stmt) => {
+                self.byte(1);
+                self.stmt(stmt);
+            }
+            None => self.byte(0),
+        }
+    }
+
+    fn function_id(&mut self, function: &Rc<Function>) {
+        self.varint(self.function_ids[&Rc::as_ptr(function)]);
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Block { statements, brace } => {
+                self.byte(0);
+                self.stmts(statements);
+                self.token(brace);
+            }
+            Stmt::Break { keyword } => {
+                self.byte(1);
+                self.token(keyword);
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                self.byte(2);
+                self.token(name);
+                self.optional_id(*superclass);
+                self.varint(methods.len());
+                for method in methods {
+                    self.function_id(method);
+                }
+            }
+            Stmt::Continue { keyword } => {
+                self.byte(3);
+                self.token(keyword);
+            }
+            Stmt::Expression { expression } => {
+                self.byte(4);
+                self.id(*expression);
+            }
+            Stmt::For {
+                keyword,
+                initializer,
+                condition,
+                increment,
+                body,
+            } => {
+                self.byte(5);
+                self.token(keyword);
+                self.optional_stmt(initializer.as_deref());
+                self.optional_id(*condition);
+                self.optional_id(*increment);
+                self.stmt(body);
+            }
+            Stmt::ForIn {
+                keyword,
+                name,
+                iterable,
+                body,
+            } => {
+                self.byte(6);
+                self.token(keyword);
+                self.token(name);
+                self.id(*iterable);
+                self.stmt(body);
+            }
+            Stmt::Function(function) => {
+                self.byte(7);
+                self.function_id(function);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.byte(8);
+                self.id(*condition);
+                self.stmt(then_branch);
+                self.optional_stmt(else_branch.as_deref());
+            }
+            Stmt::Print { expression } => {
+                self.byte(9);
+                self.id(*expression);
+            }
+            Stmt::Return { keyword, value } => {
+                self.byte(10);
+                self.token(keyword);
+                self.optional_id(*value);
+            }
+            Stmt::Var { name, initializer } => {
+                self.byte(11);
+                self.token(name);
+                self.optional_id(*initializer);
+            }
+            Stmt::While { condition, body } => {
+                self.byte(12);
+                self.id(*condition);
+                self.stmt(body);
+            }
+        }
+    }
+
+    // Writes the functions declared in `statements`, each after the ones
+    // declared inside it
+    fn functions(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            self.declared(stmt);
+        }
+    }
+
+    fn declared(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Block { statements, .. } => self.functions(statements),
+            Stmt::Class { methods, .. } => {
+                for method in methods {
+                    self.function(method);
+                }
+            }
+            Stmt::Function(function) => self.function(function),
+            Stmt::For { body, .. } | Stmt::ForIn { body, .. } | Stmt::While { body, .. } => {
+                self.declared(body)
+            }
+            Stmt::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.declared(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.declared(else_branch);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn function(&mut self, function: &Rc<Function>) {
+        self.functions(&function.body);
+        self.token(&function.name);
+        self.varint(function.params.len());
+        for param in &function.params {
+            self.token(param);
+        }
+        self.stmts(&function.body);
+        self.token(&function.brace);
+        let id = self.function_ids.len();
+        self.function_ids.insert(Rc::as_ptr(function), id);
+    }
+}
+
+type LoadResult<T> = Result<T, LoadError>;
+
+// Heights are of the subtree under each node, and each read of a child
+// raises the height of the node being read to fit it
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    constants: Vec<Constant>,
+    // The line table, with how far into it the tokens read so far reach
+    runs: Vec<(u32, usize)>,
+    run: usize,
+    used: usize,
+    exprs: ExprArena,
+    // Id and height of each node read, by its index
+    nodes: Vec<(ExprId, usize)>,
+    // Each function read and its height, until a statement takes it
+    functions: Vec<(Option<Rc<Function>>, usize)>,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> LoadResult<u8> {
+        let byte = *self
+            .bytes
+            .get(self.pos)
+            .ok_or_else(|| corrupted("it ends early"))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn take(&mut self, len: usize) -> LoadResult<&[u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| corrupted("it ends early"))?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn varint(&mut self) -> LoadResult<usize> {
+        let mut n: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return n.try_into().map_err(|_| corrupted("a number is too big"));
+            }
+        }
+        Err(corrupted("a number is too big"))
+    }
+
+    fn constants(&mut self) -> LoadResult<()> {
+        for _ in 0..self.varint()? {
+            let constant = match self.byte()? {
+                0 => Constant::Number(f64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+                1 => {
+                    let len = self.varint()?;
+                    let bytes = self.take(len)?.to_vec();
+                    let s =
+                        String::from_utf8(bytes).map_err(|_| corrupted("a string isn't UTF-8"))?;
+                    Constant::Str(s)
+                }
+                _ => return Err(corrupted("a constant is of an unknown kind")),
+            };
+            self.constants.push(constant);
+        }
+        Ok(())
+    }
+
+    fn string(&mut self) -> LoadResult<String> {
+        let index = self.varint()?;
+        match self.constants.get(index) {
+            Some(Constant::Str(s)) => Ok(s.clone()),
+            _ => Err(corrupted("a string constant is missing")),
+        }
+    }
+
+    fn number(&mut self) -> LoadResult<f64> {
+        let index = self.varint()?;
+        match self.constants.get(index) {
+            Some(Constant::Number(n)) => Ok(*n),
+            _ => Err(corrupted("a number constant is missing")),
+        }
+    }
+
+    fn lines(&mut self) -> LoadResult<()> {
+        for _ in 0..self.varint()? {
+            let line = self
+                .varint()?
+                .try_into()
+                .map_err(|_| corrupted("a line is too big"))?;
+            let count = self.varint()?;
+            self.runs.push((line, count));
+        }
+        Ok(())
+    }
+
+    // The line of the next token
+    fn line(&mut self) -> LoadResult<u32> {
+        while let Some(&(line, count)) = self.runs.get(self.run) {
+            if self.used < count {
+                self.used += 1;
+                return Ok(line);
+            }
+            self.run += 1;
+            self.used = 0;
+        }
+        Err(corrupted("its line table is too short"))
+    }
+
+    fn token(&mut self) -> LoadResult<Token> {
+        let line = self.line()?;
+        let kind = match self.byte()? {
+            STRING => TokenType::Literal(LiteralKind::Str(self.string()?)),
+            NUMBER => TokenType::Literal(LiteralKind::Number(self.number()?)),
+            IDENTIFIER => TokenType::Literal(LiteralKind::Identifier(self.string()?)),
+            ERROR => TokenType::Error(self.string()?),
+            code => KINDS
+                .get(code as usize)
+                .cloned()
+                .ok_or_else(|| corrupted("a token is of an unknown kind"))?,
+        };
+        Ok(Token::new(kind, line))
+    }
+
+    fn id_at(&mut self, index: usize, height: &mut usize) -> LoadResult<ExprId> {
+        let &(id, below) = self
+            .nodes
+            .get(index)
+            .ok_or_else(|| corrupted("an expression is missing"))?;
+        *height = (*height).max(below);
+        Ok(id)
+    }
+
+    // Only nodes already read can be operands, so the tree has no cycles
+    fn id(&mut self, height: &mut usize) -> LoadResult<ExprId> {
+        let index = self.varint()?;
+        self.id_at(index, height)
+    }
+
+    fn ids(&mut self, height: &mut usize) -> LoadResult<Vec<ExprId>> {
+        let mut ids = Vec::new();
+        for _ in 0..self.varint()? {
+            ids.push(self.id(height)?);
+        }
+        Ok(ids)
+    }
+
+    fn optional_id(&mut self, height: &mut usize) -> LoadResult<Option<ExprId>> {
+        match self.varint()? {
+            0 => Ok(None),
+            n => self.id_at(n - 1, height).map(Some),
+        }
+    }
+
+    fn expr(&mut self) -> LoadResult<()> {
+        let mut height = 0;
+        let h = &mut height;
+        let expr = match self.byte()? {
+            0 => Expr::Binary {
+                left: self.id(h)?,
+                operator: self.token()?,
+                right: self.id(h)?,
+            },
+            1 => Expr::Assign {
+                name: self.token()?,
+                value: self.id(h)?,
+            },
+            2 => Expr::Call {
+                callee: self.id(h)?,
+                paren: self.token()?,
+                arguments: self.ids(h)?,
+            },
+            3 => Expr::Get {
+                object: self.id(h)?,
+                name: self.token()?,
+            },
+            4 => Expr::Grouping {
+                expression: self.id(h)?,
+            },
+            5 => Expr::Index {
+                object: self.id(h)?,
+                bracket: self.token()?,
+                index: self.id(h)?,
+            },
+            6 => Expr::Lit(self.token()?),
+            7 => Expr::List {
+                bracket: self.token()?,
+                elements: self.ids(h)?,
+            },
+            8 => Expr::Logical {
+                left: self.id(h)?,
+                operator: self.token()?,
+                right: self.id(h)?,
+            },
+            9 => {
+                let brace = self.token()?;
+                let mut entries = Vec::new();
+                for _ in 0..self.varint()? {
+                    entries.push((self.id(h)?, self.id(h)?));
+                }
+                Expr::Map { brace, entries }
+            }
+            10 => Expr::Set {
+                object: self.id(h)?,
+                name: self.token()?,
+                value: self.id(h)?,
+            },
+            11 => Expr::SetIndex {
+                object: self.id(h)?,
+                bracket: self.token()?,
+                index: self.id(h)?,
+                value: self.id(h)?,
+            },
+            12 => Expr::Slice {
+                object: self.id(h)?,
+                bracket: self.token()?,
+                start: self.optional_id(h)?,
+                end: self.optional_id(h)?,
+            },
+            13 => Expr::Super {
+                keyword: self.token()?,
+                method: self.token()?,
+            },
+            14 => Expr::This {
+                keyword: self.token()?,
+            },
+            15 => Expr::Unary {
+                operator: self.token()?,
+                right: self.id(h)?,
+            },
+            16 => Expr::Variable {
+                name: self.token()?,
+            },
+            _ => return Err(corrupted("an expression is of an unknown kind")),
+        };
+        let height = taller(height)?;
+        let id = self.exprs.push(expr);
+        self.nodes.push((id, height));
+        Ok(())
+    }
+
+    // `depth` counts the statements around these in the same body, so
+    // reading them can't recurse without bound
+    fn stmts(&mut self, depth: usize, height: &mut usize) -> LoadResult<Vec<Stmt>> {
+        let mut statements = Vec::new();
+        for _ in 0..self.varint()? {
+            statements.push(self.stmt(depth, height)?);
+        }
+        Ok(statements)
+    }
+
+    fn optional_stmt(&mut self, depth: usize, height: &mut usize) -> LoadResult<Option<Box<Stmt>>> {
+        match self.byte()? {
+            0 => Ok(None),
+            1 => Ok(Some(Box::new(self.stmt(depth, height)?))),
+            _ => Err(corrupted("a statement is neither there nor missing")),
+        }
+    }
+
+    fn function_id(&mut self, height: &mut usize) -> LoadResult<Rc<Function>> {
+        let index = self.varint()?;
+        let (function, below) = self
+            .functions
+            .get_mut(index)
+            .ok_or_else(|| corrupted("a function is missing"))?;
+        let function = function
+            .take()
+            .ok_or_else(|| corrupted("a function is declared twice"))?;
+        *height = (*height).max(*below);
+        Ok(function)
+    }
+
+    fn stmt(&mut self, depth: usize, height: &mut usize) -> LoadResult<Stmt> {
+        if depth > MAX_DEPTH {
+            return Err(corrupted("it's nested too deeply"));
+        }
+        let inner = depth + 1;
+        let mut below = 0;
+        let h = &mut below;
+        let stmt = match self.byte()? {
+            0 => Stmt::Block {
+                statements: self.stmts(inner, h)?,
+                brace: self.token()?,
+            },
+            1 => Stmt::Break {
+                keyword: self.token()?,
+            },
+            2 => {
+                let name = self.token()?;
+                let superclass = self.optional_id(h)?;
+                let mut methods = Vec::new();
+                for _ in 0..self.varint()? {
+                    methods.push(self.function_id(h)?);
+                }
+                Stmt::Class {
+                    name,
+                    superclass,
+                    methods,
+                }
+            }
+            3 => Stmt::Continue {
+                keyword: self.token()?,
+            },
+            4 => Stmt::Expression {
+                expression: self.id(h)?,
+            },
+            5 => Stmt::For {
+                keyword: self.token()?,
+                initializer: self.optional_stmt(inner, h)?,
+                condition: self.optional_id(h)?,
+                increment: self.optional_id(h)?,
+                body: Box::new(self.stmt(inner, h)?),
+            },
+            6 => Stmt::ForIn {
+                keyword: self.token()?,
+                name: self.token()?,
+                iterable: self.id(h)?,
+                body: Box::new(self.stmt(inner, h)?),
+            },
+            7 => Stmt::Function(self.function_id(h)?),
+            8 => Stmt::If {
+                condition: self.id(h)?,
+                then_branch: Box::new(self.stmt(inner, h)?),
+                else_branch: self.optional_stmt(inner, h)?,
+            },
+            9 => Stmt::Print {
+                expression: self.id(h)?,
+            },
+            10 => Stmt::Return {
+                keyword: self.token()?,
+                value: self.optional_id(h)?,
+            },
+            11 => Stmt::Var {
+                name: self.token()?,
+                initializer: self.optional_id(h)?,
+            },
+            12 => Stmt::While {
+                condition: self.id(h)?,
+                body: Box::new(self.stmt(inner, h)?),
+            },
+            _ => return Err(corrupted("a statement is of an unknown kind")),
+        };
+        *height = (*height).max(taller(below)?);
+        Ok(stmt)
+    }
+
+    fn function(&mut self) -> LoadResult<()> {
+        let name = self.token()?;
+        let count = self.varint()?;
+        if count > 255 {
+            return Err(corrupted("a function has too many parameters"));
+        }
+        let mut params = Vec::new();
+        for _ in 0..count {
+            params.push(self.token()?);
+        }
+        let mut below = 0;
+        let body = self.stmts(0, &mut below)?;
+        let brace = self.token()?;
+        let function = Function {
+            name,
+            params,
+            body,
+            brace,
+        };
+        self.functions
+            .push((Some(Rc::new(function)), taller(below)?));
+        Ok(())
+    }
+}
+
+// The height of a node with children up to `below` high
+fn taller(below: usize) -> LoadResult<usize> {
+    match below + 1 {
+        height if height > MAX_DEPTH => Err(corrupted("it's nested too deeply")),
+        height => Ok(height),
+    }
+}
+
+// The rules the parser enforces about where statements and expressions can
+// appear, which the interpreter relies on
+struct Checker {
+    function: FunctionKind,
+    class: ClassKind,
+    // Enclosing the current statement within its function
+    loops: usize,
+}
+
+impl Checker {
+    fn function(
+        &mut self,
+        exprs: &ExprArena,
+        function: &Function,
+        kind: FunctionKind,
+    ) -> LoadResult<()> {
+        let enclosing = mem::replace(&mut self.function, kind);
+        let loops = mem::replace(&mut self.loops, 0);
+        let result = walk_function(self, exprs, function);
+        self.function = enclosing;
+        self.loops = loops;
+        result
+    }
+}
+
+fn misplaced(keyword: &Token) -> LoadError {
+    LoadError::Corrupted(format!(
+        "'{}' on line {} is out of place",
+        keyword, keyword.line
+    ))
+}
+
+impl Visitor<LoadResult<()>> for Checker {
+    fn visit_stmt(&mut self, exprs: &ExprArena, stmt: &Stmt) -> LoadResult<()> {
+        match stmt {
+            Stmt::Break { keyword } | Stmt::Continue { keyword } if self.loops == 0 => {
+                Err(misplaced(keyword))
+            }
+            Stmt::Return { keyword, value } => match self.function {
+                FunctionKind::None => Err(misplaced(keyword)),
+                FunctionKind::Initializer if value.is_some() => Err(misplaced(keyword)),
+                _ => walk_stmt(self, exprs, stmt),
+            },
+            Stmt::Class {
+                superclass,
+                methods,
+                ..
+            } => {
+                let kind = match superclass {
+                    Some(superclass) if exprs.variable_name(*superclass).is_none() => {
+                        return Err(corrupted("a superclass isn't a name"))
+                    }
+                    Some(_) => ClassKind::Subclass,
+                    None => ClassKind::Class,
+                };
+                let enclosing = mem::replace(&mut self.class, kind);
+                let result = methods.iter().try_for_each(|method| {
+                    let kind = match method.name.name() {
+                        "init" => FunctionKind::Initializer,
+                        _ => FunctionKind::Method,
+                    };
+                    self.function(exprs, method, kind)
+                });
+                self.class = enclosing;
+                result
+            }
+            Stmt::Function(function) => self.function(exprs, function, FunctionKind::Function),
+            Stmt::For { .. } | Stmt::ForIn { .. } | Stmt::While { .. } => {
+                self.loops += 1;
+                let result = walk_stmt(self, exprs, stmt);
+                self.loops -= 1;
+                result
+            }
+            _ => walk_stmt(self, exprs, stmt),
+        }
+    }
+
+    fn visit_expr(&mut self, exprs: &ExprArena, id: ExprId) -> LoadResult<()> {
+        match &exprs[id] {
+            Expr::This { keyword } if self.class == ClassKind::None => Err(misplaced(keyword)),
+            Expr::Super { keyword, .. } if self.class != ClassKind::Subclass => {
+                Err(misplaced(keyword))
+            }
+            _ => walk_expr(self, exprs, id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn parse(src: &str) -> Program {
+        Parser::new(Scanner::new(src).scan_tokens().to_vec())
+            .parse()
+            .unwrap()
+    }
+
+    // Every node's line, to check the line table. Nodes may be stored in
+    // another order once loaded.
+    fn lines(program: &Program) -> Vec<u32> {
+        let exprs = &program.exprs;
+        let mut lines: Vec<u32> = exprs.iter().map(|(id, _)| exprs.line(id)).collect();
+        lines.sort_unstable();
+        lines.extend(program.statements.iter().map(|s| s.line(exprs)));
+        lines
+    }
+
+    // Puts a right checksum on a file that's been tampered with
+    fn reseal(bytes: &mut Vec<u8>) {
+        let end = bytes.len() - 4;
+        bytes.truncate(end);
+        let checksum = crc32(bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+    }
+
+    fn error(bytes: &[u8]) -> Option<LoadError> {
+        load(bytes).err()
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let src = "class A { init(x) { this.x = x; } get() { return this.x; } }\n\
+                   class B < A { get() { return super.get() * -2; } }\n\
+                   fun f(n) {\n  fun g() { return n; }\n  return g;\n}\n\
+                   var m = {\"a\": [1, 2.5, nil], 2: true};\n\
+                   for (var i = 0; i < 3; i = i + 1) { if (i == 1) continue; else break; }\n\
+                   for (var x in m[\"a\"][0:]) print x or false and !x;\n\
+                   while (false) {}\n\
+                   m[2] = B(f(1)()).get();\nprint m;";
+        let program = parse(src);
+        let loaded = load(&compile(&program)).unwrap();
+        assert_eq!(loaded.to_string(), program.to_string());
+        assert_eq!(lines(&loaded), lines(&program));
+        assert_eq!(compile(&loaded), compile(&program));
+    }
+
+    #[test]
+    fn test_rejects() {
+        let bytes = compile(&parse("var a = \"x\";\nprint a;"));
+        assert_eq!(error(b"print 1;"), Some(LoadError::NotCompiled));
+
+        let mut newer = bytes.clone();
+        newer[MAGIC.len()] = 2;
+        assert_eq!(error(&newer), Some(LoadError::Version(2)));
+
+        for i in MAGIC.len() + 2..bytes.len() {
+            let mut flipped = bytes.clone();
+            flipped[i] ^= 0x10;
+            assert!(matches!(load(&flipped), Err(LoadError::Corrupted(_))));
+        }
+        for len in MAGIC.len()..bytes.len() {
+            assert!(load(&bytes[..len]).is_err());
+        }
+
+        // Damage that the checksum is made to agree with
+        let mut extra = bytes.clone();
+        extra.insert(bytes.len() - 4, 0);
+        reseal(&mut extra);
+        assert_eq!(
+            error(&extra),
+            Some(corrupted("there's more after the script"))
+        );
+    }
+
+    #[test]
+    fn test_checks_like_the_parser() {
+        let keyword = |kind| Token::new(kind, 3);
+        let outside = |stmt| Program {
+            statements: vec![stmt],
+            ..Program::default()
+        };
+        let stmt = Stmt::Return {
+            keyword: keyword(TokenType::Return),
+            value: None,
+        };
+        assert_eq!(
+            error(&compile(&outside(stmt))),
+            Some(corrupted("'return' on line 3 is out of place"))
+        );
+        let stmt = Stmt::Break {
+            keyword: keyword(TokenType::Break),
+        };
+        assert!(error(&compile(&outside(stmt))).is_some());
+
+        let mut program = parse("fun f() {}");
+        let mut exprs = (*program.exprs).clone();
+        let this = exprs.push(Expr::This {
+            keyword: keyword(TokenType::This),
+        });
+        program
+            .statements
+            .push(Stmt::Expression { expression: this });
+        program.exprs = Rc::new(exprs);
+        assert!(error(&compile(&program)).is_some());
+
+        // A tower of functions each declared in the one before
+        let mut program = parse("fun f() {}");
+        for _ in 0..MAX_DEPTH {
+            let function = match &program.statements[0] {
+                Stmt::Function(function) => Rc::clone(function),
+                _ => unreachable!(),
+            };
+            let outer = Function {
+                body: vec![Stmt::Function(Rc::clone(&function))],
+                ..(*function).clone()
+            };
+            program.statements = vec![Stmt::Function(Rc::new(outer))];
+        }
+        assert_eq!(
+            error(&compile(&program)),
+            Some(corrupted("it's nested too deeply"))
+        );
+    }
+}
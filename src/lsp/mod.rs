@@ -0,0 +1,650 @@
+// A Language Server Protocol server for editors, behind `luxor lsp`.
+//
+// Documents are synced whole on every change. Each version is scanned,
+// parsed and linted to publish diagnostics. They aren't kept as
+// `incremental::Document`s: that parses each top-level declaration on its
+// own, while symbols and lints need the whole script resolved as one
+// program, so every change would be parsed in full all the same. The
+// `Symbols` of the latest version that parsed answer hover,
+// go-to-definition, references, document symbols and completion, so they
+// keep working while a line is half typed.
+// Positions on the wire count UTF-16 code units from 0; inside they are
+// lines and character columns from 1.
+//
+// Lints follow the nearest `luxor.toml` to the document, or to the workspace
+// root for documents that aren't files, as `luxor lint` run from there would.
+
+pub mod jsonrpc;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::lint::{self, Config};
+use crate::parser::Parser;
+use crate::scanner::{Scanner, KEYWORDS};
+use crate::symbols::{Position, Symbol, SymbolKind, Symbols};
+use crate::token::{LiteralKind, Token, TokenType};
+use crate::Interpreter;
+
+pub use jsonrpc::Json;
+use jsonrpc::{read_message, write_message};
+
+// JSON-RPC error codes
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+// Serves one client until it sends `exit` or closes the input. Returns the
+// exit status the protocol asks for: 0 if `shutdown` came first, else 1.
+pub fn serve<R: BufRead, W: Write>(mut input: R, output: W) -> io::Result<i32> {
+    let mut server = Server {
+        output,
+        documents: HashMap::new(),
+        root: None,
+        builtins: Interpreter::new()
+            .globals()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect(),
+        shut_down: false,
+    };
+
+    while let Some(body) = read_message(&mut input)? {
+        let message = match Json::parse(&body) {
+            Ok(message) => message,
+            Err(e) => {
+                server.respond(&Json::Null, Err((PARSE_ERROR, e)))?;
+                continue;
+            }
+        };
+        if message.get("method").as_str() == Some("exit") {
+            break;
+        }
+        server.handle(&message)?;
+    }
+
+    Ok(if server.shut_down { 0 } else { 1 })
+}
+
+type Response = Result<Json, (i32, String)>;
+
+struct Document {
+    text: String,
+    tokens: Vec<Token>,
+    // From the latest version that parsed, which may not be this one. Until
+    // this one parses too, lines after an edit may have moved since.
+    symbols: Option<Symbols>,
+}
+
+struct Server<W> {
+    output: W,
+    documents: HashMap<String, Document>,
+    // The workspace's directory, if the client opened one
+    root: Option<PathBuf>,
+    // Natives every script can use, offered as completions
+    builtins: Vec<String>,
+    shut_down: bool,
+}
+
+impl<W: Write> Server<W> {
+    fn handle(&mut self, message: &Json) -> io::Result<()> {
+        let method = match message.get("method").as_str() {
+            Some(method) => method,
+            // A response to something we never send
+            None => return Ok(()),
+        };
+        let params = message.get("params");
+
+        // Requests have an id; notifications don't and get no reply
+        let id = match message {
+            Json::Object(members) => members.iter().find(|(k, _)| k == "id").map(|(_, v)| v),
+            _ => None,
+        };
+        let id = match id {
+            Some(id) => id,
+            None => return self.notification(method, params),
+        };
+
+        let response = if self.shut_down && method != "shutdown" {
+            Err((INVALID_REQUEST, "Server is shutting down".to_string()))
+        } else {
+            self.request(method, params)
+        };
+        self.respond(id, response)
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> io::Result<()> {
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or("");
+        match method {
+            "textDocument/didOpen" => {
+                let text = params
+                    .get("textDocument")
+                    .get("text")
+                    .as_str()
+                    .unwrap_or("");
+                self.update(uri, text.to_string())
+            }
+            "textDocument/didChange" => {
+                // With full sync the last change holds the whole text
+                let changes = params.get("contentChanges").as_array();
+                match changes.last().and_then(|c| c.get("text").as_str()) {
+                    Some(text) => self.update(uri, text.to_string()),
+                    None => Ok(()),
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                self.publish(uri, Vec::new())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn request(&mut self, method: &str, params: &Json) -> Response {
+        match method {
+            "initialize" => {
+                let root = params.get("rootUri").as_str().and_then(file_path);
+                self.root = root.or_else(|| params.get("rootPath").as_str().map(PathBuf::from));
+                Ok(Json::object(vec![
+                    (
+                        "capabilities",
+                        Json::object(vec![
+                            ("textDocumentSync", Json::from(1)),
+                            ("hoverProvider", Json::from(true)),
+                            ("definitionProvider", Json::from(true)),
+                            ("referencesProvider", Json::from(true)),
+                            ("documentSymbolProvider", Json::from(true)),
+                            ("completionProvider", Json::object(vec![])),
+                        ]),
+                    ),
+                    (
+                        "serverInfo",
+                        Json::object(vec![
+                            ("name", Json::string("luxor")),
+                            ("version", Json::string(env!("CARGO_PKG_VERSION"))),
+                        ]),
+                    ),
+                ]))
+            }
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Json::Null)
+            }
+            "textDocument/hover" => self.at_position(params, |doc, _, token, symbol| {
+                let symbols = doc.symbols.as_ref().unwrap();
+                let text = format!("({}) {}", symbol.kind.name(), symbol.detail(symbols));
+                Json::object(vec![
+                    (
+                        "contents",
+                        Json::object(vec![
+                            ("kind", Json::string("plaintext")),
+                            ("value", Json::String(text)),
+                        ]),
+                    ),
+                    ("range", doc.range(Position::of(token), token.name())),
+                ])
+            }),
+            "textDocument/definition" => self.at_position(params, |doc, uri, _, symbol| {
+                doc.location(uri, symbol.position, &symbol.name)
+            }),
+            "textDocument/references" => {
+                let include_declaration = params
+                    .get("context")
+                    .get("includeDeclaration")
+                    .as_bool()
+                    .unwrap_or(false);
+                self.at_position(params, |doc, uri, _, symbol| {
+                    let mut positions = symbol.references.clone();
+                    if include_declaration {
+                        positions.insert(0, symbol.position);
+                    }
+                    Json::Array(
+                        positions
+                            .into_iter()
+                            .map(|p| doc.location(uri, p, &symbol.name))
+                            .collect(),
+                    )
+                })
+            }
+            "textDocument/documentSymbol" => {
+                let doc = self.document(params)?;
+                Ok(match &doc.symbols {
+                    Some(symbols) => Json::Array(
+                        symbols
+                            .symbols
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, s)| s.parent.is_none())
+                            .map(|(i, s)| doc.document_symbol(symbols, i, s))
+                            .collect(),
+                    ),
+                    None => Json::Array(Vec::new()),
+                })
+            }
+            "textDocument/completion" => {
+                let doc = self.document(params)?;
+                let (line, character) = lsp_position(params)?;
+                Ok(Json::Array(self.completions(doc, line, character)))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method '{}'", method))),
+        }
+    }
+
+    fn document(&self, params: &Json) -> Result<&Document, (i32, String)> {
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or("");
+        self.documents
+            .get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("Unknown document '{}'", uri)))
+    }
+
+    // Answers a request about the name at `params.position`, or with null
+    // when there's no known name there
+    fn at_position(
+        &self,
+        params: &Json,
+        answer: impl FnOnce(&Document, &Json, &Token, &Symbol) -> Json,
+    ) -> Response {
+        let doc = self.document(params)?;
+        let uri = params.get("textDocument").get("uri");
+        let (line, character) = lsp_position(params)?;
+
+        // The name must match too, in case the symbols are from an earlier
+        // version where something else was there
+        let found = doc.symbols.as_ref().and_then(|symbols| {
+            let token = doc.identifier_at(line, character)?;
+            let symbol = symbols.at(Position::of(token))?;
+            Some((token, symbol)).filter(|_| symbol.name == token.name())
+        });
+        Ok(match found {
+            Some((token, symbol)) => answer(doc, uri, token, symbol),
+            None => Json::Null,
+        })
+    }
+
+    // What can be typed at a position: keywords, natives and the names in
+    // scope there
+    fn completions(&self, doc: &Document, line: u32, character: u32) -> Vec<Json> {
+        let item = |label: &str, kind: u32| {
+            Json::object(vec![
+                ("label", Json::string(label)),
+                ("kind", Json::from(kind)),
+            ])
+        };
+
+        let mut items: Vec<Json> = KEYWORDS.iter().map(|k| item(k, 14)).collect();
+        let mut seen: Vec<&str> = Vec::new();
+        for name in &self.builtins {
+            items.push(item(name, 3));
+            seen.push(name);
+        }
+        let scope = doc.symbols.as_ref().zip(doc.position(line, character));
+        if let Some((symbols, position)) = scope {
+            for symbol in symbols.visible_at(position) {
+                if seen.contains(&symbol.name.as_str()) {
+                    continue;
+                }
+                seen.push(&symbol.name);
+                let kind = match symbol.kind {
+                    SymbolKind::Function => 3,
+                    SymbolKind::Class => 7,
+                    _ => 6,
+                };
+                items.push(item(&symbol.name, kind));
+            }
+        }
+        items
+    }
+
+    fn update(&mut self, uri: &str, text: String) -> io::Result<()> {
+        let tokens = Scanner::new(&text).scan_tokens().to_vec();
+        let mut diagnostics = Vec::new();
+        let symbols = match Parser::new(tokens.clone()).parse() {
+            Ok(program) => Some(Symbols::analyze(&program)),
+            Err(errors) => {
+                for e in errors {
+                    diagnostics.push((e.line, 1, e.message, None));
+                }
+                self.documents.remove(uri).and_then(|doc| doc.symbols)
+            }
+        };
+        let config = match self.config(uri) {
+            Ok(config) => config,
+            Err(message) => {
+                diagnostics.push((1, 2, message, None));
+                Config::default()
+            }
+        };
+        if let Ok(lints) = lint::lint(&text, &config) {
+            for lint in lints {
+                diagnostics.push((lint.line, 2, lint.message, Some(lint.rule.name())));
+            }
+        }
+
+        let doc = Document {
+            text,
+            tokens,
+            symbols,
+        };
+        let diagnostics = diagnostics
+            .into_iter()
+            .map(|(line, severity, message, code)| {
+                let mut members = vec![
+                    ("range", doc.line_range(line)),
+                    ("severity", Json::from(severity)),
+                    ("source", Json::string("luxor")),
+                    ("message", Json::String(message)),
+                ];
+                if let Some(code) = code {
+                    members.push(("code", Json::string(code)));
+                }
+                Json::object(members)
+            })
+            .collect();
+        self.documents.insert(uri.to_string(), doc);
+        self.publish(uri, diagnostics)
+    }
+
+    // The settings in the nearest `luxor.toml`, looking up from the
+    // document's directory and then from the workspace root. A file that
+    // can't be used is reported, and the defaults linted with instead.
+    fn config(&self, uri: &str) -> Result<Config, String> {
+        let dir = file_path(uri).and_then(|path| path.parent().map(Path::to_path_buf));
+        let path = dir
+            .iter()
+            .chain(&self.root)
+            .find_map(|dir| lint::find_config(dir));
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(Config::default()),
+        };
+        let src = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Config::parse(&src).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn publish(&mut self, uri: &str, diagnostics: Vec<Json>) -> io::Result<()> {
+        let notification = Json::object(vec![
+            ("jsonrpc", Json::string("2.0")),
+            ("method", Json::string("textDocument/publishDiagnostics")),
+            (
+                "params",
+                Json::object(vec![
+                    ("uri", Json::string(uri)),
+                    ("diagnostics", Json::Array(diagnostics)),
+                ]),
+            ),
+        ]);
+        write_message(&mut self.output, &notification)
+    }
+
+    fn respond(&mut self, id: &Json, response: Response) -> io::Result<()> {
+        let outcome = match response {
+            Ok(result) => ("result", result),
+            Err((code, message)) => (
+                "error",
+                Json::object(vec![
+                    ("code", Json::Number(f64::from(code))),
+                    ("message", Json::String(message)),
+                ]),
+            ),
+        };
+        let message = Json::object(vec![
+            ("jsonrpc", Json::string("2.0")),
+            ("id", id.clone()),
+            outcome,
+        ]);
+        write_message(&mut self.output, &message)
+    }
+}
+
+impl Document {
+    fn line_text(&self, line: u32) -> &str {
+        self.text.lines().nth(line as usize - 1).unwrap_or("")
+    }
+
+    // Where an LSP position is, in lines and character columns from 1.
+    // `None` past the last line there could be.
+    fn position(&self, line: u32, character: u32) -> Option<Position> {
+        let line = line.checked_add(1)?;
+        let column = utf16_to_column(self.line_text(line), character);
+        Some(Position { line, column })
+    }
+
+    // The identifier under an LSP position, including just after its end
+    fn identifier_at(&self, line: u32, character: u32) -> Option<&Token> {
+        let Position { line, column } = self.position(line, character)?;
+        self.tokens.iter().find(|t| {
+            let is_identifier = matches!(t.kind, TokenType::Literal(LiteralKind::Identifier(_)));
+            let len = t.name().chars().count() as u32;
+            is_identifier && t.line == line && t.column <= column && column <= t.column + len
+        })
+    }
+
+    // LSP range covering `name` at `position`
+    fn range(&self, position: Position, name: &str) -> Json {
+        let text = self.line_text(position.line);
+        let start = column_to_utf16(text, position.column);
+        let end = start + name.encode_utf16().count() as u32;
+        lsp_range(position.line - 1, start, end)
+    }
+
+    // The whole of a line, for diagnostics that only know their line
+    fn line_range(&self, line: u32) -> Json {
+        let line = line.max(1);
+        let end = self.line_text(line).encode_utf16().count() as u32;
+        lsp_range(line - 1, 0, end)
+    }
+
+    fn location(&self, uri: &Json, position: Position, name: &str) -> Json {
+        Json::object(vec![
+            ("uri", uri.clone()),
+            ("range", self.range(position, name)),
+        ])
+    }
+
+    fn document_symbol(&self, symbols: &Symbols, index: usize, symbol: &Symbol) -> Json {
+        let kind = match symbol.kind {
+            SymbolKind::Class => 5,
+            SymbolKind::Method => 6,
+            SymbolKind::Function => 12,
+            SymbolKind::Variable | SymbolKind::Parameter => 13,
+        };
+        let range = self.range(symbol.position, &symbol.name);
+        let children: Vec<Json> = symbols
+            .children(index)
+            .filter(|(_, s)| s.kind != SymbolKind::Parameter)
+            .map(|(i, s)| self.document_symbol(symbols, i, s))
+            .collect();
+        Json::object(vec![
+            ("name", Json::string(&symbol.name)),
+            ("detail", Json::String(symbol.detail(symbols))),
+            ("kind", Json::from(kind)),
+            ("range", range.clone()),
+            ("selectionRange", range),
+            ("children", Json::Array(children)),
+        ])
+    }
+}
+
+// The local path a `file:` URI names, with escapes decoded
+fn file_path(uri: &str) -> Option<PathBuf> {
+    let path = uri
+        .strip_prefix("file://")
+        .map(|rest| rest.strip_prefix("localhost").unwrap_or(rest))
+        .filter(|path| path.starts_with('/'))?;
+    let mut bytes = Vec::new();
+    let mut rest = path.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) if b == b'%' => {
+                bytes.push(byte);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+// The line and character of `params.position`
+fn lsp_position(params: &Json) -> Result<(u32, u32), (i32, String)> {
+    let position = params.get("position");
+    match (
+        position.get("line").as_u32(),
+        position.get("character").as_u32(),
+    ) {
+        (Some(line), Some(character)) => Ok((line, character)),
+        _ => Err((INVALID_PARAMS, "Expected a position".to_string())),
+    }
+}
+
+fn lsp_range(line: u32, start: u32, end: u32) -> Json {
+    let position = |character: u32| {
+        Json::object(vec![
+            ("line", Json::from(line)),
+            ("character", Json::from(character)),
+        ])
+    };
+    Json::object(vec![("start", position(start)), ("end", position(end))])
+}
+
+// Character column, from 1, of a UTF-16 offset into a line
+fn utf16_to_column(line: &str, offset: u32) -> u32 {
+    let mut units = 0;
+    let mut column = 1;
+    for c in line.chars() {
+        if units >= offset {
+            break;
+        }
+        units += c.len_utf16() as u32;
+        column += 1;
+    }
+    column
+}
+
+// UTF-16 offset of a character column, from 1, in a line
+fn column_to_utf16(line: &str, column: u32) -> u32 {
+    line.chars()
+        .take(column.saturating_sub(1) as usize)
+        .map(|c| c.len_utf16() as u32)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_positions() {
+        let line = "a😀é b";
+        assert_eq!(utf16_to_column(line, 0), 1);
+        assert_eq!(utf16_to_column(line, 3), 3);
+        assert_eq!(utf16_to_column(line, 5), 5);
+        for column in 1..=6 {
+            assert_eq!(utf16_to_column(line, column_to_utf16(line, column)), column);
+        }
+    }
+
+    #[test]
+    fn test_file_path() {
+        assert_eq!(
+            file_path("file:///home/me/a%20b.lox"),
+            Some(PathBuf::from("/home/me/a b.lox"))
+        );
+        assert_eq!(
+            file_path("file://localhost/tmp/%C3%A9.lox"),
+            Some(PathBuf::from("/tmp/é.lox"))
+        );
+        assert_eq!(
+            file_path("file:///100%.lox"),
+            Some(PathBuf::from("/100%.lox"))
+        );
+        assert_eq!(file_path("untitled:Untitled-1"), None);
+        assert_eq!(file_path("file://server/share/a.lox"), None);
+    }
+
+    // The diagnostics published for each document opened
+    fn diagnostics(root: &Path, documents: &[(&str, &str)]) -> Vec<Json> {
+        let root = format!("file://{}", root.display());
+        let mut messages = vec![Json::object(vec![
+            ("id", Json::from(1)),
+            ("method", Json::string("initialize")),
+            (
+                "params",
+                Json::object(vec![("rootUri", Json::String(root))]),
+            ),
+        ])];
+        for (uri, text) in documents {
+            let document = Json::object(vec![
+                ("uri", Json::string(uri)),
+                ("text", Json::string(text)),
+            ]);
+            messages.push(Json::object(vec![
+                ("method", Json::string("textDocument/didOpen")),
+                ("params", Json::object(vec![("textDocument", document)])),
+            ]));
+        }
+        let mut input = Vec::new();
+        for message in &messages {
+            jsonrpc::write_message(&mut input, message).unwrap();
+        }
+
+        let mut output = Vec::new();
+        serve(&input[..], &mut output).unwrap();
+        let mut output = &output[..];
+        let mut published = Vec::new();
+        while let Some(body) = read_message(&mut output).unwrap() {
+            let message = Json::parse(&body).unwrap();
+            if message.get("method").as_str() == Some("textDocument/publishDiagnostics") {
+                published.push(message.get("params").get("diagnostics").clone());
+            }
+        }
+        published
+    }
+
+    #[test]
+    fn test_config() {
+        let root = std::env::temp_dir().join(format!("luxor-lsp-{}", std::process::id()));
+        fs::create_dir_all(root.join("sub dir")).unwrap();
+        fs::write(root.join("luxor.toml"), "[lint]\nunused-variable = false\n").unwrap();
+        let file = format!("file://{}/sub%20dir/a.lox", root.display());
+        let src = "var x = 1;\n";
+
+        // Found from the document's directory, and from the root for a
+        // document that isn't a file
+        let published = diagnostics(&root, &[(&file, src), ("untitled:1", src)]);
+        assert_eq!(published, vec![Json::Array(Vec::new()); 2]);
+
+        fs::write(root.join("luxor.toml"), "[lint]\nbogus = true\n").unwrap();
+        let published = diagnostics(&root, &[(&file, src)]);
+        let messages: Vec<_> = published[0]
+            .as_array()
+            .iter()
+            .map(|d| d.get("message").as_str().unwrap().to_string())
+            .collect();
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].contains("Unknown lint rule 'bogus'."));
+        assert_eq!(messages[1], "Variable is never used.");
+    }
+
+    #[test]
+    fn test_bad_json() {
+        let input = "Content-Length: 5\r\n\r\n{oops";
+        let mut output = Vec::new();
+        assert_eq!(serve(input.as_bytes(), &mut output).unwrap(), 1);
+        let output = String::from_utf8(output).unwrap();
+        let body = Json::parse(&output[output.find('{').unwrap()..]).unwrap();
+        assert_eq!(body.get("id"), &Json::Null);
+        assert_eq!(body.get("error").get("code"), &Json::Number(-32700.0));
+    }
+}
@@ -1,89 +1,342 @@
-use std::io::{self, Write};
-use std::{fs, process};
+use std::fs;
+use std::io::{self, Read};
+use std::process;
+use std::thread;
 
-mod ast;
-mod parser;
-mod scanner;
-mod token;
+use luxor::ast::Program;
+use luxor::parser::Parser;
+use luxor::scanner::Scanner;
+use luxor::{json, loxc, Capabilities, Error, GcOptions, Interpreter, Limits};
 
-use scanner::Scanner;
+mod cmd;
+mod line_editor;
+mod repl;
+
+const USAGE: &str = "\
+Usage: luxor [options] [script | -]
+       luxor compile [-o <file>] [file | -]
+       luxor fmt [--check] [file... | -]
+       luxor highlight [--html] [file | -]
+       luxor lint [--config <file>] [file... | -]
+       luxor lsp
+
+With no script, starts the interactive prompt. '-' reads the script from
+stdin. A script compiled by 'luxor compile' runs like the one it came from.
+
+Options:
+  -e <code>     Run <code> instead of a script
+  --tokens      Print the tokens of the script
+  --ast         Print the syntax tree as S-expressions
+  --ast-json    Print the syntax tree as JSON
+  --gc-stress   Collect garbage at every step, to test the collector
+  --gc-stats    Print what the garbage collector did when the run ends
+  -h, --help    Show this message
+
+The printing options may be combined; the script isn't run when any of
+them is given. See 'luxor <command> --help' for the compiler, formatter,
+highlighter, linter and language server.";
+
+// Of the thread everything runs on, so scripts can recurse more deeply than
+// the main thread's stack would allow
+const STACK_SIZE: usize = 64 << 20;
 
 fn main() {
-    let mut args = std::env::args();
-    let mut luxor = Luxor::new();
-
-    if args.len() > 2 {
-        println!("Usage: ./luxor [script]");
-        process::exit(64);
-    } else if args.len() == 2 {
-        let filename = args.next().unwrap();
-        luxor.run_file(&filename).unwrap();
-    } else {
-        luxor.run_prompt().unwrap();
+    let thread = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run)
+        .unwrap_or_else(|e| fail(&e.into()));
+    if thread.join().is_err() {
+        process::exit(101);
     }
 }
 
-#[derive(Default)]
-struct Luxor {
-    had_error: bool,
-}
+fn run() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("compile") => process::exit(cmd::compile::run(&args[1..])),
+        Some("fmt") => process::exit(cmd::fmt::run(&args[1..])),
+        Some("highlight") => process::exit(cmd::highlight::run(&args[1..])),
+        Some("lint") => process::exit(cmd::lint::run(&args[1..])),
+        Some("lsp") => process::exit(cmd::lsp::run(&args[1..])),
+        _ => {}
+    }
 
-impl Luxor {
-    fn new() -> Self {
-        Default::default()
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{}\n\n{}", msg, USAGE);
+            process::exit(64);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return;
     }
 
-    fn run_file(&mut self, f: &str) -> Result<(), io::Error> {
-        let src = fs::read_to_string(f)?;
-        self.run(&src);
-        Ok(())
+    let mut luxor = Luxor::new(&options);
+    let src = match &options.source {
+        Source::Prompt => {
+            if let Err(e) = luxor.run_prompt() {
+                eprintln!("{}", e);
+                process::exit(74);
+            }
+            return;
+        }
+        Source::File(path) => fs::read(path),
+        Source::Stdin => {
+            let mut src = Vec::new();
+            io::stdin().read_to_end(&mut src).map(|_| src)
+        }
+        Source::Inline(code) => Ok(code.clone().into_bytes()),
+    };
+    let src = src.unwrap_or_else(|e| fail(&e.into()));
+
+    // A .loxc file that doesn't start like one is a damaged one, not a script
+    let compiled = loxc::is_compiled(&src)
+        || matches!(&options.source, Source::File(path) if path.ends_with(".loxc"));
+    if compiled {
+        let program = loxc::load(&src).unwrap_or_else(|e| fail(&e.into()));
+        if options.tokens {
+            eprintln!("A compiled script has no tokens to print");
+            process::exit(64);
+        } else if options.dumps_anything() {
+            print_tree(&options, &program);
+        } else {
+            luxor.run_program(&program);
+        }
+        return;
     }
 
-    fn run_prompt(&mut self) -> Result<(), io::Error> {
-        let mut input = String::new();
-
-        loop {
-            print!("> ");
-            let _ = io::stdout().flush();
-
-            match io::stdin().read_line(&mut input) {
-                Ok(n) => {
-                    if n == 0 {
-                        // EOF
-                        return Ok(());
-                    } else {
-                        self.run(&input.trim());
-                    };
+    let src = String::from_utf8(src)
+        .unwrap_or_else(|e| fail(&io::Error::new(io::ErrorKind::InvalidData, e).into()));
+    if options.dumps_anything() {
+        dump(&options, &src);
+    } else {
+        luxor.run(&src);
+    }
+}
+
+// Where the program comes from
+#[derive(Debug, PartialEq)]
+enum Source {
+    Prompt,
+    File(String),
+    Stdin,
+    Inline(String),
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    source: Source,
+    tokens: bool,
+    ast: bool,
+    ast_json: bool,
+    gc_stress: bool,
+    gc_stats: bool,
+    help: bool,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options {
+            source: Source::Prompt,
+            tokens: false,
+            ast: false,
+            ast_json: false,
+            gc_stress: false,
+            gc_stats: false,
+            help: false,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let source = match arg.as_str() {
+                "--tokens" => {
+                    options.tokens = true;
+                    continue;
+                }
+                "--ast" => {
+                    options.ast = true;
+                    continue;
+                }
+                "--ast-json" => {
+                    options.ast_json = true;
+                    continue;
+                }
+                "--gc-stress" => {
+                    options.gc_stress = true;
+                    continue;
+                }
+                "--gc-stats" => {
+                    options.gc_stats = true;
+                    continue;
                 }
-                Err(e) => return Err(e),
+                "-h" | "--help" => {
+                    options.help = true;
+                    continue;
+                }
+                "-e" => match args.next() {
+                    Some(code) => Source::Inline(code.clone()),
+                    None => return Err("-e needs an argument".to_string()),
+                },
+                "-" => Source::Stdin,
+                flag if flag.starts_with('-') => {
+                    return Err(format!("Unknown option '{}'", flag));
+                }
+                path => Source::File(path.to_string()),
+            };
+
+            if options.source != Source::Prompt {
+                return Err("Only one script can be given".to_string());
             }
+            options.source = source;
+        }
 
-            input.clear();
+        if options.dumps_anything() && options.source == Source::Prompt {
+            return Err("Nothing to print, give a script or -e <code>".to_string());
+        }
+
+        Ok(options)
+    }
+
+    fn dumps_anything(&self) -> bool {
+        self.tokens || self.ast || self.ast_json
+    }
+}
+
+// Print the requested stages. Tokens come out even when the script doesn't
+// parse, since that's usually when they're wanted.
+fn dump(options: &Options, src: &str) {
+    let tokens = Scanner::new(src).scan_tokens().to_vec();
+    if options.tokens {
+        for t in &tokens {
+            println!("{:?}", t);
+        }
+    }
+
+    if options.ast || options.ast_json {
+        let program = Parser::new(tokens)
+            .parse()
+            .unwrap_or_else(|errors| fail(&Error::Syntax(errors)));
+        print_tree(options, &program);
+    }
+}
+
+fn print_tree(options: &Options, program: &Program) {
+    if options.ast {
+        for stmt in &program.statements {
+            println!("{}", program.exprs.sexp(stmt));
+        }
+    }
+    if options.ast_json {
+        println!("{}", json::program(program));
+    }
+}
+
+fn fail(e: &Error) -> ! {
+    eprintln!("{}", e);
+    process::exit(exit_code(e));
+}
+
+struct Luxor {
+    interpreter: Interpreter,
+    gc_stats: bool,
+}
+
+impl Luxor {
+    // Scripts run from the command line get full access to the machine
+    fn new(options: &Options) -> Self {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_capabilities(Capabilities::all());
+        interpreter.set_limits(Limits {
+            max_stack_bytes: Some(STACK_SIZE / 2),
+            ..Limits::default()
+        });
+        interpreter.set_gc_options(GcOptions {
+            stress: options.gc_stress,
+            ..GcOptions::default()
+        });
+        Luxor {
+            interpreter,
+            gc_stats: options.gc_stats,
         }
     }
 
-    // Scanner here
     fn run(&mut self, src: &str) {
-        let mut sc = Scanner::new(src);
-        let tokens = sc.scan_tokens();
+        let result = self.interpreter.eval_str(src);
+        self.print_gc_stats();
+        if let Err(e) = result {
+            fail(&e);
+        }
+    }
 
-        for t in tokens {
-            if t.is_error() {
-                if let token::TokenType::Error(err) = &t.kind {
-                    self.error(t.line, &err);
-                }
-            } else {
-                println!("{:?}", t);
-            }
+    fn run_program(&mut self, program: &Program) {
+        let result = self.interpreter.interpret(program);
+        self.print_gc_stats();
+        if let Err(e) = result {
+            fail(&e.into());
         }
     }
 
-    pub fn error(&mut self, line: u32, message: &str) {
-        self.report(line, "", message);
+    fn run_prompt(&mut self) -> Result<(), io::Error> {
+        let result = repl::run(&mut self.interpreter);
+        self.print_gc_stats();
+        result
+    }
+
+    // To stderr, so it doesn't mix with what the script prints
+    fn print_gc_stats(&self) {
+        if self.gc_stats {
+            let stats = self.interpreter.gc_stats();
+            eprintln!(
+                "gc: {} collections, {} objects freed, {} live",
+                stats.collections, stats.freed, stats.live
+            );
+        }
     }
+}
+
+// sysexits.h, like the reference implementation
+fn exit_code(e: &Error) -> i32 {
+    match e {
+        Error::Io(_) => 66,
+        Error::Syntax(_) | Error::Load(_) => 65,
+        Error::Runtime(_) => 70,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        Options::parse(&args)
+    }
+
+    #[test]
+    fn test_options() {
+        assert_eq!(parse(&[]).unwrap().source, Source::Prompt);
+        assert_eq!(
+            parse(&["script.lox"]).unwrap().source,
+            Source::File("script.lox".to_string())
+        );
+
+        let options = parse(&["--tokens", "--ast-json", "-"]).unwrap();
+        assert!(options.tokens && options.ast_json && !options.ast);
+        assert_eq!(options.source, Source::Stdin);
+
+        assert_eq!(
+            parse(&["-e", "print 1;", "--ast"]).unwrap().source,
+            Source::Inline("print 1;".to_string())
+        );
+
+        assert!(parse(&["-e"]).is_err());
+        assert!(parse(&["--ast"]).is_err());
+        assert!(parse(&["a.lox", "b.lox"]).is_err());
+        assert!(parse(&["--bogus"]).is_err());
 
-    fn report(&mut self, line: u32, whe: &str, message: &str) {
-        eprintln!("[line {}] Error{}: {}", line, whe, message);
-        self.had_error = true;
+        let options = parse(&["--gc-stress", "--gc-stats", "a.lox"]).unwrap();
+        assert!(options.gc_stress && options.gc_stats);
     }
 }
@@ -0,0 +1,187 @@
+// Maps: a hash map from the values that can be hashed to any value, which
+// remembers the order keys were first inserted in
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+use crate::error::RuntimeError;
+use crate::function::{Arity, NativeFunction};
+use crate::value::Value;
+
+// A value that can be a map key
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    Nil,
+    Bool(bool),
+    // The bits of the number, with -0 folded into 0 as `==` would
+    Number(u64),
+    Str(String),
+}
+
+impl Key {
+    pub fn from_value(value: &Value) -> Result<Key, RuntimeError> {
+        match value {
+            Value::Nil => Ok(Key::Nil),
+            Value::Bool(b) => Ok(Key::Bool(*b)),
+            Value::Number(n) if *n == 0.0 => Ok(Key::Number(0f64.to_bits())),
+            Value::Number(n) => Ok(Key::Number(n.to_bits())),
+            Value::Str(s) => Ok(Key::Str(s.clone())),
+            _ => Err("Map key must be a string, number, boolean or nil.".into()),
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            Key::Nil => Value::Nil,
+            Key::Bool(b) => Value::Bool(*b),
+            Key::Number(bits) => Value::Number(f64::from_bits(*bits)),
+            Key::Str(s) => Value::Str(s.clone()),
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_value())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LoxMap {
+    entries: Vec<(Key, Value)>,
+    // Where each key's entry is in `entries`
+    index: HashMap<Key, usize>,
+}
+
+impl LoxMap {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &Key) -> Option<&Value> {
+        self.index.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn contains_key(&self, key: &Key) -> bool {
+        self.index.contains_key(key)
+    }
+
+    // A key that's already there keeps its place; returns the value it had
+    pub fn insert(&mut self, key: Key, value: Value) -> Option<Value> {
+        match self.index.get(&key) {
+            Some(&i) => Some(mem::replace(&mut self.entries[i].1, value)),
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+                None
+            }
+        }
+    }
+
+    // Linear in the number of entries after the removed one
+    pub fn remove(&mut self, key: &Key) -> Option<Value> {
+        let i = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        for (key, _) in &self.entries[i..] {
+            if let Some(j) = self.index.get_mut(key) {
+                *j -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    // Entries in insertion order
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &Value)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+}
+
+// The method `name` bound to `map`, `None` if maps have no such method
+pub(crate) fn method(map: &Rc<RefCell<LoxMap>>, name: &str) -> Option<Value> {
+    let arity = match name {
+        "keys" | "len" | "values" => Arity::Exact(0),
+        "has" | "remove" => Arity::Exact(1),
+        _ => return None,
+    };
+
+    let map = Rc::clone(map);
+    let method = name.to_string();
+    let function = NativeFunction::new(name, arity, move |args| {
+        match method.as_str() {
+            "keys" => Ok(Value::list(
+                map.borrow().iter().map(|(key, _)| key.to_value()).collect(),
+            )),
+            "len" => Ok(Value::Number(map.borrow().len() as f64)),
+            "values" => Ok(Value::list(
+                map.borrow()
+                    .iter()
+                    .map(|(_, value)| value.clone())
+                    .collect(),
+            )),
+            "has" => Ok(Value::Bool(
+                map.borrow().contains_key(&Key::from_value(&args[0])?),
+            )),
+            // nil when the key isn't there
+            "remove" => Ok(map
+                .borrow_mut()
+                .remove(&Key::from_value(&args[0])?)
+                .unwrap_or(Value::Nil)),
+            _ => unreachable!(),
+        }
+    });
+    Some(Value::Native(Rc::new(function)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(s: &str) -> Key {
+        Key::Str(s.to_string())
+    }
+
+    #[test]
+    fn test_insertion_order() {
+        let mut map = LoxMap::new();
+        for (i, k) in ["c", "a", "b", "d"].iter().enumerate() {
+            map.insert(key(k), Value::Number(i as f64));
+        }
+        // Replacing a value keeps the key where it was
+        assert_eq!(map.insert(key("a"), Value::Nil), Some(Value::Number(1.0)));
+        assert_eq!(map.remove(&key("c")), Some(Value::Number(0.0)));
+        assert_eq!(map.remove(&key("c")), None);
+
+        let keys: Vec<String> = map.iter().map(|(k, _)| k.to_string()).collect();
+        assert_eq!(keys, ["a", "b", "d"]);
+        assert_eq!(map.get(&key("d")), Some(&Value::Number(3.0)));
+        assert_eq!(map.get(&key("a")), Some(&Value::Nil));
+        assert_eq!(map.len(), 3);
+    }
+
+    #[test]
+    fn test_keys() {
+        assert_eq!(
+            Key::from_value(&Value::Number(-0.0)),
+            Key::from_value(&Value::Number(0.0))
+        );
+        assert_ne!(
+            Key::from_value(&Value::Number(1.0)),
+            Key::from_value(&Value::Str("1".to_string()))
+        );
+        assert_eq!(
+            Key::from_value(&Value::list(vec![])),
+            Err("Map key must be a string, number, boolean or nil.".into())
+        );
+    }
+}
@@ -0,0 +1,87 @@
+// Built-in global functions
+
+use std::io::{self, BufRead};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, fs};
+
+use crate::capability::Capability;
+use crate::error::RuntimeError;
+use crate::function::Arity;
+use crate::interpreter::Interpreter;
+use crate::value::{Range, Value};
+
+pub(crate) fn define_globals(interpreter: &mut Interpreter) {
+    interpreter.define_io_native("clock", 0, Capability::Clock, |_| {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| RuntimeError::from(e.to_string()))?;
+        Ok(Value::Number(now.as_secs_f64()))
+    });
+
+    // The variable's value, or nil if it isn't set
+    interpreter.define_io_native("getenv", 1, Capability::Env, |args| {
+        let name = string_arg(&args[0])?;
+        Ok(env::var(name).map(Value::Str).unwrap_or(Value::Nil))
+    });
+
+    interpreter.define_io_native("readFile", 1, Capability::FileRead, |args| {
+        let path = string_arg(&args[0])?;
+        fs::read_to_string(path)
+            .map(Value::Str)
+            .map_err(|e| format!("Could not read '{}': {}.", path, e).into())
+    });
+
+    interpreter.define_io_native("writeFile", 2, Capability::FileWrite, |args| {
+        let path = string_arg(&args[0])?;
+        fs::write(path, args[1].to_string())
+            .map(|_| Value::Nil)
+            .map_err(|e| format!("Could not write '{}': {}.", path, e).into())
+    });
+
+    // range(end), range(start, end) or range(start, end, step): the numbers
+    // from `start` (0 by default) up to but not including `end`. Loops work
+    // them out one at a time, so a range costs the same however long it is.
+    interpreter.define_native("range", Arity::Between(1, 3), |args| {
+        let numbers = args
+            .iter()
+            .map(|arg| match arg {
+                Value::Number(n) if n.is_finite() => Ok(*n),
+                Value::Number(_) => Err("Range bounds must be finite.".into()),
+                _ => Err("Range bounds must be numbers.".into()),
+            })
+            .collect::<Result<Vec<f64>, RuntimeError>>()?;
+        let (start, end, step) = match numbers[..] {
+            [end] => (0.0, end, 1.0),
+            [start, end] => (start, end, 1.0),
+            [start, end, step] => (start, end, step),
+            _ => unreachable!(),
+        };
+        if step == 0.0 {
+            return Err("Range step can't be 0.".into());
+        }
+
+        if !((end - start) / step).is_finite() {
+            return Err("Range bounds must be finite.".into());
+        }
+        Ok(Value::Range(Range { start, end, step }))
+    });
+
+    // A line without its terminator, or nil at the end of input
+    interpreter.define_io_native("readLine", 0, Capability::Stdin, |_| {
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) => Ok(Value::Nil),
+            Ok(_) => Ok(Value::Str(
+                line.trim_end_matches(&['\r', '\n'][..]).to_string(),
+            )),
+            Err(e) => Err(e.to_string().into()),
+        }
+    });
+}
+
+fn string_arg(value: &Value) -> Result<&str, RuntimeError> {
+    match value {
+        Value::Str(s) => Ok(s),
+        _ => Err("Argument must be a string.".into()),
+    }
+}
@@ -1,43 +1,625 @@
 // Recursive descent
 
+use std::rc::Rc;
+
 use crate::ast::*;
+use crate::error::SyntaxError;
+use crate::resolver;
 use crate::token::*;
 
+type ParseResult<T> = Result<T, SyntaxError>;
+
+// How deeply statements and expressions may nest, so a pathological script
+// can't overflow the stack. Blocks, bodies, arguments and grouped or
+// prefixed expressions count as a level; the parser recurses for each, and
+// so does every pass over the tree afterwards.
+const MAX_NESTING: usize = 100;
+
+// How deep the tree may get. The operators, calls and properties of a chain
+// like `a + b + c` are parsed in a loop, but each nests the ones before it
+// in the tree, which later passes recurse into. A level costs those passes
+// far less stack than a level of `MAX_NESTING` costs the parser.
+const MAX_TREE_DEPTH: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum FunctionKind {
+    None,
+    Function,
+    Initializer,
+    Method,
+}
+
+impl FunctionKind {
+    fn describe(self) -> &'static str {
+        match self {
+            FunctionKind::Function => "function",
+            _ => "method",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ClassKind {
+    None,
+    Class,
+    Subclass,
+}
+
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    errors: Vec<SyntaxError>,
+    exprs: ExprArena,
+    // What kind of function and class body encloses the current token
+    current_function: FunctionKind,
+    current_class: ClassKind,
+    // How many loops enclose the current token within its function
+    loop_depth: usize,
+    // How deeply the current token is nested, see `MAX_NESTING`
+    depth: usize,
+    // How deep in the tree the current token is, see `MAX_TREE_DEPTH`
+    tree_depth: usize,
+    // Set by `parse_repl`: the last statement may leave off its ';'
+    allow_trailing_expression: bool,
+    trailing_expression: bool,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, current: 0 }
+        // The scanner leaves its errors in the stream; collect them up front
+        // so the grammar never has to look at them.
+        let mut errors = Vec::new();
+        let mut kept = Vec::with_capacity(tokens.len());
+        for t in tokens {
+            if let TokenType::Error(msg) = &t.kind {
+                errors.push(SyntaxError::new(t.line, "", msg));
+            } else {
+                kept.push(t);
+            }
+        }
+
+        Parser {
+            tokens: kept,
+            current: 0,
+            errors,
+            exprs: ExprArena::new(),
+            current_function: FunctionKind::None,
+            current_class: ClassKind::None,
+            loop_depth: 0,
+            depth: 0,
+            tree_depth: 0,
+            allow_trailing_expression: false,
+            trailing_expression: false,
+        }
+    }
+
+    pub fn parse(mut self) -> Result<Program, Vec<SyntaxError>> {
+        self.program()
+    }
+
+    // program -> declaration* EOF ;
+    fn program(&mut self) -> Result<Program, Vec<SyntaxError>> {
+        let mut statements = Vec::new();
+
+        while !self.at_end() {
+            match self.declaration() {
+                Ok(stmt) => statements.push(stmt),
+                Err(e) => {
+                    self.errors.push(e);
+                    self.synchronize();
+                }
+            }
+        }
+
+        let exprs = std::mem::take(&mut self.exprs);
+        self.finish(exprs, statements)
+    }
+
+    // Parses the next top-level declaration as a program of its own, for
+    // tools that reparse a script a piece at a time. `None` at the end.
+    pub fn parse_declaration(&mut self) -> Option<Result<Program, Vec<SyntaxError>>> {
+        if self.at_end() {
+            return None;
+        }
+
+        let result = self.declaration();
+        let exprs = std::mem::take(&mut self.exprs);
+        Some(match result {
+            Ok(stmt) => self.finish(exprs, vec![stmt]),
+            Err(e) => {
+                self.errors.push(e);
+                self.synchronize();
+                Err(std::mem::take(&mut self.errors))
+            }
+        })
+    }
+
+    // Resolves a program that parsed cleanly; scoping errors are only looked
+    // for once there are no others
+    fn finish(
+        &mut self,
+        exprs: ExprArena,
+        statements: Vec<Stmt>,
+    ) -> Result<Program, Vec<SyntaxError>> {
+        if self.errors.is_empty() {
+            let (locals, errors) = resolver::resolve(&exprs, &statements);
+            self.errors = errors;
+            if self.errors.is_empty() {
+                return Ok(Program {
+                    exprs: Rc::new(exprs),
+                    statements,
+                    locals: Rc::new(locals),
+                });
+            }
+        }
+        self.errors.sort_by_key(|e| e.line);
+        Err(std::mem::take(&mut self.errors))
+    }
+
+    // Index of the next token `parse_declaration` would look at, counting
+    // only the tokens that aren't errors
+    pub fn position(&self) -> usize {
+        self.current
+    }
+
+    // Like `parse`, but the input may end with an expression that has no ';'.
+    // The flag says whether it did, in which case the last statement is
+    // that expression.
+    pub fn parse_repl(mut self) -> Result<(Program, bool), Vec<SyntaxError>> {
+        self.allow_trailing_expression = true;
+        let program = self.program()?;
+        Ok((program, self.trailing_expression))
+    }
+
+    // declaration -> classDecl | funDecl | varDecl | statement ;
+    fn declaration(&mut self) -> ParseResult<Stmt> {
+        if self.match_tokens(&[TokenType::Class]) {
+            return self.class_declaration();
+        }
+        if self.match_tokens(&[TokenType::Fun]) {
+            return Ok(Stmt::Function(self.function(FunctionKind::Function)?));
+        }
+        if self.match_tokens(&[TokenType::Var]) {
+            return self.var_declaration();
+        }
+
+        self.statement()
+    }
+
+    // classDecl -> "class" IDENTIFIER ( "<" IDENTIFIER )? "{" function* "}" ;
+    fn class_declaration(&mut self) -> ParseResult<Stmt> {
+        let name = self.consume_identifier("Expect class name.")?;
+
+        let superclass = if self.match_tokens(&[TokenType::Less]) {
+            let superclass = self.consume_identifier("Expect superclass name.")?;
+            if superclass.name() == name.name() {
+                let e = self.error(&superclass, "A class can't inherit from itself.");
+                self.errors.push(e);
+            }
+            Some(self.push(Expr::Variable { name: superclass }))
+        } else {
+            None
+        };
+
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
+
+        let enclosing = self.current_class;
+        self.current_class = if superclass.is_some() {
+            ClassKind::Subclass
+        } else {
+            ClassKind::Class
+        };
+
+        let mut methods = Vec::new();
+        let mut result = Ok(());
+        while !self.check(&TokenType::RightBrace) && !self.at_end() {
+            match self.function(FunctionKind::Method) {
+                Ok(method) => methods.push(method),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        self.current_class = enclosing;
+        result?;
+
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
+
+        Ok(Stmt::Class {
+            name,
+            superclass,
+            methods,
+        })
+    }
+
+    // funDecl  -> "fun" function ;
+    // function -> IDENTIFIER "(" parameters? ")" block ;
+    fn function(&mut self, kind: FunctionKind) -> ParseResult<Rc<Function>> {
+        self.nested(|parser| parser.nested_function(kind))
+    }
+
+    fn nested_function(&mut self, mut kind: FunctionKind) -> ParseResult<Rc<Function>> {
+        let name = self.consume_identifier(&format!("Expect {} name.", kind.describe()))?;
+        if kind == FunctionKind::Method && name.name() == "init" {
+            kind = FunctionKind::Initializer;
+        }
+
+        self.consume(
+            TokenType::LeftParen,
+            &format!("Expect '(' after {} name.", kind.describe()),
+        )?;
+
+        // parameters -> IDENTIFIER ( "," IDENTIFIER )* ;
+        let mut params = Vec::new();
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if params.len() >= 255 {
+                    let e = self.error(self.peek(), "Can't have more than 255 parameters.");
+                    self.errors.push(e);
+                }
+
+                params.push(self.consume_identifier("Expect parameter name.")?);
+
+                if !self.match_tokens(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
+
+        self.consume(
+            TokenType::LeftBrace,
+            &format!("Expect '{{' before {} body.", kind.describe()),
+        )?;
+        // A loop around the declaration doesn't reach into the body
+        let enclosing = std::mem::replace(&mut self.current_function, kind);
+        let loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+        let block = self.block();
+        self.current_function = enclosing;
+        self.loop_depth = loop_depth;
+
+        let (body, brace) = block?;
+        Ok(Rc::new(Function {
+            name,
+            params,
+            body,
+            brace,
+        }))
+    }
+
+    // varDecl -> "var" IDENTIFIER ( "=" expression )? ";" ;
+    fn var_declaration(&mut self) -> ParseResult<Stmt> {
+        let name = self.consume_identifier("Expect variable name.")?;
+
+        let initializer = if self.match_tokens(&[TokenType::Equal]) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        )?;
+        Ok(Stmt::Var { name, initializer })
+    }
+
+    // statement -> breakStmt | continueStmt | exprStmt | forStmt | ifStmt
+    //            | printStmt | returnStmt | whileStmt | block ;
+    fn statement(&mut self) -> ParseResult<Stmt> {
+        self.nested(Self::nested_statement)
+    }
+
+    fn nested_statement(&mut self) -> ParseResult<Stmt> {
+        if self.match_tokens(&[TokenType::Break, TokenType::Continue]) {
+            return self.loop_jump();
+        }
+        if self.match_tokens(&[TokenType::For]) {
+            return self.for_statement();
+        }
+        if self.match_tokens(&[TokenType::If]) {
+            return self.if_statement();
+        }
+        if self.match_tokens(&[TokenType::Print]) {
+            return self.print_statement();
+        }
+        if self.match_tokens(&[TokenType::Return]) {
+            return self.return_statement();
+        }
+        if self.match_tokens(&[TokenType::While]) {
+            return self.while_statement();
+        }
+        if self.match_tokens(&[TokenType::LeftBrace]) {
+            let (statements, brace) = self.block()?;
+            return Ok(Stmt::Block { statements, brace });
+        }
+
+        self.expression_statement()
+    }
+
+    // forStmt -> "for" "(" ( varDecl | exprStmt | ";" )
+    //            expression? ";" expression? ")" statement
+    //          | "for" "(" "var" IDENTIFIER "in" expression ")" statement ;
+    fn for_statement(&mut self) -> ParseResult<Stmt> {
+        let keyword = self.previous().clone();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
+
+        if self.check(&TokenType::Var) && self.check_ahead(2, &TokenType::In) {
+            return self.for_in_statement(keyword);
+        }
+
+        let initializer = if self.match_tokens(&[TokenType::Semicolon]) {
+            None
+        } else if self.match_tokens(&[TokenType::Var]) {
+            Some(Box::new(self.var_declaration()?))
+        } else {
+            Some(Box::new(self.expression_statement()?))
+        };
+
+        let condition = if !self.check(&TokenType::Semicolon) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
+
+        let increment = if !self.check(&TokenType::RightParen) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+
+        let body = Box::new(self.loop_body()?);
+        Ok(Stmt::For {
+            keyword,
+            initializer,
+            condition,
+            increment,
+            body,
+        })
+    }
+
+    fn for_in_statement(&mut self, keyword: Token) -> ParseResult<Stmt> {
+        // The 'var'
+        self.advance();
+        let name = self.consume_identifier("Expect variable name.")?;
+        self.consume(TokenType::In, "Expect 'in' after loop variable.")?;
+        let iterable = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after for-in clause.")?;
+
+        let body = Box::new(self.loop_body()?);
+        Ok(Stmt::ForIn {
+            keyword,
+            name,
+            iterable,
+            body,
+        })
+    }
+
+    // ifStmt -> "if" "(" expression ")" statement ( "else" statement )? ;
+    fn if_statement(&mut self) -> ParseResult<Stmt> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
+
+        let then_branch = Box::new(self.statement()?);
+        let else_branch = if self.match_tokens(&[TokenType::Else]) {
+            Some(Box::new(self.statement()?))
+        } else {
+            None
+        };
+
+        Ok(Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        })
+    }
+
+    // printStmt -> "print" expression ";" ;
+    fn print_statement(&mut self) -> ParseResult<Stmt> {
+        let expression = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
+        Ok(Stmt::Print { expression })
+    }
+
+    // returnStmt -> "return" expression? ";" ;
+    fn return_statement(&mut self) -> ParseResult<Stmt> {
+        let keyword = self.previous().clone();
+        if self.current_function == FunctionKind::None {
+            let e = self.error(&keyword, "Can't return from top-level code.");
+            self.errors.push(e);
+        }
+
+        let value = if !self.check(&TokenType::Semicolon) {
+            if self.current_function == FunctionKind::Initializer {
+                let e = self.error(&keyword, "Can't return a value from an initializer.");
+                self.errors.push(e);
+            }
+            Some(self.expression()?)
+        } else {
+            None
+        };
+
+        self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+        Ok(Stmt::Return { keyword, value })
+    }
+
+    // breakStmt -> "break" ";" ;
+    // continueStmt -> "continue" ";" ;
+    fn loop_jump(&mut self) -> ParseResult<Stmt> {
+        let keyword = self.previous().clone();
+        if self.loop_depth == 0 {
+            let e = self.error(&keyword, &format!("Can't use '{}' outside of a loop.", keyword));
+            self.errors.push(e);
+        }
+
+        self.consume(TokenType::Semicolon, &format!("Expect ';' after '{}'.", keyword))?;
+        Ok(match keyword.kind {
+            TokenType::Break => Stmt::Break { keyword },
+            _ => Stmt::Continue { keyword },
+        })
+    }
+
+    fn loop_body(&mut self) -> ParseResult<Stmt> {
+        self.loop_depth += 1;
+        let body = self.statement();
+        self.loop_depth -= 1;
+        body
+    }
+
+    // whileStmt -> "while" "(" expression ")" statement ;
+    fn while_statement(&mut self) -> ParseResult<Stmt> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+        let body = Box::new(self.loop_body()?);
+
+        Ok(Stmt::While { condition, body })
     }
 
-    // expression -> equality ;
-    fn expression(&mut self) -> Box<Expr> {
-        self.equality()
+    // block -> "{" declaration* "}" ;
+    fn block(&mut self) -> ParseResult<(Vec<Stmt>, Token)> {
+        let mut statements = Vec::new();
+
+        while !self.check(&TokenType::RightBrace) && !self.at_end() {
+            statements.push(self.declaration()?);
+        }
+
+        let brace = self
+            .consume(TokenType::RightBrace, "Expect '}' after block.")?
+            .clone();
+        Ok((statements, brace))
+    }
+
+    // exprStmt -> expression ";" ;
+    fn expression_statement(&mut self) -> ParseResult<Stmt> {
+        let expression = self.expression()?;
+        // Only a statement of its own at the top level, not the body of an
+        // `if` or loop, can be the one that's echoed
+        let top_level = self.depth == 1;
+        if self.allow_trailing_expression && top_level && self.at_end() {
+            self.trailing_expression = true;
+            return Ok(Stmt::Expression { expression });
+        }
+        self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+        Ok(Stmt::Expression { expression })
+    }
+
+    // expression -> assignment ;
+    fn expression(&mut self) -> ParseResult<ExprId> {
+        self.nested(Self::assignment)
+    }
+
+    // assignment -> ( call "." )? IDENTIFIER "=" assignment
+    //             | call "[" expression "]" "=" assignment | logic_or ;
+    fn assignment(&mut self) -> ParseResult<ExprId> {
+        let expr = self.or()?;
+
+        if self.match_tokens(&[TokenType::Equal]) {
+            let equals = self.previous().clone();
+            let value = self.nested(Self::assignment)?;
+
+            // The target turns into the assignment, keeping its id
+            let assignment = match &self.exprs[expr] {
+                Expr::Variable { name } => Expr::Assign {
+                    name: name.clone(),
+                    value,
+                },
+                Expr::Get { object, name } => Expr::Set {
+                    object: *object,
+                    name: name.clone(),
+                    value,
+                },
+                Expr::Index {
+                    object,
+                    bracket,
+                    index,
+                } => Expr::SetIndex {
+                    object: *object,
+                    bracket: bracket.clone(),
+                    index: *index,
+                    value,
+                },
+                _ => {
+                    // Report without synchronizing, the parser isn't confused
+                    let e = self.error(&equals, "Invalid assignment target.");
+                    self.errors.push(e);
+                    return Ok(expr);
+                }
+            };
+            self.exprs[expr] = assignment;
+        }
+
+        Ok(expr)
+    }
+
+    // logic_or -> logic_and ( "or" logic_and )* ;
+    fn or(&mut self) -> ParseResult<ExprId> {
+        let tree_depth = self.tree_depth;
+        let mut expr = self.and()?;
+
+        while self.match_tokens(&[TokenType::Or]) {
+            self.lengthen()?;
+            let operator = self.previous().clone();
+            let right = self.and()?;
+            expr = self.push(Expr::Logical {
+                left: expr,
+                operator,
+                right,
+            });
+        }
+
+        self.tree_depth = tree_depth;
+        Ok(expr)
+    }
+
+    // logic_and -> equality ( "and" equality )* ;
+    fn and(&mut self) -> ParseResult<ExprId> {
+        let tree_depth = self.tree_depth;
+        let mut expr = self.equality()?;
+
+        while self.match_tokens(&[TokenType::And]) {
+            self.lengthen()?;
+            let operator = self.previous().clone();
+            let right = self.equality()?;
+            expr = self.push(Expr::Logical {
+                left: expr,
+                operator,
+                right,
+            });
+        }
+
+        self.tree_depth = tree_depth;
+        Ok(expr)
     }
 
     // equality -> comparison ( ( "!=" | "==" ) comparison )* ;
-    fn equality(&mut self) -> Box<Expr> {
-        let expr = self.comparison();
+    fn equality(&mut self) -> ParseResult<ExprId> {
+        let tree_depth = self.tree_depth;
+        let mut expr = self.comparison()?;
 
-        while self.match_tokens(&[TokenType::BangEqual, TokenType::Equal]) {
-            let operator = self.previous();
-            let right = self.comparison();
-            expr = Box::new(Expr::Binary {
+        while self.match_tokens(&[TokenType::BangEqual, TokenType::EqualEqual]) {
+            self.lengthen()?;
+            let operator = self.previous().clone();
+            let right = self.comparison()?;
+            expr = self.push(Expr::Binary {
                 left: expr,
-                operator: *operator.clone(),
+                operator,
                 right,
             });
         }
 
-        expr
+        self.tree_depth = tree_depth;
+        Ok(expr)
     }
 
-    // comparison -> term ( ( "<" | "<=" | "<" | "<=" ) term )* ;
-    fn comparison(&mut self) -> Box<Expr> {
-        let expr = self.term();
+    // comparison -> term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
+    fn comparison(&mut self) -> ParseResult<ExprId> {
+        let tree_depth = self.tree_depth;
+        let mut expr = self.term()?;
 
         while self.match_tokens(&[
             TokenType::Greater,
@@ -45,90 +627,357 @@ impl Parser {
             TokenType::Less,
             TokenType::LessEqual,
         ]) {
-            let operator = self.previous();
-            let right = self.term();
-            expr = Box::new(Expr::Binary {
+            self.lengthen()?;
+            let operator = self.previous().clone();
+            let right = self.term()?;
+            expr = self.push(Expr::Binary {
                 left: expr,
-                operator: *operator.clone(),
+                operator,
                 right,
             });
         }
 
-        expr
+        self.tree_depth = tree_depth;
+        Ok(expr)
     }
 
     // term -> factor ( ( "-" | "+" ) factor )* ;
-    fn term(&mut self) -> Box<Expr> {
-        let expr = self.factor();
+    fn term(&mut self) -> ParseResult<ExprId> {
+        let tree_depth = self.tree_depth;
+        let mut expr = self.factor()?;
 
         while self.match_tokens(&[TokenType::Minus, TokenType::Plus]) {
-            let operator = self.previous();
-            let right = self.factor();
-            expr = Box::new(Expr::Binary {
+            self.lengthen()?;
+            let operator = self.previous().clone();
+            let right = self.factor()?;
+            expr = self.push(Expr::Binary {
                 left: expr,
-                operator: *operator.clone(),
+                operator,
                 right,
             });
         }
 
-        expr
+        self.tree_depth = tree_depth;
+        Ok(expr)
     }
 
     // factor -> unary ( ( "/" | "*" ) unary )* ;
-    fn factor(&self) -> Box<Expr> {
-        let expr = self.unary();
+    fn factor(&mut self) -> ParseResult<ExprId> {
+        let tree_depth = self.tree_depth;
+        let mut expr = self.unary()?;
 
         while self.match_tokens(&[TokenType::Slash, TokenType::Star]) {
-            let operator = self.previous();
-            let right = self.unary();
-            expr = Box::new(Expr::Binary {
+            self.lengthen()?;
+            let operator = self.previous().clone();
+            let right = self.unary()?;
+            expr = self.push(Expr::Binary {
                 left: expr,
-                operator: *operator.clone(),
+                operator,
                 right,
             });
         }
 
-        expr
+        self.tree_depth = tree_depth;
+        Ok(expr)
     }
 
-    // unary -> ( "!" | "-" ) unary | primary ;
-    fn unary(&self) -> Box<Expr> {
+    // unary -> ( "!" | "-" ) unary | call ;
+    fn unary(&mut self) -> ParseResult<ExprId> {
         if self.match_tokens(&[TokenType::Bang, TokenType::Minus]) {
-            let operator = self.previous();
-            let right = self.unary();
-            return Box::new(Expr::Unary {
-                operator: *operator.clone(),
-                right
-            })
+            let operator = self.previous().clone();
+            let right = self.nested(Self::unary)?;
+            return Ok(self.push(Expr::Unary { operator, right }));
         }
 
-        self.primary()
+        self.call()
     }
 
-    fn primary(&mut self) -> Box<Expr> {
-        if self.match_tokens(&[TokenType::False]) {
+    // call -> primary ( "(" arguments? ")" | "." IDENTIFIER | "[" subscript "]" )* ;
+    fn call(&mut self) -> ParseResult<ExprId> {
+        let tree_depth = self.tree_depth;
+        let mut expr = self.primary()?;
 
+        // Each call, subscript or property nests the ones before it
+        loop {
+            if self.match_tokens(&[TokenType::LeftParen]) {
+                self.lengthen()?;
+                expr = self.finish_call(expr)?;
+            } else if self.match_tokens(&[TokenType::LeftBracket]) {
+                self.lengthen()?;
+                expr = self.subscript(expr)?;
+            } else if self.match_tokens(&[TokenType::Dot]) {
+                self.lengthen()?;
+                let name = self.consume_identifier("Expect property name after '.'.")?;
+                expr = self.push(Expr::Get { object: expr, name });
+            } else {
+                break;
+            }
         }
-        if self.match_tokens(&[TokenType::True]) {
 
+        self.tree_depth = tree_depth;
+        Ok(expr)
+    }
+
+    // arguments -> expression ( "," expression )* ;
+    fn finish_call(&mut self, callee: ExprId) -> ParseResult<ExprId> {
+        let mut arguments = Vec::new();
+
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if arguments.len() >= 255 {
+                    let e = self.error(self.peek(), "Can't have more than 255 arguments.");
+                    self.errors.push(e);
+                }
+
+                arguments.push(self.expression()?);
+
+                if !self.match_tokens(&[TokenType::Comma]) {
+                    break;
+                }
+            }
         }
-        if self.match_tokens(&[TokenType::Nil]) {
 
+        let paren = self
+            .consume(TokenType::RightParen, "Expect ')' after arguments.")?
+            .clone();
+
+        Ok(self.push(Expr::Call {
+            callee,
+            paren,
+            arguments,
+        }))
+    }
+
+    // subscript -> expression | expression? ":" expression? ;
+    fn subscript(&mut self, object: ExprId) -> ParseResult<ExprId> {
+        let start = if self.check(&TokenType::Colon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+
+        if let Some(index) = start {
+            if !self.check(&TokenType::Colon) {
+                let bracket = self
+                    .consume(TokenType::RightBracket, "Expect ']' after index.")?
+                    .clone();
+                return Ok(self.push(Expr::Index {
+                    object,
+                    bracket,
+                    index,
+                }));
+            }
+        }
+
+        // The ':'
+        self.advance();
+        let end = if self.check(&TokenType::RightBracket) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        let bracket = self
+            .consume(TokenType::RightBracket, "Expect ']' after slice.")?
+            .clone();
+        Ok(self.push(Expr::Slice {
+            object,
+            bracket,
+            start,
+            end,
+        }))
+    }
+
+    // primary -> "true" | "false" | "nil" | "this" | NUMBER | STRING
+    //          | IDENTIFIER | "(" expression ")" | "super" "." IDENTIFIER
+    //          | "[" ( expression ( "," expression )* )? "]"
+    //          | "{" ( entry ( "," entry )* )? "}" ;
+    // entry -> expression ":" expression ;
+    //
+    // A "{" that starts a statement opens a block, so a map literal can only
+    // start an expression that's nested in something else.
+    fn primary(&mut self) -> ParseResult<ExprId> {
+        if self.match_tokens(&[TokenType::False, TokenType::True, TokenType::Nil]) {
+            return Ok(self.push(Expr::Lit(self.previous().clone())));
+        }
+
+        if self.match_tokens(&[TokenType::This]) {
+            let keyword = self.previous().clone();
+            if self.current_class == ClassKind::None {
+                let e = self.error(&keyword, "Can't use 'this' outside of a class.");
+                self.errors.push(e);
+            }
+            return Ok(self.push(Expr::This { keyword }));
+        }
+
+        if self.match_tokens(&[TokenType::Super]) {
+            let keyword = self.previous().clone();
+            match self.current_class {
+                ClassKind::None => {
+                    let e = self.error(&keyword, "Can't use 'super' outside of a class.");
+                    self.errors.push(e);
+                }
+                ClassKind::Class => {
+                    let e =
+                        self.error(&keyword, "Can't use 'super' in a class with no superclass.");
+                    self.errors.push(e);
+                }
+                ClassKind::Subclass => (),
+            }
+            self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
+            let method = self.consume_identifier("Expect superclass method name.")?;
+            return Ok(self.push(Expr::Super { keyword, method }));
+        }
+
+        if self.is_identifier() {
+            let name = self.advance().clone();
+            return Ok(self.push(Expr::Variable { name }));
         }
 
         if self.is_literal() {
-            self.advance();
-            return Box::new(Expr::Lit(*self.previous().clone()))
+            let literal = self.advance().clone();
+            return Ok(self.push(Expr::Lit(literal)));
+        }
+
+        if self.match_tokens(&[TokenType::LeftParen]) {
+            let expression = self.expression()?;
+            self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
+            return Ok(self.push(Expr::Grouping { expression }));
+        }
+
+        if self.match_tokens(&[TokenType::LeftBracket]) {
+            let bracket = self.previous().clone();
+            let mut elements = Vec::new();
+            if !self.check(&TokenType::RightBracket) {
+                loop {
+                    elements.push(self.expression()?);
+                    if !self.match_tokens(&[TokenType::Comma]) {
+                        break;
+                    }
+                }
+            }
+            self.consume(TokenType::RightBracket, "Expect ']' after list elements.")?;
+            return Ok(self.push(Expr::List { bracket, elements }));
+        }
+
+        if self.match_tokens(&[TokenType::LeftBrace]) {
+            let brace = self.previous().clone();
+            let mut entries = Vec::new();
+            if !self.check(&TokenType::RightBrace) {
+                loop {
+                    let key = self.expression()?;
+                    self.consume(TokenType::Colon, "Expect ':' after map key.")?;
+                    entries.push((key, self.expression()?));
+                    if !self.match_tokens(&[TokenType::Comma]) {
+                        break;
+                    }
+                }
+            }
+            self.consume(TokenType::RightBrace, "Expect '}' after map entries.")?;
+            return Ok(self.push(Expr::Map { brace, entries }));
         }
+
+        Err(self.error(self.peek(), "Expect expression."))
+    }
+
+    fn push(&mut self, expr: Expr) -> ExprId {
+        self.exprs.push(expr)
     }
 
     fn is_literal(&self) -> bool {
-        match self.peek().kind {
-            TokenType::Literal(_) => true,
-            _ => false
+        matches!(
+            self.peek().kind,
+            TokenType::Literal(LiteralKind::Str(_)) | TokenType::Literal(LiteralKind::Number(_))
+        )
+    }
+
+    fn is_identifier(&self) -> bool {
+        matches!(
+            self.peek().kind,
+            TokenType::Literal(LiteralKind::Identifier(_))
+        )
+    }
+
+    fn consume(&mut self, t: TokenType, message: &str) -> ParseResult<&Token> {
+        if self.check(&t) {
+            return Ok(self.advance());
+        }
+
+        Err(self.error(self.peek(), message))
+    }
+
+    fn consume_identifier(&mut self, message: &str) -> ParseResult<Token> {
+        if self.is_identifier() {
+            return Ok(self.advance().clone());
+        }
+
+        Err(self.error(self.peek(), message))
+    }
+
+    fn error(&self, token: &Token, message: &str) -> SyntaxError {
+        if token.kind == TokenType::EOF {
+            SyntaxError::new(token.line, " at end", message)
+        } else {
+            SyntaxError::new(token.line, &format!(" at '{}'", token), message)
+        }
+    }
+
+    // Discard tokens until we're probably at the start of the next statement
+    fn synchronize(&mut self) {
+        self.advance();
+
+        while !self.at_end() {
+            if self.previous().kind == TokenType::Semicolon {
+                return;
+            }
+
+            match self.peek().kind {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return => return,
+                _ => {
+                    self.advance();
+                }
+            }
         }
     }
 
+    // Runs `parse` one level deeper
+    fn nested<T, F>(&mut self, parse: F) -> ParseResult<T>
+    where
+        F: FnOnce(&mut Self) -> ParseResult<T>,
+    {
+        let (depth, tree_depth) = (self.depth, self.tree_depth);
+        let result = self.deepen().and_then(|_| parse(self));
+        self.depth = depth;
+        self.tree_depth = tree_depth;
+        result
+    }
+
+    // One more level of nesting, until the caller puts `depth` and
+    // `tree_depth` back
+    fn deepen(&mut self) -> ParseResult<()> {
+        self.depth += 1;
+        self.tree_depth += 1;
+        if self.depth > MAX_NESTING || self.tree_depth > MAX_TREE_DEPTH {
+            return Err(self.error(self.peek(), "Too much nesting."));
+        }
+        Ok(())
+    }
+
+    // One more link in a chain, until the caller puts `tree_depth` back
+    fn lengthen(&mut self) -> ParseResult<()> {
+        self.tree_depth += 1;
+        if self.tree_depth > MAX_TREE_DEPTH {
+            return Err(self.error(self.peek(), "Expression is too long."));
+        }
+        Ok(())
+    }
+
     fn match_tokens(&mut self, tkns: &[TokenType]) -> bool {
         for tkn in tkns {
             if self.check(tkn) {
@@ -156,6 +1005,13 @@ impl Parser {
         &self.peek().kind == t
     }
 
+    // Like `check`, for the token `n` past the current one
+    fn check_ahead(&self, n: usize, t: &TokenType) -> bool {
+        self.tokens
+            .get(self.current + n)
+            .is_some_and(|token| &token.kind == t)
+    }
+
     fn at_end(&self) -> bool {
         self.peek().kind == TokenType::EOF
     }
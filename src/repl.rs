@@ -0,0 +1,247 @@
+// Interactive prompt
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use luxor::parser::Parser;
+use luxor::scanner::{Scanner, KEYWORDS};
+use luxor::token::{Token, TokenType};
+use luxor::Interpreter;
+
+use crate::line_editor::LineEditor;
+
+const PROMPT: &str = "> ";
+const CONTINUATION_PROMPT: &str = "... ";
+
+const HELP: &str = "\
+:help          Show this message
+:load <file>   Run a file in the current session
+:reset         Forget everything defined so far
+:tokens        Toggle printing the tokens of each entry
+:ast           Toggle printing the syntax tree of each entry
+:env           List globals and their types
+:time          Show how long the last entry took to run
+:quit          Leave the REPL (Ctrl-D works too)";
+
+pub fn run(interpreter: &mut Interpreter) -> Result<(), io::Error> {
+    let mut repl = Repl {
+        interpreter,
+        show_tokens: false,
+        show_ast: false,
+        last_elapsed: None,
+    };
+    let mut editor = LineEditor::new();
+
+    loop {
+        let src = match read_entry(&mut editor, &repl.completions()) {
+            Ok(Some(src)) => src,
+            Ok(None) => break,
+            // Ctrl-C throws away the entry being typed
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        let command = src.trim();
+        if command.starts_with(':') {
+            if !repl.command(command) {
+                break;
+            }
+        } else {
+            repl.eval(&src);
+        }
+    }
+
+    Ok(())
+}
+
+struct Repl<'a> {
+    interpreter: &'a mut Interpreter,
+    show_tokens: bool,
+    show_ast: bool,
+    last_elapsed: Option<Duration>,
+}
+
+impl Repl<'_> {
+    // Words offered by tab completion
+    fn completions(&self) -> Vec<String> {
+        let mut words: Vec<String> = KEYWORDS.iter().map(|k| k.to_string()).collect();
+        words.extend(self.interpreter.globals().into_iter().map(|(name, _)| name));
+        words
+    }
+
+    fn eval(&mut self, src: &str) {
+        if self.show_tokens || self.show_ast {
+            let tokens = Scanner::new(src).scan_tokens().to_vec();
+            if self.show_tokens {
+                for t in &tokens {
+                    println!("{:?}", t);
+                }
+            }
+            if self.show_ast {
+                // Errors are reported when the entry runs
+                if let Ok((program, _)) = Parser::new(tokens).parse_repl() {
+                    for stmt in &program.statements {
+                        println!("{}", program.exprs.sexp(stmt));
+                    }
+                }
+            }
+        }
+
+        let start = Instant::now();
+        let result = self.interpreter.eval_repl(src);
+        self.last_elapsed = Some(start.elapsed());
+
+        match result {
+            Ok(Some(value)) => println!("{}", value),
+            Ok(None) => (),
+            // Keep the session going, errors only end scripts
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    // Returns false when it's time to quit
+    fn command(&mut self, line: &str) -> bool {
+        let mut parts = line.splitn(2, char::is_whitespace);
+        let command = parts.next().unwrap_or_default();
+        let arg = parts.next().map(str::trim).unwrap_or_default();
+
+        match command {
+            ":help" => println!("{}", HELP),
+            ":load" if arg.is_empty() => eprintln!("Usage: :load <file>"),
+            ":load" => {
+                let start = Instant::now();
+                let result = self.interpreter.run_file(arg);
+                self.last_elapsed = Some(start.elapsed());
+                if let Err(e) = result {
+                    eprintln!("{}", e);
+                }
+            }
+            ":reset" => {
+                self.interpreter.reset();
+                println!("Session reset.");
+            }
+            ":tokens" => {
+                self.show_tokens = !self.show_tokens;
+                println!("Token dump {}.", on_off(self.show_tokens));
+            }
+            ":ast" => {
+                self.show_ast = !self.show_ast;
+                println!("AST dump {}.", on_off(self.show_ast));
+            }
+            ":env" => {
+                for (name, value) in self.interpreter.globals() {
+                    println!("{}: {}", name, value.type_name());
+                }
+            }
+            ":time" => match self.last_elapsed {
+                Some(elapsed) => println!("{:?}", elapsed),
+                None => println!("Nothing has run yet."),
+            },
+            ":quit" | ":q" => return false,
+            _ => eprintln!("Unknown command '{}', try :help.", command),
+        }
+
+        true
+    }
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    if enabled {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+// Read lines until they form a complete entry, `None` at EOF. A blank
+// continuation line submits the entry as is.
+fn read_entry(editor: &mut LineEditor, words: &[String]) -> Result<Option<String>, io::Error> {
+    let mut src = String::new();
+    let mut prompt = PROMPT;
+
+    loop {
+        let line = match editor.read_line(prompt, words)? {
+            Some(line) => line + "\n",
+            // EOF, run whatever was typed so far
+            None if src.trim().is_empty() => return Ok(None),
+            None => return Ok(Some(src)),
+        };
+
+        if !src.is_empty() && line.trim().is_empty() {
+            return Ok(Some(src));
+        }
+
+        src.push_str(&line);
+        if src.trim_start().starts_with(':') || is_complete(&src) {
+            return Ok(Some(src));
+        }
+        prompt = CONTINUATION_PROMPT;
+    }
+}
+
+// Whether `src` could be run as is, or is obviously waiting for more: an
+// open bracket, string or block comment, or a trailing operator
+fn is_complete(src: &str) -> bool {
+    let mut sc = Scanner::new(src);
+    let tokens = sc.scan_tokens();
+
+    let mut depth = 0;
+    for t in tokens {
+        match &t.kind {
+            TokenType::LeftParen | TokenType::LeftBrace | TokenType::LeftBracket => depth += 1,
+            TokenType::RightParen | TokenType::RightBrace | TokenType::RightBracket => depth -= 1,
+            TokenType::Error(msg) if msg.starts_with("Unterminated") => return false,
+            _ => (),
+        }
+    }
+    if depth > 0 {
+        return false;
+    }
+
+    let last = tokens.iter().rev().find(|t| t.kind != TokenType::EOF);
+    !last.is_some_and(expects_operand)
+}
+
+fn expects_operand(t: &Token) -> bool {
+    matches!(
+        t.kind,
+        TokenType::Minus
+            | TokenType::Plus
+            | TokenType::Slash
+            | TokenType::Star
+            | TokenType::Bang
+            | TokenType::BangEqual
+            | TokenType::Equal
+            | TokenType::EqualEqual
+            | TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual
+            | TokenType::And
+            | TokenType::Or
+            | TokenType::Comma
+            | TokenType::Dot
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_complete() {
+        assert!(is_complete("print 1;\n"));
+        assert!(is_complete("}\n"));
+        assert!(is_complete(""));
+
+        assert!(!is_complete("fun add(a, b) {\n"));
+        assert!(!is_complete("print add(1,\n"));
+        assert!(!is_complete("var s = \"multi\n"));
+        assert!(!is_complete("/* still\n going"));
+        assert!(!is_complete("var x = 1 +\n"));
+        assert!(!is_complete("if (a and\n"));
+        assert!(!is_complete("var xs = [\n"));
+        assert!(!is_complete("var xs = [\n  1,\n  [2, 3],\n"));
+        assert!(is_complete("var xs = [\n  1,\n  [2, 3],\n];\n"));
+    }
+}
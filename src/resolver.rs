@@ -0,0 +1,263 @@
+// Static scoping: works out before a program runs which declaration each
+// use of a name refers to, so closures see the variables that were in scope
+// where they were written rather than whatever has that name when they're
+// called.
+//
+// Scopes here line up one for one with the environments the interpreter
+// makes at run time, so the answer is a number of environments to walk out.
+// Names found in no enclosing scope are globals, which are looked up by name
+// once the whole program has been seen.
+
+use std::collections::HashMap;
+
+use crate::ast::{Expr, ExprArena, ExprId, Function, Stmt};
+use crate::error::SyntaxError;
+use crate::token::Token;
+use crate::visit::{walk_expr, walk_stmt, walk_stmts, Visitor};
+
+// For each variable, assignment, `this` and `super` that refers to a local,
+// how many scopes out from the use it's declared. Keyed by the id of the
+// use, so it goes with the `ExprArena` the program was parsed into.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Locals {
+    depths: HashMap<ExprId, usize>,
+}
+
+impl Locals {
+    // `None` for a global
+    pub fn depth(&self, id: ExprId) -> Option<usize> {
+        self.depths.get(&id).copied()
+    }
+}
+
+// Also reports the errors that only show up once scopes are known
+pub fn resolve(exprs: &ExprArena, statements: &[Stmt]) -> (Locals, Vec<SyntaxError>) {
+    let mut resolver = Resolver {
+        scopes: Vec::new(),
+        locals: Locals::default(),
+        errors: Vec::new(),
+    };
+    walk_stmts(&mut resolver, exprs, statements);
+    (resolver.locals, resolver.errors)
+}
+
+struct Resolver {
+    // Enclosing local scopes, innermost last. Each name maps to whether its
+    // declaration is finished, i.e. past any initializer.
+    scopes: Vec<HashMap<String, bool>>,
+    locals: Locals,
+    errors: Vec<SyntaxError>,
+}
+
+impl Resolver {
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token) {
+        let scope = match self.scopes.last_mut() {
+            Some(scope) => scope,
+            // Globals can be redeclared
+            None => return,
+        };
+        if scope.insert(name.name().to_string(), false).is_some() {
+            self.error(name, "Already a variable with this name in this scope.");
+        }
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.name().to_string(), true);
+        }
+    }
+
+    // A scope holding just `this` or `super`
+    fn begin_keyword_scope(&mut self, keyword: &str) {
+        let mut scope = HashMap::new();
+        scope.insert(keyword.to_string(), true);
+        self.scopes.push(scope);
+    }
+
+    fn resolve_local(&mut self, id: ExprId, name: &str) {
+        let depth = self
+            .scopes
+            .iter()
+            .rev()
+            .position(|scope| scope.contains_key(name));
+        if let Some(depth) = depth {
+            self.locals.depths.insert(id, depth);
+        }
+    }
+
+    // Parameters and the body share a scope, as they share an environment
+    // when the function is called
+    fn function(&mut self, exprs: &ExprArena, function: &Function) {
+        self.begin_scope();
+        for param in &function.params {
+            self.declare(param);
+            self.define(param);
+        }
+        walk_stmts(self, exprs, &function.body);
+        self.end_scope();
+    }
+
+    fn error(&mut self, token: &Token, message: &str) {
+        let location = format!(" at '{}'", token);
+        self.errors
+            .push(SyntaxError::new(token.line, &location, message));
+    }
+}
+
+impl Visitor for Resolver {
+    fn visit_stmt(&mut self, exprs: &ExprArena, stmt: &Stmt) {
+        match stmt {
+            Stmt::Block { statements, .. } => {
+                self.begin_scope();
+                walk_stmts(self, exprs, statements);
+                self.end_scope();
+            }
+            // Methods close over a scope holding `super`, if there's a
+            // superclass, and are bound into one holding `this`
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                self.declare(name);
+                self.define(name);
+                if let Some(superclass) = superclass {
+                    self.visit_expr(exprs, *superclass);
+                    self.begin_keyword_scope("super");
+                }
+                self.begin_keyword_scope("this");
+                for method in methods {
+                    self.function(exprs, method);
+                }
+                self.end_scope();
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+            }
+            // The name is in scope in the body, so functions can recurse
+            Stmt::Function(function) => {
+                self.declare(&function.name);
+                self.define(&function.name);
+                self.function(exprs, function);
+            }
+            // Only a loop with an initializer gets a scope of its own
+            Stmt::For { initializer, .. } => {
+                if initializer.is_some() {
+                    self.begin_scope();
+                    walk_stmt(self, exprs, stmt);
+                    self.end_scope();
+                } else {
+                    walk_stmt(self, exprs, stmt);
+                }
+            }
+            Stmt::ForIn {
+                name,
+                iterable,
+                body,
+                ..
+            } => {
+                self.visit_expr(exprs, *iterable);
+                self.begin_scope();
+                self.declare(name);
+                self.define(name);
+                self.visit_stmt(exprs, body);
+                self.end_scope();
+            }
+            Stmt::Var { name, initializer } => {
+                self.declare(name);
+                if let Some(initializer) = initializer {
+                    self.visit_expr(exprs, *initializer);
+                }
+                self.define(name);
+            }
+            _ => walk_stmt(self, exprs, stmt),
+        }
+    }
+
+    fn visit_expr(&mut self, exprs: &ExprArena, id: ExprId) {
+        match &exprs[id] {
+            Expr::Assign { name, value } => {
+                self.visit_expr(exprs, *value);
+                self.resolve_local(id, name.name());
+            }
+            Expr::Super { .. } => self.resolve_local(id, "super"),
+            Expr::This { .. } => self.resolve_local(id, "this"),
+            Expr::Variable { name } => {
+                let declaring = self.scopes.last().and_then(|scope| scope.get(name.name()));
+                if declaring == Some(&false) {
+                    self.error(name, "Can't read local variable in its own initializer.");
+                }
+                self.resolve_local(id, name.name());
+            }
+            _ => walk_expr(self, exprs, id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    // The depth of every resolved variable named `name`, in id order
+    fn depths(src: &str, name: &str) -> Vec<Option<usize>> {
+        let tokens = Scanner::new(src).scan_tokens().to_vec();
+        let program = Parser::new(tokens).parse().unwrap();
+        program
+            .exprs
+            .iter()
+            .filter(|(_, expr)| match expr {
+                Expr::Variable { name: n } | Expr::Assign { name: n, .. } => n.name() == name,
+                _ => false,
+            })
+            .map(|(id, _)| program.locals.depth(id))
+            .collect()
+    }
+
+    fn errors(src: &str) -> Vec<String> {
+        let tokens = Scanner::new(src).scan_tokens().to_vec();
+        match Parser::new(tokens).parse() {
+            Ok(_) => vec![],
+            Err(errors) => errors.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_depths() {
+        // Globals aren't resolved; the block's `a` is once it's declared
+        assert_eq!(
+            depths("var a; { fun f() { a; } f(); var a; a = 1; }", "a"),
+            vec![None, Some(0)]
+        );
+        assert_eq!(depths("fun f(x) { { return x; } }", "x"), vec![Some(1)]);
+        // A loop with an initializer has a scope, one without doesn't
+        assert_eq!(
+            depths("{ var i; for (i = 0; i;) {} for (;i;) {} }", "i"),
+            vec![Some(1), Some(1), Some(0)]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            errors("{ var a = 1; var a = 2; }\nfun f(b, b) {}\nvar c; var c;"),
+            vec![
+                "[line 1] Error at 'a': Already a variable with this name in this scope.",
+                "[line 2] Error at 'b': Already a variable with this name in this scope.",
+            ]
+        );
+        assert_eq!(
+            errors("var a = 1;\n{ var a = a; }\nvar b = b;"),
+            vec!["[line 2] Error at 'a': Can't read local variable in its own initializer."]
+        );
+    }
+}
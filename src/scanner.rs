@@ -2,35 +2,93 @@ use std::str::Chars;
 use std::iter::Peekable;
 use crate::token::*;
 
+// Every reserved word, each of which `Scanner::lookup_ident` turns into its
+// own token type
+pub const KEYWORDS: &[&str] = &[
+    "and", "break", "class", "continue", "else", "false", "for", "fun", "if",
+    "in", "nil", "or", "print", "return", "super", "this", "true", "var",
+    "while",
+];
+
+// A comment, which is otherwise skipped like whitespace
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    // Including the `//` or `/* */` markers
+    pub text: String,
+    pub line: u32,
+    pub end_line: u32,
+    // Byte offset of its first character
+    pub offset: usize,
+    // How many tokens came before it
+    pub token_index: usize,
+}
+
 pub struct Scanner<'a> {
     source: Peekable<Chars<'a>>,
-    total_len: u32,
     tokens: Vec<Token>,
+    comments: Vec<Comment>,
     start_pos: u32,
     current_pos: u32,
     line: u32,
+    // `current_pos` at the newline that began the current line
+    line_start_pos: u32,
+    // Byte offsets of `start_pos` and of the next character
+    start_byte: usize,
+    current_byte: usize,
 }
 
 impl<'a> Scanner<'a> {
     pub fn new(source: &'a str) -> Self {
+        Scanner::resume(source, 0, 1, 1)
+    }
+
+    // Starts scanning `source` part way through, at byte `offset`, which
+    // must be where a token or comment starts; `line` and `column` are
+    // where that is. Comments count `token_index` from there.
+    pub fn resume(source: &'a str, offset: usize, line: u32, column: u32) -> Self {
         Scanner {
-            source: source.chars().peekable(),
-            total_len: source.len() as u32,
+            source: source[offset..].chars().peekable(),
             tokens: Vec::new(),
+            comments: Vec::new(),
             start_pos: 0,
-            current_pos: 0,
-            line: 1,
+            current_pos: column.saturating_sub(1),
+            line,
+            line_start_pos: 0,
+            start_byte: offset,
+            current_byte: offset,
         }
     }
 
     pub fn scan_tokens(&mut self) -> &Vec<Token> {
-        while let Some(c) = self.advance() {
+        while self.scan_token().kind != TokenType::EOF {}
+        &self.tokens
+    }
+
+    // Scans up to the end of the next token, passing over whitespace and
+    // comments, and returns it. At the end of the source that's EOF.
+    pub fn scan_token(&mut self) -> &Token {
+        let scanned = self.tokens.len();
+        while self.tokens.len() == scanned {
+            let c = match self.advance() {
+                Some(c) => c,
+                None => {
+                    // The failed `advance` already stepped past the end
+                    self.start_pos = self.current_pos;
+                    self.start_byte = self.current_byte;
+                    self.add_token(TokenType::EOF);
+                    break;
+                }
+            };
             self.start_pos = self.current_pos;
+            self.start_byte = self.current_byte - c.len_utf8();
             match c {
                 '(' => self.add_token(TokenType::LeftParen),
                 ')' => self.add_token(TokenType::RightParen),
                 '{' => self.add_token(TokenType::LeftBrace),
                 '}' => self.add_token(TokenType::RightBrace),
+                '[' => self.add_token(TokenType::LeftBracket),
+                ']' => self.add_token(TokenType::RightBracket),
+                ':' => self.add_token(TokenType::Colon),
                 ',' => self.add_token(TokenType::Comma),
                 '.' => self.add_token(TokenType::Dot),
                 '-' => self.add_token(TokenType::Minus),
@@ -66,49 +124,60 @@ impl<'a> Scanner<'a> {
                     }
                 }
                 '/' => {
+                    let line = self.line;
                     if self.match_char('/') {
                         // we found two forward slashes, consume until end of line
+                        let mut text = String::from("//");
                         while let Some(c) = self.peek() {
                             if *c == '\n' {
                                 break;
                             }
 
+                            text.push(*c);
                             self.advance();
                         }
+                        self.add_comment(text.trim_end().to_string(), line);
                     } else if self.match_char('*') {
                         // C-style /* ... */ block comment
+                        let mut text = String::from("/*");
+                        let mut closed = false;
                         while let Some(c) = self.peek() {
                             match c {
                                 '\n' => {
-                                    self.line += 1;
+                                    text.push('\n');
                                     self.advance();
+                                    self.new_line();
                                 }
                                 '*' => {
-                                    if let Some(next) = self.peek_next() {
-                                        match next {
-                                            '/' => {
-                                                // consume */ and exit
-                                                self.advance();
-                                                self.advance();
-                                                break;
-                                            }
-                                            _ => {
-                                              self.advance();
-                                            }
-                                        }
+                                    if let Some('/') = self.peek_next() {
+                                        // consume */ and exit
+                                        self.advance();
+                                        self.advance();
+                                        text.push_str("*/");
+                                        closed = true;
+                                        break;
                                     }
+                                    text.push('*');
+                                    self.advance();
                                 }
                                 _ => {
+                                  text.push(*c);
                                   self.advance();
                                 }
                             }
                         }
+
+                        if closed {
+                            self.add_comment(text, line);
+                        } else {
+                            self.add_token(TokenType::Error("Unterminated block comment".to_string()));
+                        }
                     } else {
                         self.add_token(TokenType::Slash);
                     }
                 }
                 ' ' | '\r' | '\t' => (),
-                '\n' => self.line += 1,
+                '\n' => self.new_line(),
                 '"' => self.read_string(),
                 '0'..='9' => self.read_number(c),
                 'A'..='Z' | 'a'..='z' | '_' => self.read_identifier(c),
@@ -116,14 +185,14 @@ impl<'a> Scanner<'a> {
             }
         }
 
-        self.tokens.push(Token::new( TokenType::EOF, self.line));
-
-        &self.tokens
+        &self.tokens[scanned]
     }
 
     fn advance(&mut self) -> Option<char> {
         self.current_pos += 1;
-        self.source.next()
+        let c = self.source.next()?;
+        self.current_byte += c.len_utf8();
+        Some(c)
     }
 
     fn match_char(&mut self, expected: char) -> bool {
@@ -142,7 +211,7 @@ impl<'a> Scanner<'a> {
 
     fn peek_next(&mut self) -> Option<char> {
         let mut iter_dup = self.source.clone();
-        if let Some(_) = iter_dup.next() {
+        if iter_dup.next().is_some() {
             iter_dup.next()
         } else {
             None
@@ -161,11 +230,11 @@ impl<'a> Scanner<'a> {
                 }
                 _ => {
                     s.push(*c);
-                    if *c == '\n' {
-                        self.line += 1;
-                    }
-
+                    let newline = *c == '\n';
                     self.advance();
+                    if newline {
+                        self.new_line();
+                    }
                 }
             }
         }
@@ -177,29 +246,29 @@ impl<'a> Scanner<'a> {
         let mut num = n.to_string();
         while let Some(c) = self.peek() {
             match *c {
-                d if c.is_digit(10) => {
+                d if c.is_ascii_digit() => {
                     num.push(d);
                     self.advance();
                 }
                 '.' => {
                     if let Some(cn) = self.peek_next() {
-                        if cn.is_digit(10) {
+                        if cn.is_ascii_digit() {
                             num.push('.');
                             self.advance();
                         }
-                        break
                     }
+                    break
                 }
                 _ => break
             }
         }
 
         while let Some(c) = self.peek() {
-            if !c.is_digit(10) {
+            if !c.is_ascii_digit() {
                 break;
             };
 
-            num.push(c.clone());
+            num.push(*c);
             self.advance();
         }
 
@@ -223,33 +292,63 @@ impl<'a> Scanner<'a> {
         self.add_token(Self::lookup_ident(s));
     }
 
+    // Called after consuming a '\n'
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start_pos = self.current_pos;
+    }
+
     fn add_token(&mut self, t: TokenType) {
-        self.tokens.push(Token::new(t, self.line));
+        // A string that spans lines starts on an earlier one
+        let column = self.start_pos.saturating_sub(self.line_start_pos);
+        let mut token = Token::new(t, self.line);
+        token.column = column;
+        token.offset = self.start_byte;
+        token.len = self.current_byte - self.start_byte;
+        self.tokens.push(token);
+    }
+
+    fn add_comment(&mut self, text: String, line: u32) {
+        self.comments.push(Comment {
+            text,
+            line,
+            end_line: self.line,
+            offset: self.start_byte,
+            token_index: self.tokens.len(),
+        });
+    }
+
+    // Comments found by `scan_tokens`, in source order
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
     }
 
     fn is_alphanumeric(c: char) -> bool {
         c.is_ascii_alphanumeric() || c == '_'
     }
 
-    fn lookup_ident(s: String) -> TokenType {
+    pub fn lookup_ident(s: String) -> TokenType {
         match s.as_str() {
-            "and"    => TokenType::And,
-            "class"  => TokenType::Class,
-            "else"   => TokenType::Else,
-            "false"  => TokenType::False,
-            "for"    => TokenType::For,
-            "fun"    => TokenType::Fun,
-            "if"     => TokenType::If,
-            "nil"    => TokenType::Nil,
-            "or"     => TokenType::Or,
-            "print"  => TokenType::Print,
-            "return" => TokenType::Return,
-            "super"  => TokenType::Super,
-            "this"   => TokenType::This,
-            "true"   => TokenType::True,
-            "var"    => TokenType::Var,
-            "while"  => TokenType::While,
-            _        => TokenType::Literal(LiteralKind::Identifier(s)),
+            "and"      => TokenType::And,
+            "break"    => TokenType::Break,
+            "class"    => TokenType::Class,
+            "continue" => TokenType::Continue,
+            "else"     => TokenType::Else,
+            "false"    => TokenType::False,
+            "for"      => TokenType::For,
+            "fun"      => TokenType::Fun,
+            "if"       => TokenType::If,
+            "in"       => TokenType::In,
+            "nil"      => TokenType::Nil,
+            "or"       => TokenType::Or,
+            "print"    => TokenType::Print,
+            "return"   => TokenType::Return,
+            "super"    => TokenType::Super,
+            "this"     => TokenType::This,
+            "true"     => TokenType::True,
+            "var"      => TokenType::Var,
+            "while"    => TokenType::While,
+            _          => TokenType::Literal(LiteralKind::Identifier(s)),
         }
     }
 }
@@ -318,6 +417,23 @@ partner""#;
         assert_eq!(tokens, &expected);
     }
 
+    #[test]
+    fn test_brackets() {
+        let mut sc = Scanner::new("xs[1:]");
+        let kinds: Vec<TokenType> = sc.scan_tokens().iter().map(|t| t.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenType::Literal(LiteralKind::Identifier("xs".to_string())),
+                TokenType::LeftBracket,
+                TokenType::Literal(LiteralKind::Number(1.0)),
+                TokenType::Colon,
+                TokenType::RightBracket,
+                TokenType::EOF,
+            ]
+        );
+    }
+
     #[test]
     fn test_double_slash_comment() {
         let s = r#""string here" != 56 // Nothing followed"#;
@@ -332,6 +448,44 @@ partner""#;
         assert_eq!(tokens, &expected);
     }
 
+    #[test]
+    fn test_columns() {
+        let mut sc = Scanner::new("var  ab = \"x\";\n\tab;");
+        let columns: Vec<(u32, u32)> = sc.scan_tokens().iter().map(|t| (t.line, t.column)).collect();
+        assert_eq!(columns, vec![(1, 1), (1, 6), (1, 9), (1, 11), (1, 14), (2, 2), (2, 4), (2, 5)]);
+    }
+
+    #[test]
+    fn test_offsets() {
+        let s = "x = \"é\"; // ü\ny";
+        let mut sc = Scanner::new(s);
+        let texts: Vec<&str> = sc.scan_tokens().iter().map(|t| &s[t.offset..t.offset + t.len]).collect();
+        assert_eq!(texts, ["x", "=", "\"é\"", ";", "y", ""]);
+        assert_eq!(sc.tokens.last().unwrap().offset, s.len());
+        assert_eq!(sc.comments()[0].offset, 10);
+    }
+
+    #[test]
+    fn test_resume() {
+        let s = "a;\n  b /* c */ d";
+        let mut sc = Scanner::resume(s, 5, 2, 3);
+        let tokens: Vec<(u32, u32, usize)> = sc.scan_tokens().iter().map(|t| (t.line, t.column, t.offset)).collect();
+        assert_eq!(tokens, vec![(2, 3, 5), (2, 13, 15), (2, 14, 16)]);
+        assert_eq!(sc.comments()[0].token_index, 1);
+    }
+
+    #[test]
+    fn test_comments_kept() {
+        let s = "a // one  \n/* two\nlines */ b";
+        let mut sc = Scanner::new(s);
+        sc.scan_tokens();
+        let expected = [
+            Comment { text: "// one".to_string(),           line: 1, end_line: 1, offset: 2,  token_index: 1 },
+            Comment { text: "/* two\nlines */".to_string(), line: 2, end_line: 3, offset: 11, token_index: 1 },
+        ];
+        assert_eq!(sc.comments(), expected);
+    }
+
     #[test]
     fn test_reserved_words() {
         let s = r#"if 3 and 5 or "hello" else nil"#;
@@ -351,6 +505,29 @@ partner""#;
         assert_eq!(tokens, &expected);
     }
 
+    #[test]
+    fn test_unterminated_block_comment() {
+        let s = "1 /* never\n closed *";
+        let mut sc = Scanner::new(s);
+        let tokens = sc.scan_tokens();
+        let expected = vec![
+            Token::new(TokenType::Literal(LiteralKind::Number(1.0)), 1),
+            Token::new(TokenType::Error("Unterminated block comment".to_string()), 2),
+            Token::new(TokenType::EOF, 2),
+        ];
+        assert_eq!(tokens, &expected);
+    }
+
+    #[test]
+    fn test_keywords() {
+        for k in KEYWORDS {
+            assert!(!matches!(
+                Scanner::lookup_ident(k.to_string()),
+                TokenType::Literal(LiteralKind::Identifier(_))
+            ));
+        }
+    }
+
     #[test]
     fn test_statement() {
         let s = "var x = 3.5 + 1;";
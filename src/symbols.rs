@@ -0,0 +1,450 @@
+// What every name in a program refers to, for editor tooling: each
+// declaration becomes a `Symbol` that lists the places it's used.
+//
+// Names resolve lexically, like the interpreter does at run time, except
+// that globals are looked up once the whole program has been seen, since a
+// function may use a global declared below it. Properties resolve only where
+// the class is known: `this.name` to a method of the enclosing class and
+// `super.name` to a method of the superclass.
+
+use std::collections::HashMap;
+
+use crate::ast::{Expr, ExprArena, ExprId, Function, Program, Stmt};
+use crate::token::Token;
+use crate::visit::{walk_expr, walk_stmt, walk_stmts, Visitor};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Variable,
+    Parameter,
+    Function,
+    Class,
+    Method,
+}
+
+impl SymbolKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            SymbolKind::Variable => "variable",
+            SymbolKind::Parameter => "parameter",
+            SymbolKind::Function => "function",
+            SymbolKind::Class => "class",
+            SymbolKind::Method => "method",
+        }
+    }
+}
+
+// A line and a column, both counting from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Position {
+    pub line: u32,
+    pub column: u32,
+}
+
+impl Position {
+    pub fn of(token: &Token) -> Position {
+        Position {
+            line: token.line,
+            column: token.column,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    // Where the name is declared
+    pub position: Position,
+    // The function or class it's declared in, if any
+    pub parent: Option<usize>,
+    // Declared in the global scope, rather than in a block or function
+    pub global: bool,
+    // Where the block, function or loop a local is declared in ends, so it
+    // can be used from `position` up to here. `None` for globals.
+    pub scope_end: Option<Position>,
+    // Parameters of functions and methods, the superclass of classes
+    pub params: Vec<String>,
+    pub superclass: Option<String>,
+    pub references: Vec<Position>,
+}
+
+impl Symbol {
+    // The name with what else the declaration says about it, e.g.
+    // "add(a, b)" or "B < A"
+    pub fn detail(&self, symbols: &Symbols) -> String {
+        match self.kind {
+            SymbolKind::Variable | SymbolKind::Parameter => self.name.clone(),
+            SymbolKind::Function => format!("{}({})", self.name, self.params.join(", ")),
+            SymbolKind::Method => {
+                let class = self.parent.map_or("", |i| symbols.symbols[i].name.as_str());
+                format!("{}.{}({})", class, self.name, self.params.join(", "))
+            }
+            SymbolKind::Class => match &self.superclass {
+                Some(superclass) => format!("{} < {}", self.name, superclass),
+                None => self.name.clone(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    // In the order they're declared
+    pub symbols: Vec<Symbol>,
+    // Index of the symbol declared or used at each position
+    at: HashMap<Position, usize>,
+}
+
+impl Symbols {
+    pub fn analyze(program: &Program) -> Symbols {
+        let mut resolver = Resolver {
+            symbols: Symbols::default(),
+            scopes: vec![Vec::new()],
+            scope_end: None,
+            parents: Vec::new(),
+            classes: Vec::new(),
+            unresolved: Vec::new(),
+        };
+        walk_stmts(&mut resolver, &program.exprs, &program.statements);
+        resolver.finish()
+    }
+
+    // The symbol whose name is declared or used at `position`
+    pub fn at(&self, position: Position) -> Option<&Symbol> {
+        self.at.get(&position).map(|&i| &self.symbols[i])
+    }
+
+    pub fn index_at(&self, position: Position) -> Option<usize> {
+        self.at.get(&position).copied()
+    }
+
+    // The variables, functions and classes that can be used at `position`:
+    // every global, and the locals declared before it in scopes around it
+    pub fn visible_at(&self, position: Position) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter().filter(move |s| {
+            let in_scope = s.position <= position && s.scope_end.is_none_or(|end| position <= end);
+            s.kind != SymbolKind::Method && (s.global || in_scope)
+        })
+    }
+
+    // Symbols declared directly inside the one at `parent`, with their
+    // indices
+    pub fn children(&self, parent: usize) -> impl Iterator<Item = (usize, &Symbol)> {
+        self.symbols
+            .iter()
+            .enumerate()
+            .filter(move |(_, s)| s.parent == Some(parent))
+    }
+}
+
+struct Resolver {
+    symbols: Symbols,
+    // Names in scope, innermost last; the first is the global scope
+    scopes: Vec<Vec<(String, usize)>>,
+    // Where the innermost scope ends, `None` for the global scope or a loop
+    // directly in it
+    scope_end: Option<Position>,
+    // Enclosing functions and classes, innermost last
+    parents: Vec<usize>,
+    // Enclosing classes, innermost last
+    classes: Vec<usize>,
+    // Uses of names with no declaration in scope, to look up as globals
+    unresolved: Vec<(String, Position)>,
+}
+
+impl Resolver {
+    fn declare(&mut self, name: &Token, kind: SymbolKind) -> usize {
+        let index = self.add(name, kind);
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push((name.name().to_string(), index));
+        }
+        index
+    }
+
+    fn add(&mut self, name: &Token, kind: SymbolKind) -> usize {
+        let index = self.symbols.symbols.len();
+        let parent = self.parents.last().copied();
+        let global = self.scopes.len() == 1 && kind != SymbolKind::Method;
+        let position = Position::of(name);
+        self.symbols.symbols.push(Symbol {
+            name: name.name().to_string(),
+            kind,
+            position,
+            parent,
+            global,
+            scope_end: if global { None } else { self.scope_end },
+            params: Vec::new(),
+            superclass: None,
+            references: Vec::new(),
+        });
+        self.symbols.at.insert(position, index);
+        index
+    }
+
+    fn reference(&mut self, index: usize, name: &Token) {
+        let position = Position::of(name);
+        self.symbols.symbols[index].references.push(position);
+        self.symbols.at.insert(position, index);
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.iter().rev().find(|(n, _)| n == name))
+            .map(|&(_, index)| index)
+    }
+
+    fn use_name(&mut self, name: &Token) {
+        match self.lookup(name.name()) {
+            Some(index) => self.reference(index, name),
+            None => self
+                .unresolved
+                .push((name.name().to_string(), Position::of(name))),
+        }
+    }
+
+    fn method(&self, class: usize, name: &str) -> Option<usize> {
+        self.symbols
+            .symbols
+            .iter()
+            .position(|s| s.parent == Some(class) && s.kind == SymbolKind::Method && s.name == name)
+    }
+
+    // Runs `f` in a new scope that ends at `end`
+    fn scope(&mut self, end: Option<Position>, f: impl FnOnce(&mut Self)) {
+        let enclosing = std::mem::replace(&mut self.scope_end, end);
+        self.scopes.push(Vec::new());
+        f(self);
+        self.scopes.pop();
+        self.scope_end = enclosing;
+    }
+
+    // Where a loop's scope ends. Only a block body knows where it ends, so
+    // other loops are taken to go on to the end of the enclosing scope.
+    fn loop_end(&self, body: &Stmt) -> Option<Position> {
+        match body {
+            Stmt::Block { brace, .. } => Some(Position::of(brace)),
+            _ => self.scope_end,
+        }
+    }
+
+    fn function(&mut self, exprs: &ExprArena, function: &Function, index: usize) {
+        self.symbols.symbols[index].params = function
+            .params
+            .iter()
+            .map(|p| p.name().to_string())
+            .collect();
+        self.parents.push(index);
+        self.scope(Some(Position::of(&function.brace)), |resolver| {
+            for param in &function.params {
+                resolver.declare(param, SymbolKind::Parameter);
+            }
+            walk_stmts(resolver, exprs, &function.body);
+        });
+        self.parents.pop();
+    }
+
+    fn finish(mut self) -> Symbols {
+        let globals = self.scopes.swap_remove(0);
+        for (name, position) in std::mem::take(&mut self.unresolved) {
+            if let Some(&(_, index)) = globals.iter().find(|(n, _)| *n == name) {
+                self.symbols.symbols[index].references.push(position);
+                self.symbols.at.insert(position, index);
+            }
+        }
+        for symbol in &mut self.symbols.symbols {
+            symbol.references.sort();
+        }
+        self.symbols
+    }
+}
+
+impl Visitor for Resolver {
+    fn visit_stmt(&mut self, exprs: &ExprArena, stmt: &Stmt) {
+        match stmt {
+            Stmt::Block { statements, brace } => {
+                self.scope(Some(Position::of(brace)), |resolver| {
+                    walk_stmts(resolver, exprs, statements)
+                });
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                let superclass = superclass.and_then(|id| exprs.variable_name(id));
+                let superclass_index = superclass.and_then(|s| {
+                    self.use_name(s);
+                    self.lookup(s.name())
+                });
+                let class = self.declare(name, SymbolKind::Class);
+                self.symbols.symbols[class].superclass = superclass.map(|s| s.name().to_string());
+
+                // Methods can call each other whatever order they're in
+                self.parents.push(class);
+                let indices: Vec<usize> = methods
+                    .iter()
+                    .map(|m| self.add(&m.name, SymbolKind::Method))
+                    .collect();
+                self.classes.push(class);
+                if let Some(superclass) = superclass_index {
+                    // Methods find `super` in a scope around them, as they
+                    // do when the program runs
+                    self.scopes.push(vec![("super".to_string(), superclass)]);
+                }
+                for (method, index) in methods.iter().zip(indices) {
+                    self.function(exprs, method, index);
+                }
+                if superclass_index.is_some() {
+                    self.scopes.pop();
+                }
+                self.classes.pop();
+                self.parents.pop();
+            }
+            Stmt::For { body, .. } => {
+                self.scope(self.loop_end(body), |resolver| {
+                    walk_stmt(resolver, exprs, stmt)
+                });
+            }
+            Stmt::ForIn {
+                name,
+                iterable,
+                body,
+                ..
+            } => {
+                self.visit_expr(exprs, *iterable);
+                self.scope(self.loop_end(body), |resolver| {
+                    resolver.declare(name, SymbolKind::Variable);
+                    resolver.visit_stmt(exprs, body);
+                });
+            }
+            Stmt::Function(function) => {
+                let index = self.declare(&function.name, SymbolKind::Function);
+                self.function(exprs, function, index);
+            }
+            Stmt::Var { name, initializer } => {
+                if let Some(initializer) = initializer {
+                    self.visit_expr(exprs, *initializer);
+                }
+                self.declare(name, SymbolKind::Variable);
+            }
+            _ => walk_stmt(self, exprs, stmt),
+        }
+    }
+
+    fn visit_expr(&mut self, exprs: &ExprArena, id: ExprId) {
+        match &exprs[id] {
+            Expr::Variable { name } | Expr::Assign { name, .. } => self.use_name(name),
+            Expr::Get { object, name } | Expr::Set { object, name, .. } => {
+                if let Expr::This { .. } = exprs[*object] {
+                    let method = self
+                        .classes
+                        .last()
+                        .and_then(|&c| self.method(c, name.name()));
+                    if let Some(index) = method {
+                        self.reference(index, name);
+                    }
+                }
+            }
+            Expr::Super { method, .. } => {
+                let found = self
+                    .lookup("super")
+                    .and_then(|class| self.method(class, method.name()));
+                if let Some(index) = found {
+                    self.reference(index, method);
+                }
+            }
+            _ => {}
+        }
+        walk_expr(self, exprs, id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn analyze(src: &str) -> Symbols {
+        let tokens = Scanner::new(src).scan_tokens().to_vec();
+        Symbols::analyze(&Parser::new(tokens).parse().unwrap())
+    }
+
+    fn at(line: u32, column: u32) -> Position {
+        Position { line, column }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let symbols = analyze(concat!(
+            "fun f(a) { return a + g; }\n",
+            "var g = 1;\n",
+            "{ var g = 2; print g; }\n",
+            "print f(g);\n",
+        ));
+        let f = symbols.at(at(1, 5)).unwrap();
+        assert_eq!(f.kind, SymbolKind::Function);
+        assert_eq!(f.references, vec![at(4, 7)]);
+        assert_eq!(f.detail(&symbols), "f(a)");
+
+        let a = symbols.at(at(1, 19)).unwrap();
+        assert_eq!((a.kind, a.position), (SymbolKind::Parameter, at(1, 7)));
+        assert_eq!(a.parent, symbols.index_at(at(1, 5)));
+
+        // The global is found from inside `f`, but not from the block that
+        // shadows it
+        let g = symbols.at(at(2, 5)).unwrap();
+        assert_eq!(g.references, vec![at(1, 23), at(4, 9)]);
+        assert!(g.global);
+        let inner = symbols.at(at(3, 20)).unwrap();
+        assert_eq!(inner.position, at(3, 7));
+        assert!(!inner.global);
+    }
+
+    #[test]
+    fn test_visible_at() {
+        let symbols = analyze(concat!(
+            "fun f(a) {\n",
+            "  { var b; }\n",
+            "  for (var i in a) print i;\n",
+            "}\n",
+            "var g;\n",
+        ));
+        let names = |line, column| -> Vec<&str> {
+            symbols
+                .visible_at(at(line, column))
+                .map(|s| s.name.as_str())
+                .collect()
+        };
+        assert_eq!(names(1, 10), vec!["f", "a", "g"]);
+        assert_eq!(names(2, 10), vec!["f", "a", "b", "g"]);
+        assert_eq!(names(2, 13), vec!["f", "a", "g"]);
+        assert_eq!(names(3, 27), vec!["f", "a", "i", "g"]);
+        assert_eq!(names(5, 1), vec!["f", "g"]);
+    }
+
+    #[test]
+    fn test_methods() {
+        let symbols = analyze(concat!(
+            "class A { init() { this.go(); } go() {} }\n",
+            "class B < A { go() { super.go(); } }\n",
+        ));
+        let go = symbols.at(at(1, 33)).unwrap();
+        assert_eq!(go.kind, SymbolKind::Method);
+        assert_eq!(go.detail(&symbols), "A.go()");
+        assert_eq!(go.references, vec![at(1, 25), at(2, 28)]);
+
+        let b = symbols.at(at(2, 7)).unwrap();
+        assert_eq!(b.detail(&symbols), "B < A");
+        assert_eq!(symbols.at(at(2, 11)).unwrap().name, "A");
+        assert_eq!(
+            symbols
+                .children(symbols.index_at(at(1, 7)).unwrap())
+                .count(),
+            2
+        );
+    }
+}
@@ -1,10 +1,10 @@
 use std::fmt;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     // Single character tokens
-    LeftParen, RightParen, LeftBrace, RightBrace,
-    Comma, Dot, Minus, Plus, Semicolon, Slash, Star,
+    LeftParen, RightParen, LeftBrace, RightBrace, LeftBracket, RightBracket,
+    Colon, Comma, Dot, Minus, Plus, Semicolon, Slash, Star,
 
     // One or two char tokens
     Bang, BangEqual,
@@ -16,7 +16,7 @@ pub enum TokenType {
     Literal(LiteralKind),
 
     // Keywords
-    And, Class, Else, False, Fun, For, If, Nil, Or,
+    And, Break, Class, Continue, Else, False, Fun, For, If, In, Nil, Or,
     Print, Return, Super, This, True, Var, While,
 
     EOF,
@@ -24,28 +24,54 @@ pub enum TokenType {
     Error(String),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum LiteralKind {
     Str(String),
     Number(f64),
     Identifier(String),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct Token {
     pub kind: TokenType,
     pub line: u32,
+    // Where its first character is on the line it starts on, counting
+    // characters from 1; 0 for tokens that weren't scanned from source
+    pub column: u32,
+    // The bytes of source it was scanned from; both 0 for tokens that
+    // weren't
+    pub offset: usize,
+    pub len: usize,
+}
+
+// Positions are left out, so tokens built by hand compare equal to scanned
+// ones
+impl PartialEq for Token {
+    fn eq(&self, other: &Token) -> bool {
+        self.kind == other.kind && self.line == other.line
+    }
 }
 
 impl Token {
     pub fn new(kind: TokenType, line: u32) -> Token {
-        Token { kind, line }
+        Token {
+            kind,
+            line,
+            column: 0,
+            offset: 0,
+            len: 0,
+        }
     }
 
     pub fn is_error(&self) -> bool {
-        match self.kind {
-            TokenType::Error(_) => true,
-            _ => false,
+        matches!(self.kind, TokenType::Error(_))
+    }
+
+    // Name of an identifier token; empty for every other kind
+    pub fn name(&self) -> &str {
+        match &self.kind {
+            TokenType::Literal(LiteralKind::Identifier(s)) => s,
+            _ => "",
         }
     }
 }
@@ -55,8 +81,11 @@ impl fmt::Display for Token {
         match &self.kind {
             TokenType::LeftParen    => f.write_str("("),
             TokenType::RightParen   => f.write_str(")"),
-            TokenType::LeftBrace    => f.write_str("{{"),
-            TokenType::RightBrace   => f.write_str("}}"),
+            TokenType::LeftBrace    => f.write_str("{"),
+            TokenType::RightBrace   => f.write_str("}"),
+            TokenType::LeftBracket  => f.write_str("["),
+            TokenType::RightBracket => f.write_str("]"),
+            TokenType::Colon        => f.write_str(":"),
             TokenType::Comma        => f.write_str(","),
             TokenType::Dot          => f.write_str("."),
             TokenType::Minus        => f.write_str("-"),
@@ -73,12 +102,15 @@ impl fmt::Display for Token {
             TokenType::Less         => f.write_str("<"),
             TokenType::LessEqual    => f.write_str("<="),
             TokenType::And          => f.write_str("and"),
+            TokenType::Break        => f.write_str("break"),
             TokenType::Class        => f.write_str("class"),
+            TokenType::Continue     => f.write_str("continue"),
             TokenType::Else         => f.write_str("else"),
             TokenType::False        => f.write_str("false"),
             TokenType::For          => f.write_str("for"),
             TokenType::Fun          => f.write_str("fun"),
             TokenType::If           => f.write_str("if"),
+            TokenType::In           => f.write_str("in"),
             TokenType::Nil          => f.write_str("nil"),
             TokenType::Or           => f.write_str("or"),
             TokenType::Print        => f.write_str("print"),
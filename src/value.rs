@@ -0,0 +1,328 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::class::{LoxClass, LoxInstance};
+use crate::function::{LoxFunction, NativeFunction};
+use crate::gc;
+use crate::map::LoxMap;
+
+// Functions, classes, instances, lists and maps are reference counted.
+// Cycles among them, like an instance with one of its own bound methods in
+// a field or a list that contains itself, are freed by the collector in
+// `gc`.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+    Function(Rc<LoxFunction>),
+    Native(Rc<NativeFunction>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<LoxMap>>),
+    Range(Range),
+}
+
+impl Value {
+    // Lox follows Ruby: only `nil` and `false` are falsey
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::Str(_) => "string",
+            Value::Function(_) | Value::Native(_) => "function",
+            Value::Class(_) => "class",
+            Value::Instance(_) => "instance",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Range(_) => "range",
+        }
+    }
+
+    pub fn list(values: Vec<Value>) -> Value {
+        Value::List(gc::list(values))
+    }
+
+    pub fn map(map: LoxMap) -> Value {
+        Value::Map(gc::map(map))
+    }
+}
+
+// The numbers from `start` up to but not including `end`, `step` apart, as
+// made by `range()`. They're only worked out as a loop asks for them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub start: f64,
+    pub end: f64,
+    pub step: f64,
+}
+
+impl Range {
+    // Saturates rather than overflowing for ranges too long to ever finish
+    pub fn len(&self) -> usize {
+        ((self.end - self.start) / self.step).ceil().max(0.0) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Worked out from the start each time, so steps that aren't exact in
+    // binary don't add up to an error
+    pub fn get(&self, i: usize) -> Option<f64> {
+        if i < self.len() {
+            Some(self.start + i as f64 * self.step)
+        } else {
+            None
+        }
+    }
+}
+
+// Functions, classes, instances, lists and maps compare by identity, ranges
+// by their bounds
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+            (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b),
+            (Value::Map(a), Value::Map(b)) => Rc::ptr_eq(a, b),
+            (Value::Range(a), Value::Range(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => f.write_str("nil"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Str(s) => f.write_str(s),
+            Value::Function(fun) => write!(f, "<fn {}>", fun.name()),
+            Value::Native(_) => f.write_str("<native fn>"),
+            Value::Class(class) => f.write_str(&class.name),
+            Value::Instance(instance) => write!(f, "{} instance", instance.borrow().class.name),
+            Value::List(_) | Value::Map(_) => write_element(f, self, &mut Vec::new()),
+            Value::Range(range) => {
+                write!(f, "range({}, {}, {})", range.start, range.end, range.step)
+            }
+        }
+    }
+}
+
+// A value inside a collection: strings are quoted, and a collection already
+// being written further out shows as `[...]` or `{...}` rather than
+// recursing forever. `open` holds those collections.
+fn write_element(
+    f: &mut fmt::Formatter<'_>,
+    value: &Value,
+    open: &mut Vec<*const ()>,
+) -> fmt::Result {
+    match value {
+        Value::Str(s) => write!(f, "\"{}\"", s),
+        Value::List(list) => {
+            let ptr = Rc::as_ptr(list) as *const ();
+            if open.contains(&ptr) {
+                return f.write_str("[...]");
+            }
+            open.push(ptr);
+            f.write_str("[")?;
+            for (i, element) in list.borrow().iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write_element(f, element, open)?;
+            }
+            open.pop();
+            f.write_str("]")
+        }
+        Value::Map(map) => {
+            let ptr = Rc::as_ptr(map) as *const ();
+            if open.contains(&ptr) {
+                return f.write_str("{...}");
+            }
+            open.push(ptr);
+            f.write_str("{")?;
+            for (i, (key, value)) in map.borrow().iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write_element(f, &key.to_value(), open)?;
+                f.write_str(": ")?;
+                write_element(f, value, open)?;
+            }
+            open.pop();
+            f.write_str("}")
+        }
+        value => write!(f, "{}", value),
+    }
+}
+
+// Conversion from a Lox value into a Rust type, `None` when the value has
+// the wrong type
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+// Conversion from a Rust type into a Lox value
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Option<Self> {
+        Some(value.clone())
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Str(s) => Some(s.clone()),
+            _ => None,
+        }
+    }
+}
+
+// Every element must convert to `T`
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::List(list) => list.borrow().iter().map(T::from_value).collect(),
+            _ => None,
+        }
+    }
+}
+
+// `nil` maps to `None`, anything else must convert to `T`
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Nil => Some(None),
+            v => T::from_value(v).map(Some),
+        }
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::Number(self)
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::Str(self)
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::Str(self.to_string())
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        Value::list(self.into_iter().map(IntoValue::into_value).collect())
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        match self {
+            Some(v) => v.into_value(),
+            None => Value::Nil,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(f64::from_value(&2.5.into_value()), Some(2.5));
+        assert_eq!(bool::from_value(&true.into_value()), Some(true));
+        assert_eq!(
+            String::from_value(&"lox".into_value()),
+            Some("lox".to_string())
+        );
+        assert_eq!(f64::from_value(&Value::Bool(true)), None);
+    }
+
+    #[test]
+    fn test_option() {
+        assert_eq!(None::<f64>.into_value(), Value::Nil);
+        assert_eq!(Option::<f64>::from_value(&Value::Nil), Some(None));
+        assert_eq!(
+            Option::<f64>::from_value(&Value::Number(1.0)),
+            Some(Some(1.0))
+        );
+        assert_eq!(Option::<f64>::from_value(&Value::Bool(false)), None);
+    }
+
+    #[test]
+    fn test_list() {
+        let list = vec![1.0, 2.0].into_value();
+        assert_eq!(Vec::<f64>::from_value(&list), Some(vec![1.0, 2.0]));
+        assert_eq!(Vec::<String>::from_value(&list), None);
+
+        if let Value::List(values) = &list {
+            values.borrow_mut().push("three".into_value());
+            values.borrow_mut().push(list.clone());
+        }
+        assert_eq!(list.to_string(), "[1, 2, \"three\", [...]]");
+        // Break the cycle so the list is freed
+        if let Value::List(values) = &list {
+            values.borrow_mut().clear();
+        }
+    }
+}
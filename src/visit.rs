@@ -0,0 +1,718 @@
+// Generic traversal of the syntax tree.
+//
+// Implement `Visitor` (or `VisitorMut` to rewrite the tree in place) and
+// override the methods for the nodes you care about. Every method defaults
+// to the matching `walk_*` function, which visits the node's children in
+// source order; call it from an override to keep descending. Expressions
+// are visited by id, with the arena they live in passed alongside.
+
+use crate::ast::{Expr, ExprArena, ExprId, Function, Program, Stmt};
+use std::fmt;
+use std::rc::Rc;
+
+// What a visit produces. `walk_*` stops at the first child that breaks and
+// returns its result, otherwise `output()`.
+pub trait VisitResult {
+    fn output() -> Self;
+
+    fn is_break(&self) -> bool;
+}
+
+impl VisitResult for () {
+    fn output() -> Self {}
+
+    fn is_break(&self) -> bool {
+        false
+    }
+}
+
+// Errors abort the walk; this covers `fmt::Result` too
+impl<E> VisitResult for Result<(), E> {
+    fn output() -> Self {
+        Ok(())
+    }
+
+    fn is_break(&self) -> bool {
+        self.is_err()
+    }
+}
+
+// Searches stop at the first `Some`
+impl<T> VisitResult for Option<T> {
+    fn output() -> Self {
+        None
+    }
+
+    fn is_break(&self) -> bool {
+        self.is_some()
+    }
+}
+
+pub trait Visitor<R: VisitResult = ()> {
+    fn visit_stmt(&mut self, exprs: &ExprArena, stmt: &Stmt) -> R {
+        walk_stmt(self, exprs, stmt)
+    }
+
+    fn visit_expr(&mut self, exprs: &ExprArena, id: ExprId) -> R {
+        walk_expr(self, exprs, id)
+    }
+
+    fn visit_function(&mut self, exprs: &ExprArena, function: &Function) -> R {
+        walk_function(self, exprs, function)
+    }
+}
+
+macro_rules! visit {
+    ($e:expr) => {{
+        let r = $e;
+        if r.is_break() {
+            return r;
+        }
+    }};
+}
+
+pub fn walk_program<R, V>(visitor: &mut V, program: &Program) -> R
+where
+    R: VisitResult,
+    V: Visitor<R> + ?Sized,
+{
+    walk_stmts(visitor, &program.exprs, &program.statements)
+}
+
+pub fn walk_stmts<R, V>(visitor: &mut V, exprs: &ExprArena, stmts: &[Stmt]) -> R
+where
+    R: VisitResult,
+    V: Visitor<R> + ?Sized,
+{
+    for stmt in stmts {
+        visit!(visitor.visit_stmt(exprs, stmt));
+    }
+    R::output()
+}
+
+pub fn walk_stmt<R, V>(visitor: &mut V, exprs: &ExprArena, stmt: &Stmt) -> R
+where
+    R: VisitResult,
+    V: Visitor<R> + ?Sized,
+{
+    match stmt {
+        Stmt::Block { statements, .. } => return walk_stmts(visitor, exprs, statements),
+        Stmt::Break { .. } | Stmt::Continue { .. } => {}
+        Stmt::Class {
+            superclass,
+            methods,
+            ..
+        } => {
+            if let Some(superclass) = superclass {
+                visit!(visitor.visit_expr(exprs, *superclass));
+            }
+            for method in methods {
+                visit!(visitor.visit_function(exprs, method));
+            }
+        }
+        Stmt::Expression { expression } | Stmt::Print { expression } => {
+            visit!(visitor.visit_expr(exprs, *expression))
+        }
+        Stmt::Function(function) => visit!(visitor.visit_function(exprs, function)),
+        Stmt::For {
+            initializer,
+            condition,
+            increment,
+            body,
+            ..
+        } => {
+            if let Some(initializer) = initializer {
+                visit!(visitor.visit_stmt(exprs, initializer));
+            }
+            if let Some(condition) = condition {
+                visit!(visitor.visit_expr(exprs, *condition));
+            }
+            if let Some(increment) = increment {
+                visit!(visitor.visit_expr(exprs, *increment));
+            }
+            visit!(visitor.visit_stmt(exprs, body));
+        }
+        Stmt::ForIn { iterable, body, .. } => {
+            visit!(visitor.visit_expr(exprs, *iterable));
+            visit!(visitor.visit_stmt(exprs, body));
+        }
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            visit!(visitor.visit_expr(exprs, *condition));
+            visit!(visitor.visit_stmt(exprs, then_branch));
+            if let Some(else_branch) = else_branch {
+                visit!(visitor.visit_stmt(exprs, else_branch));
+            }
+        }
+        Stmt::Return { value, .. } => {
+            if let Some(value) = value {
+                visit!(visitor.visit_expr(exprs, *value));
+            }
+        }
+        Stmt::Var { initializer, .. } => {
+            if let Some(initializer) = initializer {
+                visit!(visitor.visit_expr(exprs, *initializer));
+            }
+        }
+        Stmt::While { condition, body } => {
+            visit!(visitor.visit_expr(exprs, *condition));
+            visit!(visitor.visit_stmt(exprs, body));
+        }
+    }
+    R::output()
+}
+
+pub fn walk_expr<R, V>(visitor: &mut V, exprs: &ExprArena, id: ExprId) -> R
+where
+    R: VisitResult,
+    V: Visitor<R> + ?Sized,
+{
+    match &exprs[id] {
+        Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+            visit!(visitor.visit_expr(exprs, *left));
+            visit!(visitor.visit_expr(exprs, *right));
+        }
+        Expr::Assign { value, .. } => visit!(visitor.visit_expr(exprs, *value)),
+        Expr::Call {
+            callee, arguments, ..
+        } => {
+            visit!(visitor.visit_expr(exprs, *callee));
+            for arg in arguments {
+                visit!(visitor.visit_expr(exprs, *arg));
+            }
+        }
+        Expr::Get { object, .. } => visit!(visitor.visit_expr(exprs, *object)),
+        Expr::Grouping { expression } => visit!(visitor.visit_expr(exprs, *expression)),
+        Expr::Index { object, index, .. } => {
+            visit!(visitor.visit_expr(exprs, *object));
+            visit!(visitor.visit_expr(exprs, *index));
+        }
+        Expr::List { elements, .. } => {
+            for element in elements {
+                visit!(visitor.visit_expr(exprs, *element));
+            }
+        }
+        Expr::Map { entries, .. } => {
+            for (key, value) in entries {
+                visit!(visitor.visit_expr(exprs, *key));
+                visit!(visitor.visit_expr(exprs, *value));
+            }
+        }
+        Expr::Set { object, value, .. } => {
+            visit!(visitor.visit_expr(exprs, *object));
+            visit!(visitor.visit_expr(exprs, *value));
+        }
+        Expr::SetIndex {
+            object,
+            index,
+            value,
+            ..
+        } => {
+            visit!(visitor.visit_expr(exprs, *object));
+            visit!(visitor.visit_expr(exprs, *index));
+            visit!(visitor.visit_expr(exprs, *value));
+        }
+        Expr::Slice {
+            object, start, end, ..
+        } => {
+            visit!(visitor.visit_expr(exprs, *object));
+            for bound in start.iter().chain(end) {
+                visit!(visitor.visit_expr(exprs, *bound));
+            }
+        }
+        Expr::Unary { right, .. } => visit!(visitor.visit_expr(exprs, *right)),
+        Expr::Lit(_) | Expr::Super { .. } | Expr::This { .. } | Expr::Variable { .. } => (),
+    }
+    R::output()
+}
+
+pub fn walk_function<R, V>(visitor: &mut V, exprs: &ExprArena, function: &Function) -> R
+where
+    R: VisitResult,
+    V: Visitor<R> + ?Sized,
+{
+    walk_stmts(visitor, exprs, &function.body)
+}
+
+// Like `Visitor`, but with mutable access for passes that rewrite the tree.
+// New expressions can be pushed onto the arena and linked in by id.
+pub trait VisitorMut {
+    fn visit_stmt_mut(&mut self, exprs: &mut ExprArena, stmt: &mut Stmt) {
+        walk_stmt_mut(self, exprs, stmt)
+    }
+
+    fn visit_expr_mut(&mut self, exprs: &mut ExprArena, id: ExprId) {
+        walk_expr_mut(self, exprs, id)
+    }
+
+    fn visit_function_mut(&mut self, exprs: &mut ExprArena, function: &mut Function) {
+        walk_function_mut(self, exprs, function)
+    }
+}
+
+// The arena and function declarations are shared with any interpreter that
+// has run the program; those get copied rather than changed under it
+pub fn walk_program_mut<V: VisitorMut + ?Sized>(visitor: &mut V, program: &mut Program) {
+    let exprs = Rc::make_mut(&mut program.exprs);
+    walk_stmts_mut(visitor, exprs, &mut program.statements)
+}
+
+pub fn walk_stmts_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    exprs: &mut ExprArena,
+    stmts: &mut [Stmt],
+) {
+    for stmt in stmts {
+        visitor.visit_stmt_mut(exprs, stmt);
+    }
+}
+
+pub fn walk_stmt_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    exprs: &mut ExprArena,
+    stmt: &mut Stmt,
+) {
+    match stmt {
+        Stmt::Block { statements, .. } => walk_stmts_mut(visitor, exprs, statements),
+        Stmt::Break { .. } | Stmt::Continue { .. } => {}
+        Stmt::Class { methods, .. } => {
+            for method in methods {
+                visitor.visit_function_mut(exprs, Rc::make_mut(method));
+            }
+        }
+        Stmt::Expression { expression } | Stmt::Print { expression } => {
+            visitor.visit_expr_mut(exprs, *expression)
+        }
+        Stmt::Function(function) => visitor.visit_function_mut(exprs, Rc::make_mut(function)),
+        Stmt::For {
+            initializer,
+            condition,
+            increment,
+            body,
+            ..
+        } => {
+            if let Some(initializer) = initializer {
+                visitor.visit_stmt_mut(exprs, initializer);
+            }
+            if let Some(condition) = condition {
+                visitor.visit_expr_mut(exprs, *condition);
+            }
+            if let Some(increment) = increment {
+                visitor.visit_expr_mut(exprs, *increment);
+            }
+            visitor.visit_stmt_mut(exprs, body);
+        }
+        Stmt::ForIn { iterable, body, .. } => {
+            visitor.visit_expr_mut(exprs, *iterable);
+            visitor.visit_stmt_mut(exprs, body);
+        }
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            visitor.visit_expr_mut(exprs, *condition);
+            visitor.visit_stmt_mut(exprs, then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_stmt_mut(exprs, else_branch);
+            }
+        }
+        Stmt::Return { value, .. } => {
+            if let Some(value) = value {
+                visitor.visit_expr_mut(exprs, *value);
+            }
+        }
+        Stmt::Var { initializer, .. } => {
+            if let Some(initializer) = initializer {
+                visitor.visit_expr_mut(exprs, *initializer);
+            }
+        }
+        Stmt::While { condition, body } => {
+            visitor.visit_expr_mut(exprs, *condition);
+            visitor.visit_stmt_mut(exprs, body);
+        }
+    }
+}
+
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, exprs: &mut ExprArena, id: ExprId) {
+    // Operands are read up front, the visitor may replace this very node
+    match &exprs[id] {
+        Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+            let (left, right) = (*left, *right);
+            visitor.visit_expr_mut(exprs, left);
+            visitor.visit_expr_mut(exprs, right);
+        }
+        Expr::Call {
+            callee, arguments, ..
+        } => {
+            let callee = *callee;
+            let arguments = arguments.clone();
+            visitor.visit_expr_mut(exprs, callee);
+            for arg in arguments {
+                visitor.visit_expr_mut(exprs, arg);
+            }
+        }
+        Expr::Index {
+            object: left,
+            index: right,
+            ..
+        }
+        | Expr::Set {
+            object: left,
+            value: right,
+            ..
+        } => {
+            let (left, right) = (*left, *right);
+            visitor.visit_expr_mut(exprs, left);
+            visitor.visit_expr_mut(exprs, right);
+        }
+        Expr::List { elements, .. } => {
+            for element in elements.clone() {
+                visitor.visit_expr_mut(exprs, element);
+            }
+        }
+        Expr::Map { entries, .. } => {
+            for (key, value) in entries.clone() {
+                visitor.visit_expr_mut(exprs, key);
+                visitor.visit_expr_mut(exprs, value);
+            }
+        }
+        Expr::SetIndex {
+            object,
+            index,
+            value,
+            ..
+        } => {
+            let operands = [*object, *index, *value];
+            for operand in operands {
+                visitor.visit_expr_mut(exprs, operand);
+            }
+        }
+        Expr::Slice {
+            object, start, end, ..
+        } => {
+            let operands = [Some(*object), *start, *end];
+            for operand in operands.iter().flatten() {
+                visitor.visit_expr_mut(exprs, *operand);
+            }
+        }
+        Expr::Assign { value: operand, .. }
+        | Expr::Get {
+            object: operand, ..
+        }
+        | Expr::Grouping {
+            expression: operand,
+        }
+        | Expr::Unary { right: operand, .. } => {
+            let operand = *operand;
+            visitor.visit_expr_mut(exprs, operand);
+        }
+        Expr::Lit(_) | Expr::Super { .. } | Expr::This { .. } | Expr::Variable { .. } => (),
+    }
+}
+
+pub fn walk_function_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    exprs: &mut ExprArena,
+    function: &mut Function,
+) {
+    walk_stmts_mut(visitor, exprs, &mut function.body)
+}
+
+// The S-expression printer behind `ExprArena::sexp`
+pub(crate) struct Printer<'a, 'b> {
+    pub(crate) f: &'a mut fmt::Formatter<'b>,
+}
+
+impl Printer<'_, '_> {
+    // "(name e1 e2 ...)"
+    fn parens(&mut self, exprs: &ExprArena, name: &str, operands: &[ExprId]) -> fmt::Result {
+        write!(self.f, "({}", name)?;
+        for operand in operands {
+            self.f.write_str(" ")?;
+            self.visit_expr(exprs, *operand)?;
+        }
+        self.f.write_str(")")
+    }
+}
+
+impl Visitor<fmt::Result> for Printer<'_, '_> {
+    fn visit_stmt(&mut self, exprs: &ExprArena, stmt: &Stmt) -> fmt::Result {
+        match stmt {
+            Stmt::Block { statements, .. } => {
+                self.f.write_str("(block")?;
+                for stmt in statements {
+                    self.f.write_str(" ")?;
+                    self.visit_stmt(exprs, stmt)?;
+                }
+                self.f.write_str(")")
+            }
+            Stmt::Break { .. } => self.f.write_str("(break)"),
+            Stmt::Continue { .. } => self.f.write_str("(continue)"),
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                write!(self.f, "(class {}", name)?;
+                if let Some(superclass) = superclass {
+                    self.f.write_str(" < ")?;
+                    self.visit_expr(exprs, *superclass)?;
+                }
+                for method in methods {
+                    self.f.write_str(" ")?;
+                    self.visit_function(exprs, method)?;
+                }
+                self.f.write_str(")")
+            }
+            Stmt::Expression { expression } => self.parens(exprs, ";", &[*expression]),
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                // Missing clauses print as `_`
+                self.f.write_str("(for ")?;
+                match initializer {
+                    Some(initializer) => self.visit_stmt(exprs, initializer)?,
+                    None => self.f.write_str("_")?,
+                }
+                for clause in &[condition, increment] {
+                    self.f.write_str(" ")?;
+                    match clause {
+                        Some(id) => self.visit_expr(exprs, *id)?,
+                        None => self.f.write_str("_")?,
+                    }
+                }
+                self.f.write_str(" ")?;
+                self.visit_stmt(exprs, body)?;
+                self.f.write_str(")")
+            }
+            Stmt::ForIn {
+                name,
+                iterable,
+                body,
+                ..
+            } => {
+                write!(self.f, "(for-in {} ", name)?;
+                self.visit_expr(exprs, *iterable)?;
+                self.f.write_str(" ")?;
+                self.visit_stmt(exprs, body)?;
+                self.f.write_str(")")
+            }
+            Stmt::Function(function) => self.visit_function(exprs, function),
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.f.write_str("(if ")?;
+                self.visit_expr(exprs, *condition)?;
+                self.f.write_str(" ")?;
+                self.visit_stmt(exprs, then_branch)?;
+                if let Some(else_branch) = else_branch {
+                    self.f.write_str(" ")?;
+                    self.visit_stmt(exprs, else_branch)?;
+                }
+                self.f.write_str(")")
+            }
+            Stmt::Print { expression } => self.parens(exprs, "print", &[*expression]),
+            Stmt::Return { value, .. } => match value {
+                Some(value) => self.parens(exprs, "return", &[*value]),
+                None => self.f.write_str("(return)"),
+            },
+            Stmt::Var { name, initializer } => match initializer {
+                Some(initializer) => {
+                    self.parens(exprs, &format!("var {} =", name), &[*initializer])
+                }
+                None => write!(self.f, "(var {})", name),
+            },
+            Stmt::While { condition, body } => {
+                self.f.write_str("(while ")?;
+                self.visit_expr(exprs, *condition)?;
+                self.f.write_str(" ")?;
+                self.visit_stmt(exprs, body)?;
+                self.f.write_str(")")
+            }
+        }
+    }
+
+    fn visit_expr(&mut self, exprs: &ExprArena, id: ExprId) -> fmt::Result {
+        match &exprs[id] {
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            }
+            | Expr::Logical {
+                left,
+                operator,
+                right,
+            } => self.parens(exprs, &operator.to_string(), &[*left, *right]),
+            Expr::Assign { name, value } => self.parens(exprs, &format!("= {}", name), &[*value]),
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                let mut operands = vec![*callee];
+                operands.extend(arguments);
+                self.parens(exprs, "call", &operands)
+            }
+            Expr::Get { object, name } => {
+                self.f.write_str("(. ")?;
+                self.visit_expr(exprs, *object)?;
+                write!(self.f, " {})", name)
+            }
+            Expr::Grouping { expression } => self.parens(exprs, "group", &[*expression]),
+            Expr::Index { object, index, .. } => self.parens(exprs, "index", &[*object, *index]),
+            Expr::List { elements, .. } => self.parens(exprs, "list", elements),
+            Expr::Map { entries, .. } => {
+                let operands: Vec<ExprId> = entries.iter().flat_map(|&(k, v)| [k, v]).collect();
+                self.parens(exprs, "map", &operands)
+            }
+            Expr::Lit(t) => write!(self.f, "{}", t),
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.f.write_str("(= (. ")?;
+                self.visit_expr(exprs, *object)?;
+                write!(self.f, " {}) ", name)?;
+                self.visit_expr(exprs, *value)?;
+                self.f.write_str(")")
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.f.write_str("(= ")?;
+                self.parens(exprs, "index", &[*object, *index])?;
+                self.f.write_str(" ")?;
+                self.visit_expr(exprs, *value)?;
+                self.f.write_str(")")
+            }
+            // A missing bound shows as nil, which means the same
+            Expr::Slice {
+                object, start, end, ..
+            } => {
+                self.f.write_str("(slice ")?;
+                self.visit_expr(exprs, *object)?;
+                for bound in [start, end] {
+                    match bound {
+                        Some(bound) => {
+                            self.f.write_str(" ")?;
+                            self.visit_expr(exprs, *bound)?;
+                        }
+                        None => self.f.write_str(" nil")?,
+                    }
+                }
+                self.f.write_str(")")
+            }
+            Expr::Super { method, .. } => write!(self.f, "(super {})", method),
+            Expr::This { .. } => self.f.write_str("this"),
+            Expr::Unary { operator, right } => self.parens(exprs, &operator.to_string(), &[*right]),
+            Expr::Variable { name } => write!(self.f, "{}", name),
+        }
+    }
+
+    fn visit_function(&mut self, exprs: &ExprArena, function: &Function) -> fmt::Result {
+        write!(self.f, "(fun {} (", function.name)?;
+        for (i, param) in function.params.iter().enumerate() {
+            if i > 0 {
+                self.f.write_str(" ")?;
+            }
+            write!(self.f, "{}", param)?;
+        }
+        self.f.write_str(")")?;
+        for stmt in &function.body {
+            self.f.write_str(" ")?;
+            self.visit_stmt(exprs, stmt)?;
+        }
+        self.f.write_str(")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use crate::token::{LiteralKind, Token, TokenType};
+
+    fn parse(src: &str) -> Program {
+        let tokens = Scanner::new(src).scan_tokens().to_vec();
+        Parser::new(tokens).parse().unwrap()
+    }
+
+    // Collects variable names, in the order they're read
+    struct Reads(Vec<String>);
+
+    impl Visitor for Reads {
+        fn visit_expr(&mut self, exprs: &ExprArena, id: ExprId) {
+            if let Expr::Variable { name } = &exprs[id] {
+                self.0.push(name.name().to_string());
+            }
+            walk_expr(self, exprs, id)
+        }
+    }
+
+    #[test]
+    fn test_visitor() {
+        let program = parse("var a = b + c; fun f(x) { if (x) return d(e); } print a.g;");
+        let mut reads = Reads(vec![]);
+        walk_program(&mut reads, &program);
+        assert_eq!(reads.0, ["b", "c", "x", "d", "e", "a"]);
+    }
+
+    #[test]
+    fn test_visitor_stops() {
+        struct FindCall;
+
+        impl Visitor<Option<u32>> for FindCall {
+            fn visit_expr(&mut self, exprs: &ExprArena, id: ExprId) -> Option<u32> {
+                match &exprs[id] {
+                    Expr::Call { paren, .. } => Some(paren.line),
+                    _ => walk_expr(self, exprs, id),
+                }
+            }
+        }
+
+        let program = parse("print 1;\nwhile (true) { f(); }\ng();");
+        assert_eq!(walk_program(&mut FindCall, &program), Some(2));
+    }
+
+    #[test]
+    fn test_visitor_mut() {
+        // Negate every number
+        struct Negate;
+
+        impl VisitorMut for Negate {
+            fn visit_expr_mut(&mut self, exprs: &mut ExprArena, id: ExprId) {
+                if let Expr::Lit(t) = &exprs[id] {
+                    if let TokenType::Literal(LiteralKind::Number(_)) = t.kind {
+                        let operator = Token::new(TokenType::Minus, t.line);
+                        let number = exprs[id].clone();
+                        let right = exprs.push(number);
+                        exprs[id] = Expr::Unary { operator, right };
+                        return;
+                    }
+                }
+                walk_expr_mut(self, exprs, id)
+            }
+        }
+
+        let mut program = parse("fun f() { return 1 + 2; }");
+        let shared = Rc::clone(&program.exprs);
+        walk_program_mut(&mut Negate, &mut program);
+        assert_eq!(program.to_string(), "(fun f () (return (+ (- 1) (- 2))))");
+        // The arena was shared, so the original is left alone
+        assert_eq!(shared.len(), 3);
+    }
+}
@@ -0,0 +1,247 @@
+// Runs every `tests/**/*.lox` script and checks it against the annotations
+// in its comments, following the Crafting Interpreters test suite:
+//
+//   print 1; // expect: 1
+//   -nil;    // expect runtime error: Operand must be a number.
+//   var = 1; // Error at '=': Expect variable name.
+//   // [line 7] Error at end: Expect '}' after block.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use luxor::format::{format, FormatError};
+use luxor::loxc;
+use luxor::parser::Parser;
+use luxor::scanner::Scanner;
+use luxor::{Capabilities, Error, GcOptions, Interpreter, OutputBuffer, Value};
+
+#[derive(Debug, Default, PartialEq)]
+struct Outcome {
+    output: Vec<String>,
+    // Syntax errors as reported, or one runtime error as
+    // "[line N] message"
+    errors: Vec<String>,
+}
+
+fn expected(src: &str) -> Outcome {
+    let mut outcome = Outcome::default();
+
+    for (i, line) in src.lines().enumerate() {
+        let line_no = i + 1;
+        let comment = match line.find("// ") {
+            Some(at) => &line[at + 3..],
+            None => continue,
+        };
+
+        if let Some(text) = comment.strip_prefix("expect: ") {
+            outcome.output.push(text.to_string());
+        } else if let Some(message) = comment.strip_prefix("expect runtime error: ") {
+            outcome
+                .errors
+                .push(format!("[line {}] {}", line_no, message));
+        } else if comment.starts_with("Error") {
+            outcome
+                .errors
+                .push(format!("[line {}] {}", line_no, comment));
+        } else if comment.starts_with("[line ") {
+            outcome.errors.push(comment.to_string());
+        }
+    }
+
+    outcome
+}
+
+fn run(src: &str) -> Outcome {
+    run_with(src, GcOptions::default())
+}
+
+fn run_with(src: &str, gc: GcOptions) -> Outcome {
+    run_in(gc, |interpreter| interpreter.eval_str(src))
+}
+
+fn run_in(gc: GcOptions, eval: impl FnOnce(&mut Interpreter) -> Result<Value, Error>) -> Outcome {
+    let buffer = OutputBuffer::new();
+    let mut interpreter = Interpreter::new();
+    interpreter.set_output(Box::new(buffer.clone()));
+    interpreter.set_capabilities(Capabilities::all());
+    interpreter.set_gc_options(gc);
+
+    let errors = match eval(&mut interpreter) {
+        Ok(_) => vec![],
+        Err(Error::Syntax(errors)) => errors.iter().map(|e| e.to_string()).collect(),
+        Err(Error::Runtime(e)) => vec![format!("[line {}] {}", e.line(), e.message())],
+        Err(e) => vec![e.to_string()],
+    };
+
+    Outcome {
+        output: buffer.contents().lines().map(String::from).collect(),
+        errors,
+    }
+}
+
+fn diff(label: &str, expected: &[String], actual: &[String]) -> String {
+    let mut out = String::new();
+    if expected == actual {
+        return out;
+    }
+
+    out.push_str(&format!("  {}:\n", label));
+    for i in 0..expected.len().max(actual.len()) {
+        match (expected.get(i), actual.get(i)) {
+            (Some(e), Some(a)) if e == a => out.push_str(&format!("      {}\n", e)),
+            (e, a) => {
+                if let Some(e) = e {
+                    out.push_str(&format!("    - {}\n", e));
+                }
+                if let Some(a) = a {
+                    out.push_str(&format!("    + {}\n", a));
+                }
+            }
+        }
+    }
+    out
+}
+
+fn collect(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect(&path, files);
+        } else if path.extension().is_some_and(|ext| ext == "lox") {
+            files.push(path);
+        }
+    }
+}
+
+fn lox_files() -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    collect(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("tests"),
+        &mut files,
+    );
+    files.sort();
+    assert!(!files.is_empty(), "no .lox files found");
+    files
+}
+
+#[test]
+fn golden_files() {
+    check_golden_files(GcOptions::default());
+}
+
+// Collecting at every step must not change what any script does
+#[test]
+fn golden_files_under_gc_stress() {
+    check_golden_files(GcOptions {
+        stress: true,
+        ..GcOptions::default()
+    });
+}
+
+fn check_golden_files(gc: GcOptions) {
+    let files = lox_files();
+
+    let mut failures = Vec::new();
+    for path in &files {
+        let src = fs::read_to_string(path).unwrap();
+        let expected = expected(&src);
+        let actual = run_with(&src, gc.clone());
+
+        if expected != actual {
+            failures.push(format!(
+                "{}\n{}{}",
+                path.display(),
+                diff("output", &expected.output, &actual.output),
+                diff("errors", &expected.errors, &actual.errors),
+            ));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} of {} scripts failed (- expected, + actual):\n\n{}",
+        failures.len(),
+        files.len(),
+        failures.join("\n")
+    );
+}
+
+// Formatting every script that parses must be stable and must not change
+// what it prints. Errors are left out, their lines move.
+#[test]
+fn formatted_golden_files() {
+    let files = lox_files();
+
+    let mut failures = Vec::new();
+    for path in &files {
+        let src = fs::read_to_string(path).unwrap();
+        let formatted = match format(&src) {
+            Ok(formatted) => formatted,
+            Err(FormatError::Syntax(_)) => continue,
+            Err(e) => {
+                failures.push(format!("{}\n  {}", path.display(), e));
+                continue;
+            }
+        };
+
+        if format(&formatted).as_ref() != Ok(&formatted) {
+            failures.push(format!("{}\n  not idempotent", path.display()));
+        }
+        let expected = expected(&src).output;
+        let actual = run(&formatted).output;
+        if expected != actual {
+            failures.push(format!(
+                "{}\n{}",
+                path.display(),
+                diff("output", &expected, &actual)
+            ));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} of {} scripts failed after formatting (- expected, + actual):\n\n{}",
+        failures.len(),
+        files.len(),
+        failures.join("\n")
+    );
+}
+
+// Every script that parses must run the same compiled, runtime errors and
+// their lines included
+#[test]
+fn compiled_golden_files() {
+    let files = lox_files();
+
+    let mut failures = Vec::new();
+    for path in &files {
+        let src = fs::read_to_string(path).unwrap();
+        let tokens = Scanner::new(&src).scan_tokens().to_vec();
+        let program = match Parser::new(tokens).parse() {
+            Ok(program) => program,
+            Err(_) => continue,
+        };
+
+        let compiled = loxc::compile(&program);
+        let expected = expected(&src);
+        let actual = run_in(GcOptions::default(), |interpreter| {
+            interpreter.eval_compiled(&compiled)
+        });
+        if expected != actual {
+            failures.push(format!(
+                "{}\n{}{}",
+                path.display(),
+                diff("output", &expected.output, &actual.output),
+                diff("errors", &expected.errors, &actual.errors),
+            ));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} of {} scripts failed when compiled (- expected, + actual):\n\n{}",
+        failures.len(),
+        files.len(),
+        failures.join("\n")
+    );
+}
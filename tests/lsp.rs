@@ -0,0 +1,123 @@
+// Replays each `tests/lsp/*.txt` transcript against `luxor lsp` and checks
+// the server says exactly what the transcript expects. A transcript is a
+// conversation, one JSON message per line:
+//
+//   --> {"jsonrpc":"2.0","id":1,"method":"shutdown"}
+//   <-- {"jsonrpc":"2.0","id":1,"result":null}
+//
+// Blank lines and lines starting with '#' are ignored. Messages are
+// compared as JSON, so member order and spacing don't matter.
+
+use std::fs;
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use luxor::lsp::jsonrpc::{read_message, write_message};
+use luxor::lsp::Json;
+
+struct Transcript {
+    sent: Vec<Json>,
+    expected: Vec<Json>,
+    // What `luxor lsp` should exit with
+    status: i32,
+}
+
+fn transcript(path: &Path) -> Transcript {
+    let src = fs::read_to_string(path).unwrap();
+    let mut transcript = Transcript {
+        sent: Vec::new(),
+        expected: Vec::new(),
+        status: 0,
+    };
+    for (i, line) in src.lines().enumerate() {
+        let parse = |json: &str| {
+            Json::parse(json).unwrap_or_else(|e| panic!("{}:{}: {}", path.display(), i + 1, e))
+        };
+        if let Some(json) = line.strip_prefix("--> ") {
+            transcript.sent.push(parse(json));
+        } else if let Some(json) = line.strip_prefix("<-- ") {
+            transcript.expected.push(parse(json));
+        } else if let Some(status) = line.strip_prefix("# exit status: ") {
+            transcript.status = status.parse().unwrap();
+        } else if !(line.trim().is_empty() || line.starts_with('#')) {
+            panic!("{}:{}: expected '-->' or '<--'", path.display(), i + 1);
+        }
+    }
+    transcript
+}
+
+// The messages the server sends in reply, and its exit status
+fn replay(messages: &[Json]) -> (Vec<Json>, i32) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_luxor"))
+        .arg("lsp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stdin = child.stdin.take().unwrap();
+    for message in messages {
+        write_message(&mut stdin, message).unwrap();
+    }
+    stdin.flush().unwrap();
+    drop(stdin);
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut received = Vec::new();
+    while let Some(body) = read_message(&mut stdout).unwrap() {
+        received.push(Json::parse(&body).unwrap());
+    }
+    let status = child.wait().unwrap().code().unwrap_or(-1);
+    (received, status)
+}
+
+fn transcripts() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lsp");
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+        .collect();
+    files.sort();
+    assert!(!files.is_empty(), "no transcripts found");
+    files
+}
+
+#[test]
+fn lsp_transcripts() {
+    let mut failures = Vec::new();
+    for path in transcripts() {
+        let transcript = transcript(&path);
+        let (received, status) = replay(&transcript.sent);
+
+        let mut problems = Vec::new();
+        for (i, expected) in transcript.expected.iter().enumerate() {
+            match received.get(i) {
+                Some(actual) if actual == expected => {}
+                Some(actual) => problems.push(format!(
+                    "  message {}:\n    expected {}\n    got      {}",
+                    i + 1,
+                    expected,
+                    actual
+                )),
+                None => problems.push(format!("  message {}: missing {}", i + 1, expected)),
+            }
+        }
+        for (i, extra) in received.iter().enumerate().skip(transcript.expected.len()) {
+            problems.push(format!("  message {}: unexpected {}", i + 1, extra));
+        }
+        if status != transcript.status {
+            problems.push(format!(
+                "  exit status: expected {}, got {}",
+                transcript.status, status
+            ));
+        }
+
+        if !problems.is_empty() {
+            failures.push(format!("{}:\n{}", path.display(), problems.join("\n")));
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+}